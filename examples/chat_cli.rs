@@ -1,28 +1,20 @@
-use std::{
-    env,
-    io::{stdin, stdout, Write},
-};
+use std::io::{stdin, stdout, Write};
 
 use dotenvy::dotenv;
 
 use openai::{
-    chat::{ChatCompletion, ChatCompletionMessage, ChatCompletionMessageRole},
-    set_base_url, set_key,
+    chat::{ChatCompletion, ChatCompletionMessage},
+    client::set_default_client,
+    OpenAiClient,
 };
 
 #[tokio::main]
 async fn main() {
-    // Make sure you have a file named `.env` with the `OPENAI_KEY` environment variable defined!
+    // Make sure you have a file named `.env` with the `OPENAI_API_KEY` environment variable defined!
     dotenv().unwrap();
-    set_key(env::var("OPENAI_KEY").unwrap());
-    set_base_url(env::var("OPENAI_BASE_URL").unwrap_or_default());
+    set_default_client(OpenAiClient::from_env().unwrap());
 
-    let mut messages = vec![ChatCompletionMessage {
-        role: ChatCompletionMessageRole::System,
-        content: Some("You are a large language model built into a command line interface as an example of what the `openai` Rust library made by Valentine Briese can do.".to_string()),
-        name: None,
-        function_call: None,
-    }];
+    let mut messages = vec![ChatCompletionMessage::system("You are a large language model built into a command line interface as an example of what the `openai` Rust library made by Valentine Briese can do.")];
 
     loop {
         print!("User: ");
@@ -31,12 +23,7 @@ async fn main() {
         let mut user_message_content = String::new();
 
         stdin().read_line(&mut user_message_content).unwrap();
-        messages.push(ChatCompletionMessage {
-            role: ChatCompletionMessageRole::User,
-            content: Some(user_message_content),
-            name: None,
-            function_call: None,
-        });
+        messages.push(ChatCompletionMessage::user(user_message_content));
 
         let chat_completion = ChatCompletion::builder("gpt-3.5-turbo", messages.clone())
             .create()
@@ -47,7 +34,7 @@ async fn main() {
         println!(
             "{:#?}: {}",
             &returned_message.role,
-            &returned_message.content.clone().unwrap().trim()
+            returned_message.text().unwrap().trim()
         );
 
         messages.push(returned_message);
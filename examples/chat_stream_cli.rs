@@ -1,27 +1,20 @@
 use dotenvy::dotenv;
 use openai::chat::{ChatCompletion, ChatCompletionDelta};
 use openai::{
-    chat::{ChatCompletionMessage, ChatCompletionMessageRole},
-    set_key,
-};
-use std::{
-    env,
-    io::{stdin, stdout, Write},
+    chat::ChatCompletionMessage, client::set_default_client, ApiResponseOrError, OpenAiClient,
 };
+use std::io::{stdin, stdout, Write};
 use tokio::sync::mpsc::Receiver;
 
 #[tokio::main]
 async fn main() {
-    // Make sure you have a file named `.env` with the `OPENAI_KEY` environment variable defined!
+    // Make sure you have a file named `.env` with the `OPENAI_API_KEY` environment variable defined!
     dotenv().unwrap();
-    set_key(env::var("OPENAI_KEY").unwrap());
+    set_default_client(OpenAiClient::from_env().unwrap());
 
-    let mut messages = vec![ChatCompletionMessage {
-        role: ChatCompletionMessageRole::System,
-        content: Some("You're an AI that replies to each message verbosely.".to_string()),
-        name: None,
-        function_call: None,
-    }];
+    let mut messages = vec![ChatCompletionMessage::system(
+        "You're an AI that replies to each message verbosely.",
+    )];
 
     loop {
         print!("User: ");
@@ -30,12 +23,7 @@ async fn main() {
         let mut user_message_content = String::new();
 
         stdin().read_line(&mut user_message_content).unwrap();
-        messages.push(ChatCompletionMessage {
-            role: ChatCompletionMessageRole::User,
-            content: Some(user_message_content),
-            name: None,
-            function_call: None,
-        });
+        messages.push(ChatCompletionMessage::user(user_message_content));
 
         let chat_stream = ChatCompletionDelta::builder("gpt-3.5-turbo", messages.clone())
             .create_stream()
@@ -49,19 +37,25 @@ async fn main() {
     }
 }
 
-async fn listen_for_tokens(mut chat_stream: Receiver<ChatCompletionDelta>) -> ChatCompletion {
+async fn listen_for_tokens(
+    mut chat_stream: Receiver<ApiResponseOrError<ChatCompletionDelta>>,
+) -> ChatCompletion {
     let mut merged: Option<ChatCompletionDelta> = None;
     while let Some(delta) = chat_stream.recv().await {
-        let choice = &delta.choices[0];
+        let delta = delta.unwrap();
+        // The last chunk of a stream that includes usage has no choices.
+        let Some(choice) = delta.choices.first() else {
+            continue;
+        };
         if let Some(role) = &choice.delta.role {
             print!("{:#?}: ", role);
         }
         if let Some(content) = &choice.delta.content {
             print!("{}", content);
         }
-        if let Some(_) = &choice.finish_reason {
+        if choice.finish_reason.is_some() {
             // The message being streamed has been fully received.
-            print!("\n");
+            println!();
         }
         stdout().flush().unwrap();
         // Merge completion into accrued.
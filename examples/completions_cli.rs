@@ -1,12 +1,12 @@
 use dotenvy::dotenv;
-use openai::{completions::Completion, set_key};
-use std::{env, io::stdin};
+use openai::{client::set_default_client, completions::Completion, OpenAiClient};
+use std::io::stdin;
 
 #[tokio::main]
 async fn main() {
-    // Make sure you have a file named `.env` with the `OPENAI_KEY` environment variable defined!
+    // Make sure you have a file named `.env` with the `OPENAI_API_KEY` environment variable defined!
     dotenv().unwrap();
-    set_key(env::var("OPENAI_KEY").unwrap());
+    set_default_client(OpenAiClient::from_env().unwrap());
 
     loop {
         println!("Prompt:");
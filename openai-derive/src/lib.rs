@@ -0,0 +1,486 @@
+//! Derive macros for the [`openai`](https://crates.io/crates/openai) crate.
+//!
+//! `#[derive(JsonSchema)]` implements `openai::schema::JsonSchema`, and
+//! `#[derive(FunctionArguments)]` implements both `JsonSchema` and
+//! `openai::schema::FunctionArguments`. They are re-exported by `openai` with its `derive`
+//! feature, and documented there.
+//!
+//! Doc comments become descriptions, and the `serde` attributes that change the shape of the
+//! JSON are followed: `rename`, `rename_all`, `tag`, `content`, `untagged`, `default`, `skip`,
+//! `skip_deserializing` and `flatten`.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::meta::ParseNestedMeta;
+use syn::{
+    parse_macro_input, parse_quote, Attribute, Data, DeriveInput, Error, Expr, Fields, Ident,
+    LitStr, Result, Token, Variant,
+};
+
+#[proc_macro_derive(JsonSchema, attributes(serde))]
+pub fn derive_json_schema(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    json_schema_impl(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+#[proc_macro_derive(FunctionArguments, attributes(serde, function))]
+pub fn derive_function_arguments(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let output = json_schema_impl(&input).and_then(|json_schema| {
+        let function_arguments = function_arguments_impl(&input)?;
+        Ok(quote!(#json_schema #function_arguments))
+    });
+    output.unwrap_or_else(Error::into_compile_error).into()
+}
+
+fn function_arguments_impl(input: &DeriveInput) -> Result<TokenStream2> {
+    let mut name = None;
+    let mut description = None;
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("function"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                name = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else if meta.path.is_ident("description") {
+                description = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else {
+                return Err(meta.error("expected `name` or `description`"));
+            }
+            Ok(())
+        })?;
+    }
+    let name = name.unwrap_or_else(|| RenameRule::SnakeCase.apply_to_variant(&ident(&input.ident)));
+    let description = match description.or_else(|| doc_comment(&input.attrs)) {
+        Some(description) => quote!(::core::option::Option::Some(#description)),
+        None => quote!(::core::option::Option::None),
+    };
+    let ty = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::openai::schema::FunctionArguments for #ty #ty_generics #where_clause {
+            const NAME: &'static str = #name;
+            const DESCRIPTION: ::core::option::Option<&'static str> = #description;
+        }
+    })
+}
+
+fn json_schema_impl(input: &DeriveInput) -> Result<TokenStream2> {
+    let container = SerdeAttrs::parse(&input.attrs)?;
+    let schema = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(_) => object_schema(&data.fields, &container)?,
+            Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+                let ty = &fields.unnamed[0].ty;
+                quote!(<#ty as ::openai::schema::JsonSchema>::json_schema())
+            }
+            Fields::Unnamed(_) => {
+                return Err(Error::new_spanned(
+                    &input.ident,
+                    "JsonSchema can't be derived for tuple structs with several fields",
+                ))
+            }
+            Fields::Unit => object_schema(&data.fields, &container)?,
+        },
+        Data::Enum(data) => {
+            let variants: Vec<_> = data
+                .variants
+                .iter()
+                .map(|variant| Ok((variant, SerdeAttrs::parse(&variant.attrs)?)))
+                .filter(|variant| !matches!(variant, Ok((_, attrs)) if attrs.skip))
+                .collect::<Result<_>>()?;
+            enum_schema(&variants, &container)?
+        }
+        Data::Union(_) => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "JsonSchema can't be derived for unions",
+            ))
+        }
+    };
+    let ty = &input.ident;
+    let mut generics = input.generics.clone();
+    for param in generics.type_params_mut() {
+        param
+            .bounds
+            .push(parse_quote!(::openai::schema::JsonSchema));
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::openai::schema::JsonSchema for #ty #ty_generics #where_clause {
+            fn json_schema() -> ::openai::schema::__private::Value {
+                ::openai::schema::__private::definition::<Self>(|| #schema)
+            }
+        }
+    })
+}
+
+/// The schema of an object with the named fields, plus the `tag` property if given.
+fn object_schema(fields: &Fields, container: &SerdeAttrs) -> Result<TokenStream2> {
+    object_schema_with_tag(fields, container, None)
+}
+
+fn object_schema_with_tag(
+    fields: &Fields,
+    container: &SerdeAttrs,
+    tag: Option<(&str, &str)>,
+) -> Result<TokenStream2> {
+    let mut properties = Vec::new();
+    if let Some((tag, name)) = tag {
+        properties.push(quote! {
+            object.property(#tag, ::openai::schema::__private::constant(#name), ::core::option::Option::None, true);
+        });
+    }
+    for field in fields {
+        let attrs = SerdeAttrs::parse(&field.attrs)?;
+        if attrs.skip {
+            continue;
+        }
+        let ty = &field.ty;
+        let schema = quote!(<#ty as ::openai::schema::JsonSchema>::json_schema());
+        if attrs.flatten {
+            properties.push(quote!(object.flatten(#schema);));
+            continue;
+        }
+        let Some(ident) = &field.ident else {
+            continue;
+        };
+        let name = attrs
+            .rename
+            .unwrap_or_else(|| container.rename_all.apply_to_field(&self::ident(ident)));
+        let description = match doc_comment(&field.attrs) {
+            Some(description) => quote!(::core::option::Option::Some(#description)),
+            None => quote!(::core::option::Option::None),
+        };
+        let required = match attrs.default || container.default {
+            true => quote!(false),
+            false => quote!(!<#ty as ::openai::schema::JsonSchema>::optional()),
+        };
+        properties.push(quote! {
+            object.property(#name, #schema, #description, #required);
+        });
+    }
+    Ok(quote! {{
+        let mut object = ::openai::schema::__private::ObjectSchema::new();
+        #(#properties)*
+        object.into_schema()
+    }})
+}
+
+fn enum_schema(
+    variants: &[(&Variant, SerdeAttrs)],
+    container: &SerdeAttrs,
+) -> Result<TokenStream2> {
+    let name = |variant: &Variant, attrs: &SerdeAttrs| {
+        attrs.rename.clone().unwrap_or_else(|| {
+            container
+                .rename_all
+                .apply_to_variant(&ident(&variant.ident))
+        })
+    };
+    let all_unit = variants
+        .iter()
+        .all(|(variant, _)| matches!(variant.fields, Fields::Unit));
+    if all_unit && container.tag.is_none() && !container.untagged {
+        let names = variants.iter().map(|(variant, attrs)| name(variant, attrs));
+        return Ok(quote!(::openai::schema::__private::string_enum(&[#(#names),*])));
+    }
+    let mut schemas = Vec::new();
+    for (variant, attrs) in variants {
+        let name = name(variant, attrs);
+        let fields_container = SerdeAttrs {
+            rename_all: attrs.rename_all,
+            ..SerdeAttrs::default()
+        };
+        let payload = match &variant.fields {
+            Fields::Named(_) => Some(object_schema(&variant.fields, &fields_container)?),
+            Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+                let ty = &fields.unnamed[0].ty;
+                Some(quote!(<#ty as ::openai::schema::JsonSchema>::json_schema()))
+            }
+            Fields::Unnamed(_) => {
+                return Err(Error::new_spanned(
+                    &variant.ident,
+                    "JsonSchema can't be derived for tuple variants with several fields",
+                ))
+            }
+            Fields::Unit => None,
+        };
+        let schema = match (&container.tag, &container.content, container.untagged) {
+            (_, _, true) => payload.unwrap_or_else(|| quote!(::openai::schema::__private::null())),
+            (Some(tag), Some(content), _) => {
+                let payload = payload.map(|payload| {
+                    quote!(object.property(#content, #payload, ::core::option::Option::None, true);)
+                });
+                quote! {{
+                    let mut object = ::openai::schema::__private::ObjectSchema::new();
+                    object.property(#tag, ::openai::schema::__private::constant(#name), ::core::option::Option::None, true);
+                    #payload
+                    object.into_schema()
+                }}
+            }
+            (Some(tag), None, _) => match &variant.fields {
+                Fields::Unnamed(_) => return Err(Error::new_spanned(
+                    &variant.ident,
+                    "JsonSchema can't be derived for newtype variants of internally tagged enums",
+                )),
+                fields => object_schema_with_tag(fields, &fields_container, Some((tag, &name)))?,
+            },
+            (None, _, false) => match payload {
+                None => quote!(::openai::schema::__private::constant(#name)),
+                Some(payload) => quote! {{
+                    let mut object = ::openai::schema::__private::ObjectSchema::new();
+                    object.property(#name, #payload, ::core::option::Option::None, true);
+                    object.into_schema()
+                }},
+            },
+        };
+        let description = match doc_comment(&variant.attrs) {
+            Some(description) => quote!(::core::option::Option::Some(#description)),
+            None => quote!(::core::option::Option::None),
+        };
+        schemas.push(quote!(::openai::schema::__private::describe(#schema, #description)));
+    }
+    Ok(quote!(::openai::schema::__private::any_of(
+        ::std::vec![#(#schemas),*]
+    )))
+}
+
+/// The `serde` attributes of a container, field or variant that change the shape of the JSON.
+#[derive(Default)]
+struct SerdeAttrs {
+    rename: Option<String>,
+    rename_all: RenameRule,
+    tag: Option<String>,
+    content: Option<String>,
+    untagged: bool,
+    default: bool,
+    skip: bool,
+    flatten: bool,
+}
+
+impl SerdeAttrs {
+    fn parse(attrs: &[Attribute]) -> Result<Self> {
+        let mut serde = SerdeAttrs::default();
+        for attr in attrs.iter().filter(|attr| attr.path().is_ident("serde")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    serde.rename = deserialize_name(&meta)?.or(serde.rename.take());
+                } else if meta.path.is_ident("rename_all") {
+                    if let Some(rule) = deserialize_name(&meta)? {
+                        serde.rename_all = RenameRule::parse(&rule)
+                            .ok_or_else(|| meta.error(format!("unknown rename rule `{rule}`")))?;
+                    }
+                } else if meta.path.is_ident("tag") {
+                    serde.tag = Some(meta.value()?.parse::<LitStr>()?.value());
+                } else if meta.path.is_ident("content") {
+                    serde.content = Some(meta.value()?.parse::<LitStr>()?.value());
+                } else if meta.path.is_ident("untagged") {
+                    serde.untagged = true;
+                } else if meta.path.is_ident("default") {
+                    serde.default = true;
+                    skip_value(&meta)?;
+                } else if meta.path.is_ident("skip") || meta.path.is_ident("skip_deserializing") {
+                    serde.skip = true;
+                } else if meta.path.is_ident("flatten") {
+                    serde.flatten = true;
+                } else {
+                    skip_value(&meta)?;
+                }
+                Ok(())
+            })?;
+        }
+        Ok(serde)
+    }
+}
+
+/// Reads `name = "..."`, or the `deserialize` name of `name(serialize = "...", deserialize = "...")`.
+fn deserialize_name(meta: &ParseNestedMeta) -> Result<Option<String>> {
+    if meta.input.peek(Token![=]) {
+        return Ok(Some(meta.value()?.parse::<LitStr>()?.value()));
+    }
+    let mut name = None;
+    meta.parse_nested_meta(|meta| {
+        let value = meta.value()?.parse::<LitStr>()?.value();
+        if meta.path.is_ident("deserialize") {
+            name = Some(value);
+        }
+        Ok(())
+    })?;
+    Ok(name)
+}
+
+/// Consumes the value of an attribute this crate ignores.
+fn skip_value(meta: &ParseNestedMeta) -> Result<()> {
+    if meta.input.peek(Token![=]) {
+        meta.value()?.parse::<Expr>()?;
+    } else if meta.input.peek(syn::token::Paren) {
+        meta.parse_nested_meta(|meta| skip_value(&meta))?;
+    }
+    Ok(())
+}
+
+/// The text of the doc comments, without the leading space of each line.
+fn doc_comment(attrs: &[Attribute]) -> Option<String> {
+    let lines: Vec<String> = attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("doc"))
+        .filter_map(|attr| match &attr.meta.require_name_value().ok()?.value {
+            Expr::Lit(syn::ExprLit {
+                lit: syn::Lit::Str(line),
+                ..
+            }) => Some(line.value()),
+            _ => None,
+        })
+        .map(|line| {
+            line.strip_prefix(' ')
+                .unwrap_or(&line)
+                .trim_end()
+                .to_string()
+        })
+        .collect();
+    let doc = lines.join("\n").trim().to_string();
+    (!doc.is_empty()).then_some(doc)
+}
+
+fn ident(ident: &Ident) -> String {
+    let ident = ident.to_string();
+    ident.strip_prefix("r#").unwrap_or(&ident).to_string()
+}
+
+/// The case conversions of `#[serde(rename_all = "...")]`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum RenameRule {
+    #[default]
+    None,
+    LowerCase,
+    UpperCase,
+    PascalCase,
+    CamelCase,
+    SnakeCase,
+    ScreamingSnakeCase,
+    KebabCase,
+    ScreamingKebabCase,
+}
+
+impl RenameRule {
+    fn parse(rule: &str) -> Option<Self> {
+        Some(match rule {
+            "lowercase" => RenameRule::LowerCase,
+            "UPPERCASE" => RenameRule::UpperCase,
+            "PascalCase" => RenameRule::PascalCase,
+            "camelCase" => RenameRule::CamelCase,
+            "snake_case" => RenameRule::SnakeCase,
+            "SCREAMING_SNAKE_CASE" => RenameRule::ScreamingSnakeCase,
+            "kebab-case" => RenameRule::KebabCase,
+            "SCREAMING-KEBAB-CASE" => RenameRule::ScreamingKebabCase,
+            _ => return None,
+        })
+    }
+
+    /// Renames a variant, or a type, written in `PascalCase`.
+    fn apply_to_variant(self, variant: &str) -> String {
+        match self {
+            RenameRule::None | RenameRule::PascalCase => variant.to_string(),
+            RenameRule::LowerCase => variant.to_ascii_lowercase(),
+            RenameRule::UpperCase => variant.to_ascii_uppercase(),
+            RenameRule::CamelCase => variant[..1].to_ascii_lowercase() + &variant[1..],
+            RenameRule::SnakeCase => {
+                let mut snake = String::new();
+                for (i, ch) in variant.char_indices() {
+                    if i > 0 && ch.is_uppercase() {
+                        snake.push('_');
+                    }
+                    snake.push(ch.to_ascii_lowercase());
+                }
+                snake
+            }
+            RenameRule::ScreamingSnakeCase => RenameRule::SnakeCase
+                .apply_to_variant(variant)
+                .to_ascii_uppercase(),
+            RenameRule::KebabCase => RenameRule::SnakeCase
+                .apply_to_variant(variant)
+                .replace('_', "-"),
+            RenameRule::ScreamingKebabCase => RenameRule::ScreamingSnakeCase
+                .apply_to_variant(variant)
+                .replace('_', "-"),
+        }
+    }
+
+    /// Renames a field written in `snake_case`.
+    fn apply_to_field(self, field: &str) -> String {
+        match self {
+            RenameRule::None | RenameRule::LowerCase | RenameRule::SnakeCase => field.to_string(),
+            RenameRule::UpperCase | RenameRule::ScreamingSnakeCase => field.to_ascii_uppercase(),
+            RenameRule::PascalCase => {
+                let mut pascal = String::new();
+                let mut capitalize = true;
+                for ch in field.chars() {
+                    if ch == '_' {
+                        capitalize = true;
+                    } else if capitalize {
+                        pascal.push(ch.to_ascii_uppercase());
+                        capitalize = false;
+                    } else {
+                        pascal.push(ch);
+                    }
+                }
+                pascal
+            }
+            RenameRule::CamelCase => {
+                let pascal = RenameRule::PascalCase.apply_to_field(field);
+                pascal[..1].to_ascii_lowercase() + &pascal[1..]
+            }
+            RenameRule::KebabCase => field.replace('_', "-"),
+            RenameRule::ScreamingKebabCase => field.to_ascii_uppercase().replace('_', "-"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renames_like_serde() {
+        let cases = [
+            ("lowercase", "getweather", "city_name"),
+            ("UPPERCASE", "GETWEATHER", "CITY_NAME"),
+            ("PascalCase", "GetWeather", "CityName"),
+            ("camelCase", "getWeather", "cityName"),
+            ("snake_case", "get_weather", "city_name"),
+            ("SCREAMING_SNAKE_CASE", "GET_WEATHER", "CITY_NAME"),
+            ("kebab-case", "get-weather", "city-name"),
+            ("SCREAMING-KEBAB-CASE", "GET-WEATHER", "CITY-NAME"),
+        ];
+        for (rule, variant, field) in cases {
+            let rule = RenameRule::parse(rule).unwrap();
+            assert_eq!(rule.apply_to_variant("GetWeather"), variant);
+            assert_eq!(rule.apply_to_field("city_name"), field);
+        }
+        assert_eq!(RenameRule::parse("Title Case"), None);
+    }
+
+    #[test]
+    fn reads_doc_comments() {
+        let input: DeriveInput = parse_quote! {
+            /// Get the current weather.
+            ///
+            ///  Indented.
+            #[serde(rename_all = "camelCase", deny_unknown_fields)]
+            struct GetWeather;
+        };
+
+        assert_eq!(
+            doc_comment(&input.attrs).as_deref(),
+            Some("Get the current weather.\n\n Indented.")
+        );
+        let serde = SerdeAttrs::parse(&input.attrs).unwrap();
+        assert_eq!(serde.rename_all, RenameRule::CamelCase);
+    }
+}
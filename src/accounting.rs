@@ -0,0 +1,509 @@
+//! Client-wide accounting of tokens and spend, with an optional budget.
+//!
+//! A [`UsageTracker`] set on an [`OpenAiClient`](crate::OpenAiClient) adds up the
+//! [`Usage`](crate::Usage) of every chat, completion, edit and embedding response, priced with
+//! [`price_table`](crate::pricing::price_table). Totals are kept by model, by endpoint and by the
+//! tag of the client that sent the request. Clones of the client share the same tracker, so a
+//! clone can be tagged for each feature:
+//!
+//! ```
+//! use openai::accounting::{Budget, UsageTracker};
+//! use openai::OpenAiClient;
+//! use std::sync::Arc;
+//!
+//! let tracker = Arc::new(UsageTracker::new().with_budget(Budget {
+//!     daily: Some(20.0),
+//!     total: None,
+//! }));
+//! let client = OpenAiClient::new("sk-...").with_usage_tracker(tracker.clone());
+//! let summarizer = client.clone().with_usage_tag("summarizer");
+//!
+//! // Later, report the spend of each feature.
+//! for (tag, totals) in &tracker.snapshot().by_tag {
+//!     println!("{tag}: ${:.2}", totals.cost.total());
+//! }
+//! ```
+//!
+//! Once the spend of the day (in UTC) or the total spend reaches the budget, requests are refused
+//! with [`OpenAiError::BudgetExceeded`] without being sent. Requests already in flight still
+//! complete, so the budget can be overrun by the cost of the last requests.
+//!
+//! Usage is priced with the model the response reports, which names the snapshot that was used,
+//! including on Azure, where requests name a deployment. Requests of models without a price are
+//! counted in [`UsageTotals::unpriced_requests`] rather than adding no cost unnoticed.
+//!
+//! Streamed chat completions are counted when the API reports their usage in their last chunk.
+//! Clients with a tracker ask for it by setting `stream_options` to `{"include_usage": true}`,
+//! unless the request sets its own.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use web_time::{SystemTime, UNIX_EPOCH};
+
+use crate::pricing::{price_table, Cost, TokenCounts};
+use crate::{ApiResponseOrError, OpenAiError};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Spending limits in US dollars. Limits left as `None` don't apply.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct Budget {
+    /// The most that can be spent in a day, from midnight UTC.
+    pub daily: Option<f64>,
+    /// The most that can be spent over the life of the tracker.
+    pub total: Option<f64>,
+}
+
+/// The period a [`Budget`] limit applies to.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BudgetPeriod {
+    Daily,
+    Total,
+}
+
+/// Requests, tokens and spend added up.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct UsageTotals {
+    pub requests: u64,
+    /// Input tokens, including cached ones.
+    pub input_tokens: u64,
+    pub cached_input_tokens: u64,
+    pub output_tokens: u64,
+    /// The cost of the tokens of priced models.
+    pub cost: Cost,
+    /// Requests whose model has no price, whose tokens are counted but add no cost.
+    #[serde(default)]
+    pub unpriced_requests: u64,
+}
+
+/// The totals of a [`UsageTracker`] at one point in time.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct UsageSnapshot {
+    pub total: UsageTotals,
+    /// The totals of the current day, from midnight UTC.
+    pub today: UsageTotals,
+    pub by_model: BTreeMap<String, UsageTotals>,
+    /// Totals by endpoint, such as `chat/completions`.
+    pub by_endpoint: BTreeMap<String, UsageTotals>,
+    /// Totals by the tag of the client that sent the request.
+    /// Requests sent without a tag are only counted in the other totals.
+    pub by_tag: BTreeMap<String, UsageTotals>,
+}
+
+/// Adds up the usage of the requests sent by a client, and enforces its [`Budget`].
+#[derive(Debug, Default)]
+pub struct UsageTracker {
+    budget: Budget,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    total: UsageTotals,
+    /// The day `today` counts, in days since the Unix epoch.
+    day: u64,
+    today: UsageTotals,
+    by_model: HashMap<String, UsageTotals>,
+    by_endpoint: HashMap<String, UsageTotals>,
+    by_tag: HashMap<String, UsageTotals>,
+}
+
+impl UsageTotals {
+    fn add(&mut self, tokens: TokenCounts, cost: Option<Cost>) {
+        self.requests += 1;
+        self.input_tokens += tokens.input as u64;
+        self.cached_input_tokens += tokens.cached_input as u64;
+        self.output_tokens += tokens.output as u64;
+        match cost {
+            Some(cost) => self.cost += cost,
+            None => self.unpriced_requests += 1,
+        }
+    }
+}
+
+impl UsageSnapshot {
+    /// The totals as CSV, with a header row, then one row per total.
+    ///
+    /// The `dimension` column is `total`, `today`, `model`, `endpoint` or `tag`, and `key` is the
+    /// name of the model, endpoint or tag.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from(
+            "dimension,key,requests,input_tokens,cached_input_tokens,output_tokens,cost_usd,unpriced_requests\n",
+        );
+        let rows = [("total", "", &self.total), ("today", "", &self.today)]
+            .into_iter()
+            .chain(self.by_model.iter().map(|(k, t)| ("model", k.as_str(), t)))
+            .chain(
+                self.by_endpoint
+                    .iter()
+                    .map(|(k, t)| ("endpoint", k.as_str(), t)),
+            )
+            .chain(self.by_tag.iter().map(|(k, t)| ("tag", k.as_str(), t)));
+        for (dimension, key, totals) in rows {
+            writeln!(
+                csv,
+                "{dimension},{},{},{},{},{},{},{}",
+                csv_field(key),
+                totals.requests,
+                totals.input_tokens,
+                totals.cached_input_tokens,
+                totals.output_tokens,
+                totals.cost.total(),
+                totals.unpriced_requests
+            )
+            .unwrap();
+        }
+        csv
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+impl UsageTracker {
+    /// Creates a tracker without a budget.
+    pub fn new() -> Self {
+        UsageTracker::default()
+    }
+
+    pub fn with_budget(mut self, budget: Budget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    pub fn budget(&self) -> Budget {
+        self.budget
+    }
+
+    /// Returns a copy of the current totals.
+    pub fn snapshot(&self) -> UsageSnapshot {
+        let mut state = self.state.lock().unwrap();
+        state.roll_over(today());
+        UsageSnapshot {
+            total: state.total,
+            today: state.today,
+            by_model: sorted(&state.by_model),
+            by_endpoint: sorted(&state.by_endpoint),
+            by_tag: sorted(&state.by_tag),
+        }
+    }
+
+    /// Sets every total back to zero.
+    pub fn reset(&self) {
+        *self.state.lock().unwrap() = State::default();
+    }
+
+    /// Counts a request to `endpoint` that used `tokens` of `model`. If the model has no price,
+    /// the request is counted in [`UsageTotals::unpriced_requests`].
+    ///
+    /// Requests sent by a client with this tracker are counted automatically, with the model
+    /// their response reports. This is for requests that weren't, such as those sent by other
+    /// means.
+    pub fn record(&self, model: &str, endpoint: &str, tag: Option<&str>, tokens: TokenCounts) {
+        let cost = price_table().cost(model, tokens);
+        self.record_on(today(), model, endpoint, tag, tokens, cost);
+    }
+
+    /// Fails with [`OpenAiError::BudgetExceeded`] if a limit of the budget has been reached.
+    pub fn check_budget(&self) -> ApiResponseOrError<()> {
+        self.check_budget_on(today())
+    }
+
+    fn record_on(
+        &self,
+        day: u64,
+        model: &str,
+        endpoint: &str,
+        tag: Option<&str>,
+        tokens: TokenCounts,
+        cost: Option<Cost>,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        state.roll_over(day);
+        state.total.add(tokens, cost);
+        state.today.add(tokens, cost);
+        let State {
+            by_model,
+            by_endpoint,
+            by_tag,
+            ..
+        } = &mut *state;
+        for (totals, key) in [
+            (by_model, Some(model)),
+            (by_endpoint, Some(endpoint)),
+            (by_tag, tag),
+        ] {
+            if let Some(key) = key {
+                totals.entry(key.to_string()).or_default().add(tokens, cost);
+            }
+        }
+    }
+
+    fn check_budget_on(&self, day: u64) -> ApiResponseOrError<()> {
+        let mut state = self.state.lock().unwrap();
+        state.roll_over(day);
+        for (period, limit, spent) in [
+            (BudgetPeriod::Daily, self.budget.daily, &state.today),
+            (BudgetPeriod::Total, self.budget.total, &state.total),
+        ] {
+            let spent = spent.cost.total();
+            if let Some(limit) = limit.filter(|limit| spent >= *limit) {
+                return Err(OpenAiError::BudgetExceeded {
+                    period,
+                    limit,
+                    spent,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl State {
+    /// Starts counting a new day if `day` is after the one counted.
+    fn roll_over(&mut self, day: u64) {
+        if day > self.day {
+            self.day = day;
+            self.today = UsageTotals::default();
+        }
+    }
+}
+
+fn sorted(totals: &HashMap<String, UsageTotals>) -> BTreeMap<String, UsageTotals> {
+    totals
+        .iter()
+        .map(|(key, totals)| (key.clone(), *totals))
+        .collect()
+}
+
+/// The current day in UTC, in days since the Unix epoch.
+fn today() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / SECONDS_PER_DAY
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::{ChatCompletion, ChatCompletionMessage};
+    use crate::tests::{chat_completion_json, http_response, serve_responses};
+    use crate::OpenAiClient;
+    use futures_util::StreamExt;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn tokens(input: u32, output: u32) -> TokenCounts {
+        TokenCounts {
+            input,
+            cached_input: 0,
+            output,
+        }
+    }
+
+    fn cost(total: f64) -> Option<Cost> {
+        Some(Cost {
+            input: total,
+            ..Cost::default()
+        })
+    }
+
+    #[test]
+    fn totals_by_dimension() {
+        let tracker = UsageTracker::new();
+        tracker.record_on(
+            1,
+            "gpt-4o",
+            "chat/completions",
+            Some("a"),
+            tokens(10, 5),
+            cost(1.0),
+        );
+        tracker.record_on(
+            1,
+            "gpt-4o",
+            "chat/completions",
+            None,
+            tokens(20, 5),
+            cost(2.0),
+        );
+        tracker.record_on(
+            1,
+            "text-embedding-3-small",
+            "embeddings",
+            Some("a"),
+            tokens(7, 0),
+            cost(0.5),
+        );
+
+        let snapshot = tracker.snapshot();
+
+        assert_eq!(snapshot.total.requests, 3);
+        assert_eq!(snapshot.total.input_tokens, 37);
+        assert_eq!(snapshot.total.cost.total(), 3.5);
+        assert_eq!(snapshot.by_model["gpt-4o"].output_tokens, 10);
+        assert_eq!(snapshot.by_endpoint["embeddings"].requests, 1);
+        assert_eq!(snapshot.by_tag.len(), 1);
+        assert_eq!(snapshot.by_tag["a"].cost.total(), 1.5);
+        assert!(snapshot.to_csv().contains("\ntag,a,2,17,0,5,1.5,0\n"));
+        // The day of the records has passed.
+        assert_eq!(snapshot.today, UsageTotals::default());
+
+        tracker.reset();
+        assert_eq!(tracker.snapshot(), UsageSnapshot::default());
+    }
+
+    #[test]
+    fn enforces_budget() {
+        let tracker = UsageTracker::new().with_budget(Budget {
+            daily: Some(1.0),
+            total: Some(2.5),
+        });
+
+        tracker.record_on(
+            1,
+            "gpt-4o",
+            "chat/completions",
+            None,
+            tokens(1, 1),
+            cost(0.9),
+        );
+        assert!(tracker.check_budget_on(1).is_ok());
+        tracker.record_on(
+            1,
+            "gpt-4o",
+            "chat/completions",
+            None,
+            tokens(1, 1),
+            cost(0.2),
+        );
+        assert!(matches!(
+            tracker.check_budget_on(1),
+            Err(OpenAiError::BudgetExceeded {
+                period: BudgetPeriod::Daily,
+                ..
+            })
+        ));
+
+        // The daily limit starts over the next day, but the total limit doesn't.
+        assert!(tracker.check_budget_on(2).is_ok());
+        tracker.record_on(
+            2,
+            "gpt-4o",
+            "chat/completions",
+            None,
+            tokens(1, 1),
+            cost(0.9),
+        );
+        tracker.record_on(
+            3,
+            "gpt-4o",
+            "chat/completions",
+            None,
+            tokens(1, 1),
+            cost(0.5),
+        );
+        let error = tracker.check_budget_on(3).unwrap_err();
+        assert!(matches!(
+            error,
+            OpenAiError::BudgetExceeded {
+                period: BudgetPeriod::Total,
+                limit,
+                ..
+            } if limit == 2.5
+        ));
+    }
+
+    #[tokio::test]
+    async fn counts_client_requests() {
+        let mut body = chat_completion_json(
+            "gpt-4o-2024-08-06",
+            "stop",
+            json!({"role": "assistant", "content": "Hi!"}),
+        );
+        body["usage"] =
+            json!({"prompt_tokens": 1_000_000, "completion_tokens": 0, "total_tokens": 1_000_000});
+        let (base_url, requests) =
+            serve_responses(vec![http_response(200, &[], &body.to_string())]).await;
+        let tracker = Arc::new(UsageTracker::new().with_budget(Budget {
+            daily: None,
+            total: Some(2.0),
+        }));
+        let client = OpenAiClient::new("key")
+            .with_base_url(base_url)
+            .with_usage_tracker(tracker.clone())
+            .with_usage_tag("greeter");
+        // The deployment isn't a model, so the usage is priced with the model of the response.
+        let request = || {
+            ChatCompletion::builder("my-deployment", [ChatCompletionMessage::user("Hello!")])
+                .client(&client)
+                .create()
+        };
+
+        request().await.unwrap();
+        let snapshot = tracker.snapshot();
+        assert_eq!(snapshot.by_tag["greeter"].input_tokens, 1_000_000);
+        assert_eq!(snapshot.by_endpoint["chat/completions"].cost.total(), 2.5);
+        assert_eq!(snapshot.by_model["gpt-4o-2024-08-06"].requests, 1);
+        assert_eq!(snapshot.total.unpriced_requests, 0);
+
+        let error = request().await.unwrap_err();
+        assert!(matches!(error, OpenAiError::BudgetExceeded { .. }));
+        assert_eq!(
+            error.to_string(),
+            "total budget of $2.00 reached ($2.50 spent)"
+        );
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn counts_streams_and_unpriced_models() {
+        let events = [
+            r#"data: {"id":"chatcmpl-1","object":"chat.completion.chunk","created":0,"model":"my-model","choices":[{"index":0,"finish_reason":"stop","delta":{"role":"assistant","content":"Hi!"}}],"usage":null}"#,
+            r#"data: {"id":"chatcmpl-1","object":"chat.completion.chunk","created":0,"model":"my-model","choices":[],"usage":{"prompt_tokens":9,"completion_tokens":2,"total_tokens":11}}"#,
+            "data: [DONE]",
+        ]
+        .join("\n\n")
+            + "\n\n";
+        let (base_url, requests) = serve_responses(vec![http_response(
+            200,
+            &[("content-type", "text/event-stream")],
+            &events,
+        )])
+        .await;
+        let tracker = Arc::new(UsageTracker::new());
+        let client = OpenAiClient::new("key")
+            .with_base_url(base_url)
+            .with_usage_tracker(tracker.clone());
+
+        let mut deltas =
+            ChatCompletion::builder("my-model", [ChatCompletionMessage::user("Hello!")])
+                .client(&client)
+                .create_delta_stream()
+                .await
+                .unwrap();
+        let mut merged = deltas.next().await.unwrap().unwrap();
+        while let Some(delta) = deltas.next().await {
+            merged.merge(delta.unwrap()).unwrap();
+        }
+
+        assert_eq!(merged.usage.unwrap().total_tokens, 11);
+        assert!(requests.lock().unwrap()[0].contains(r#""stream_options":{"include_usage":true}"#));
+        let totals = tracker.snapshot().by_model["my-model"];
+        assert_eq!(totals.input_tokens, 9);
+        assert_eq!(totals.output_tokens, 2);
+        assert_eq!(totals.cost, Cost::default());
+        assert_eq!(totals.unpriced_requests, 1);
+    }
+}
@@ -0,0 +1,562 @@
+//! Runs tool-calling conversations with Rust handlers.
+//!
+//! An [`Agent`] holds [`AgentTool`]s, each a function definition with an async handler. Its
+//! [`run`](Agent::run) sends a chat completion request with the tools attached, runs the tools the
+//! model calls, answers with their results as `Tool` messages, and sends the conversation again,
+//! until the model replies without calling a tool:
+//!
+//! ```no_run
+//! use openai::agent::{Agent, AgentTool};
+//! use openai::chat::{ChatCompletion, ChatCompletionFunctionDefinition, ChatCompletionMessage};
+//! use serde_json::{json, Value};
+//! use std::time::Duration;
+//!
+//! # async fn example() -> openai::ApiResponseOrError<()> {
+//! let weather = AgentTool::new(
+//!     ChatCompletionFunctionDefinition {
+//!         name: "get_weather".to_string(),
+//!         description: Some("Get the current weather in a city.".to_string()),
+//!         parameters: Some(json!({
+//!             "type": "object",
+//!             "properties": { "city": { "type": "string" } },
+//!             "required": ["city"]
+//!         })),
+//!     },
+//!     |arguments: Value| async move {
+//!         let city = arguments["city"].as_str().ok_or("missing city")?;
+//!         Ok::<_, &str>(json!({ "city": city, "celsius": 22 }))
+//!     },
+//! )
+//! .with_timeout(Duration::from_secs(5));
+//! let agent = Agent::new().with_tool(weather);
+//!
+//! let question = ChatCompletionMessage::user("Should I take an umbrella in Paris?");
+//! let run = agent.run(ChatCompletion::builder("gpt-4o", [question])).await?;
+//! println!("{}", run.reply().unwrap_or_default());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The calls of one reply are independent, so they run concurrently, on the task running the
+//! agent. Failures of a tool don't stop the run: unknown tools, malformed arguments, handler
+//! errors and timeouts are sent back to the model as the result of the call, in the form
+//! `{"error": "..."}`, so it can correct itself. Errors of the API end the run.
+//!
+//! Tools can also be defined from the type of their arguments with [`AgentTool::typed`], such as
+//! a type deriving [`FunctionArguments`] with the `derive` feature. Their handler receives the
+//! arguments already parsed.
+//!
+//! Every message sent and received is kept in the [`AgentRun`], along with a record of each tool
+//! call, for auditing.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::future::{self, BoxFuture, FutureExt};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::chat::{
+    ChatCompletion, ChatCompletionBuilder, ChatCompletionFunctionDefinition, ChatCompletionMessage,
+    ChatCompletionMessageRole, ChatCompletionTool, ChatCompletionToolCall,
+};
+use crate::runtime::{timeout, Instant};
+use crate::schema::{arguments_from_value, FunctionArguments};
+use crate::ApiResponseOrError;
+
+const DEFAULT_MAX_ITERATIONS: u32 = 10;
+
+type Handler = dyn Fn(Value) -> BoxFuture<'static, Result<String, String>> + Send + Sync;
+
+/// A tool an [`Agent`] can run: a function definition sent to the model, and the handler called
+/// with the arguments of each call.
+#[derive(Clone)]
+pub struct AgentTool {
+    definition: ChatCompletionFunctionDefinition,
+    handler: Arc<Handler>,
+    timeout: Option<Duration>,
+}
+
+/// Runs conversations in which the model can call [`AgentTool`]s.
+#[derive(Clone)]
+pub struct Agent {
+    tools: HashMap<String, AgentTool>,
+    /// The names of the tools, in the order they were added.
+    order: Vec<String>,
+    max_iterations: u32,
+    tool_timeout: Option<Duration>,
+    parallel_tool_calls: bool,
+}
+
+/// The transcript of a finished [`Agent::run`].
+#[derive(Clone, Debug)]
+pub struct AgentRun {
+    /// Every message of the conversation, starting with those of the request.
+    pub messages: Vec<ChatCompletionMessage>,
+    /// The completions returned by the API, one per iteration.
+    pub completions: Vec<ChatCompletion>,
+    /// The tool calls that were run, in the order the model made them.
+    pub tool_runs: Vec<ToolRun>,
+    /// Why the run stopped.
+    pub stop: AgentStop,
+}
+
+/// Why an [`Agent::run`] stopped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AgentStop {
+    /// The model replied without calling a tool.
+    Reply,
+    /// The model was still calling tools after the maximum number of iterations.
+    MaxIterations,
+}
+
+/// A tool call run by an [`Agent`].
+#[derive(Clone, Debug)]
+pub struct ToolRun {
+    /// The call, as made by the model.
+    pub call: ChatCompletionToolCall,
+    /// The result sent back to the model, or the error sent in its place.
+    pub output: Result<String, String>,
+    /// How long the handler ran.
+    pub elapsed: Duration,
+}
+
+impl AgentTool {
+    /// A tool running `handler` with the arguments of each call, parsed as JSON.
+    ///
+    /// The handler's output is sent to the model as is if it is a string, and encoded as JSON
+    /// otherwise. Its errors are sent as `{"error": "..."}`.
+    pub fn new<F, Fut, T, E>(definition: ChatCompletionFunctionDefinition, handler: F) -> Self
+    where
+        F: Fn(Value) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<T, E>> + Send + 'static,
+        T: Serialize,
+        E: Display,
+    {
+        let handler = move |arguments| {
+            handler(arguments)
+                .map(|output| match output {
+                    Ok(output) => match serde_json::to_value(output) {
+                        Ok(Value::String(output)) => Ok(output),
+                        Ok(output) => Ok(output.to_string()),
+                        Err(error) => Err(format!("the output could not be encoded: {error}")),
+                    },
+                    Err(error) => Err(error.to_string()),
+                })
+                .boxed()
+        };
+        AgentTool {
+            definition,
+            handler: Arc::new(handler),
+            timeout: None,
+        }
+    }
+
+    /// A tool defined by the type of its arguments, running `handler` with the arguments of
+    /// each call parsed into that type.
+    ///
+    /// Arguments that don't match the type are sent back to the model as an error, with the path
+    /// of the value in error.
+    pub fn typed<A, F, Fut, T, E>(handler: F) -> Self
+    where
+        A: FunctionArguments + Send + 'static,
+        F: Fn(A) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<T, E>> + Send + 'static,
+        T: Serialize,
+        E: Display,
+    {
+        AgentTool::new(A::definition(), move |arguments| {
+            let output = arguments_from_value::<A>(A::NAME, arguments).map(&handler);
+            async move {
+                match output {
+                    Ok(output) => output.await.map_err(|error| error.to_string()),
+                    Err(error) => Err(error.to_string()),
+                }
+            }
+        })
+    }
+
+    /// Stops the handler if it runs for longer than `timeout`, replacing the agent's default.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.definition.name
+    }
+
+    pub fn definition(&self) -> &ChatCompletionFunctionDefinition {
+        &self.definition
+    }
+}
+
+impl Agent {
+    /// An agent without tools, which stops after 10 iterations and runs calls concurrently.
+    pub fn new() -> Self {
+        Agent {
+            tools: HashMap::new(),
+            order: Vec::new(),
+            max_iterations: DEFAULT_MAX_ITERATIONS,
+            tool_timeout: None,
+            parallel_tool_calls: true,
+        }
+    }
+
+    /// Adds a tool, replacing any tool of the same name.
+    pub fn with_tool(mut self, tool: AgentTool) -> Self {
+        let name = tool.name().to_string();
+        if self.tools.insert(name.clone(), tool).is_none() {
+            self.order.push(name);
+        }
+        self
+    }
+
+    /// Sets the maximum number of requests sent to the API in one run.
+    pub fn with_max_iterations(mut self, max_iterations: u32) -> Self {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    /// Sets the timeout of tools without one of their own. Tools don't time out by default.
+    pub fn with_tool_timeout(mut self, timeout: Duration) -> Self {
+        self.tool_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets whether the model may call several tools in one reply, which are then run
+    /// concurrently. Otherwise, the model calls one tool at a time.
+    pub fn with_parallel_tool_calls(mut self, parallel_tool_calls: bool) -> Self {
+        self.parallel_tool_calls = parallel_tool_calls;
+        self
+    }
+
+    pub fn tools(&self) -> impl Iterator<Item = &AgentTool> {
+        self.order.iter().map(|name| &self.tools[name])
+    }
+
+    /// Sends `request` with the agent's tools, replacing the tools it had, and runs the tools the
+    /// model calls until it replies without calling one, or the maximum number of iterations is
+    /// reached.
+    pub async fn run(&self, mut request: ChatCompletionBuilder) -> ApiResponseOrError<AgentRun> {
+        if !self.tools.is_empty() {
+            let tools: Vec<_> = self
+                .tools()
+                .map(|tool| ChatCompletionTool::function(tool.definition.clone()))
+                .collect();
+            request = request.tools(tools);
+            if !self.parallel_tool_calls {
+                request = request.parallel_tool_calls(false);
+            }
+        }
+        let mut request = request.build()?;
+        let mut run = AgentRun {
+            messages: request.messages.clone(),
+            completions: Vec::new(),
+            tool_runs: Vec::new(),
+            stop: AgentStop::MaxIterations,
+        };
+        for _ in 0..self.max_iterations {
+            let completion = ChatCompletion::create(&request).await?;
+            let message = completion
+                .choices
+                .first()
+                .map(|choice| choice.message.clone());
+            run.completions.push(completion);
+            let Some(message) = message else {
+                run.stop = AgentStop::Reply;
+                break;
+            };
+            let calls = message.tool_calls.clone().unwrap_or_default();
+            request.messages.push(message);
+            if calls.is_empty() {
+                run.stop = AgentStop::Reply;
+                break;
+            }
+            for tool_run in
+                future::join_all(calls.into_iter().map(|call| self.run_tool(call))).await
+            {
+                request.messages.push(ChatCompletionMessage::tool(
+                    tool_run.call.id.clone(),
+                    match &tool_run.output {
+                        Ok(output) => output.clone(),
+                        Err(error) => json!({ "error": error }).to_string(),
+                    },
+                ));
+                run.tool_runs.push(tool_run);
+            }
+        }
+        run.messages = request.messages;
+        Ok(run)
+    }
+
+    async fn run_tool(&self, call: ChatCompletionToolCall) -> ToolRun {
+        let start = Instant::now();
+        let output = match self.tools.get(&call.function.name) {
+            None => Err(format!("unknown tool `{}`", call.function.name)),
+            Some(tool) => match parse_arguments(&call.function.arguments) {
+                Err(error) => Err(format!("the arguments are not valid JSON: {error}")),
+                Ok(arguments) => {
+                    let output = (tool.handler)(arguments);
+                    match tool.timeout.or(self.tool_timeout) {
+                        None => output.await,
+                        Some(duration) => timeout(duration, output).await.unwrap_or_else(|| {
+                            Err(format!("timed out after {} ms", duration.as_millis()))
+                        }),
+                    }
+                }
+            },
+        };
+        ToolRun {
+            call,
+            output,
+            elapsed: start.elapsed(),
+        }
+    }
+}
+
+impl Default for Agent {
+    fn default() -> Self {
+        Agent::new()
+    }
+}
+
+impl AgentRun {
+    /// The content of the model's last reply.
+    pub fn reply(&self) -> Option<&str> {
+        self.messages
+            .last()
+            .filter(|message| matches!(message.role, ChatCompletionMessageRole::Assistant))
+            .and_then(ChatCompletionMessage::text)
+    }
+}
+
+/// Parses the arguments of a call, which models leave empty for functions without parameters.
+fn parse_arguments(arguments: &str) -> serde_json::Result<Value> {
+    match arguments.trim() {
+        "" => Ok(json!({})),
+        arguments => serde_json::from_str(arguments),
+    }
+}
+
+impl std::fmt::Debug for AgentTool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AgentTool")
+            .field("definition", &self.definition)
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+impl std::fmt::Debug for Agent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Agent")
+            .field("tools", &self.tools().collect::<Vec<_>>())
+            .field("max_iterations", &self.max_iterations)
+            .field("tool_timeout", &self.tool_timeout)
+            .field("parallel_tool_calls", &self.parallel_tool_calls)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{
+        chat_completion_json, chat_completion_response, http_response, request_body,
+        serve_responses,
+    };
+    use crate::OpenAiClient;
+
+    fn tool_calls_response(calls: &[(&str, &str, &str)]) -> String {
+        let calls: Vec<_> = calls
+            .iter()
+            .map(|(id, name, arguments)| ChatCompletionToolCall::function(*id, *name, *arguments))
+            .collect();
+        let message = json!({ "role": "assistant", "content": null, "tool_calls": calls });
+        let body = chat_completion_json("gpt-4o", "tool_calls", message);
+        http_response(200, &[], &body.to_string())
+    }
+
+    fn definition(name: &str) -> ChatCompletionFunctionDefinition {
+        ChatCompletionFunctionDefinition {
+            name: name.to_string(),
+            description: None,
+            parameters: Some(json!({"type": "object", "properties": {}})),
+        }
+    }
+
+    fn request(client: &OpenAiClient) -> ChatCompletionBuilder {
+        ChatCompletion::builder(
+            "gpt-4o",
+            [ChatCompletionMessage::user(
+                "Should I take an umbrella in Paris?",
+            )],
+        )
+        .client(client)
+    }
+
+    #[tokio::test]
+    async fn runs_tools_until_reply() {
+        let (base_url, requests) = serve_responses(vec![
+            tool_calls_response(&[
+                ("call_1", "get_weather", r#"{"city":"Paris"}"#),
+                ("call_2", "launch", "{}"),
+                ("call_3", "get_weather", r#"{"city":"#),
+                ("call_4", "get_weather", r#"{"city":"Atlantis"}"#),
+                ("call_5", "slow", ""),
+            ]),
+            chat_completion_response("gpt-4o", "Yes, it is raining."),
+        ])
+        .await;
+        let client = OpenAiClient::new("key").with_base_url(base_url);
+        let weather = AgentTool::new(definition("get_weather"), |arguments: Value| async move {
+            match arguments["city"].as_str() {
+                Some("Paris") => Ok(json!({"weather": "rain"})),
+                city => Err(format!("no weather for {city:?}")),
+            }
+        });
+        let slow = AgentTool::new(definition("slow"), |_| async {
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            Ok::<_, String>("done")
+        })
+        .with_timeout(Duration::from_millis(20));
+        let agent = Agent::new().with_tool(weather).with_tool(slow);
+
+        let run = agent.run(request(&client)).await.unwrap();
+
+        assert_eq!(run.stop, AgentStop::Reply);
+        assert_eq!(run.reply(), Some("Yes, it is raining."));
+        assert_eq!(run.completions.len(), 2);
+        assert_eq!(run.messages.len(), 8);
+        let outputs: Vec<_> = run
+            .tool_runs
+            .iter()
+            .map(|tool_run| tool_run.output.clone())
+            .collect();
+        assert_eq!(outputs[0], Ok(r#"{"weather":"rain"}"#.to_string()));
+        assert_eq!(outputs[1], Err("unknown tool `launch`".to_string()));
+        assert!(outputs[2]
+            .as_ref()
+            .is_err_and(|error| error.starts_with("the arguments are not valid JSON")));
+        assert_eq!(
+            outputs[3],
+            Err(r#"no weather for Some("Atlantis")"#.to_string())
+        );
+        assert_eq!(outputs[4], Err("timed out after 20 ms".to_string()));
+
+        let requests = requests.lock().unwrap();
+        let first = request_body(&requests[0]);
+        assert_eq!(first["tools"][0]["function"]["name"], "get_weather");
+        assert_eq!(first["tools"][1]["function"]["name"], "slow");
+        let second = request_body(&requests[1]);
+        let messages = second["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 7);
+        assert_eq!(messages[1]["tool_calls"][1]["function"]["name"], "launch");
+        assert_eq!(
+            messages[2],
+            json!({"role": "tool", "content": r#"{"weather":"rain"}"#, "tool_call_id": "call_1"})
+        );
+        assert_eq!(
+            messages[3]["content"],
+            json!({"error": "unknown tool `launch`"}).to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn runs_typed_tools() {
+        /// Add two numbers.
+        #[derive(serde::Deserialize, openai_derive::FunctionArguments)]
+        struct Add {
+            a: i64,
+            b: i64,
+        }
+
+        let (base_url, requests) = serve_responses(vec![
+            tool_calls_response(&[
+                ("call_1", "add", r#"{"a":2,"b":3}"#),
+                ("call_2", "add", r#"{"a":2,"b":"3"}"#),
+            ]),
+            chat_completion_response("gpt-4o", "5"),
+        ])
+        .await;
+        let client = OpenAiClient::new("key").with_base_url(base_url);
+        let add = AgentTool::typed(|Add { a, b }| async move { Ok::<_, String>(a + b) });
+        let agent = Agent::new().with_tool(add);
+
+        let run = agent.run(request(&client)).await.unwrap();
+
+        assert_eq!(run.tool_runs[0].output, Ok("5".to_string()));
+        assert_eq!(
+            run.tool_runs[1].output,
+            Err(
+                "invalid arguments for `add` at `b`: invalid type: string \"3\", expected i64"
+                    .to_string()
+            )
+        );
+        let first = request_body(&requests.lock().unwrap()[0]);
+        assert_eq!(
+            first["tools"][0]["function"]["description"],
+            "Add two numbers."
+        );
+    }
+
+    #[tokio::test]
+    async fn runs_calls_concurrently() {
+        let (base_url, _) = serve_responses(vec![
+            tool_calls_response(&[("call_1", "meet", "{}"), ("call_2", "meet", "{}")]),
+            chat_completion_response("gpt-4o", "Met."),
+        ])
+        .await;
+        let client = OpenAiClient::new("key").with_base_url(base_url);
+        // Each call waits for the other, so they only complete if run at the same time.
+        let barrier = Arc::new(tokio::sync::Barrier::new(2));
+        let meet = AgentTool::new(definition("meet"), move |_| {
+            let barrier = barrier.clone();
+            async move {
+                barrier.wait().await;
+                Ok::<_, String>("met")
+            }
+        });
+        let agent = Agent::new()
+            .with_tool(meet)
+            .with_tool_timeout(Duration::from_secs(5));
+
+        let run = agent.run(request(&client)).await.unwrap();
+
+        assert_eq!(run.tool_runs.len(), 2);
+        assert!(run
+            .tool_runs
+            .iter()
+            .all(|tool_run| tool_run.output == Ok("met".to_string())));
+    }
+
+    #[tokio::test]
+    async fn stops_after_max_iterations() {
+        let (base_url, requests) = serve_responses(vec![
+            tool_calls_response(&[("call_1", "noop", "")]),
+            tool_calls_response(&[("call_2", "noop", "")]),
+            chat_completion_response("gpt-4o", "Never sent."),
+        ])
+        .await;
+        let client = OpenAiClient::new("key").with_base_url(base_url);
+        let noop = AgentTool::new(definition("noop"), |_| async { Ok::<_, String>(()) });
+        let agent = Agent::new()
+            .with_tool(noop)
+            .with_max_iterations(2)
+            .with_parallel_tool_calls(false);
+
+        let run = agent.run(request(&client)).await.unwrap();
+
+        assert_eq!(run.stop, AgentStop::MaxIterations);
+        assert_eq!(run.reply(), None);
+        assert_eq!(run.tool_runs.len(), 2);
+        assert_eq!(run.tool_runs[0].output, Ok("null".to_string()));
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(request_body(&requests[0])["parallel_tool_calls"], false);
+    }
+}
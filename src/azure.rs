@@ -0,0 +1,95 @@
+//! Support for [Azure OpenAI](https://learn.microsoft.com/azure/ai-services/openai/reference).
+//!
+//! Azure serves models from deployments that are named by the user, at
+//! `{endpoint}/openai/deployments/{deployment}/...`, and expects an `api-version` query parameter
+//! and an `api-key` header. An [`OpenAiClient`](crate::OpenAiClient) configured with an
+//! [`AzureConfig`] sends chat, completion, embedding, file and moderation requests that way,
+//! mapping the model of each request to its deployment.
+//!
+//! ```
+//! use openai::azure::AzureConfig;
+//! use openai::OpenAiClient;
+//!
+//! let client = OpenAiClient::new("azure-api-key").with_azure(
+//!     AzureConfig::new("https://my-resource.openai.azure.com", "2024-02-01")
+//!         .with_deployment("gpt-4", "my-gpt-4")
+//!         .with_deployment("text-embedding-ada-002", "embeddings"),
+//! );
+//! assert_eq!(client.base_url(), "https://my-resource.openai.azure.com/openai/");
+//! ```
+
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AzureConfig {
+    endpoint: String,
+    api_version: String,
+    deployments: HashMap<String, String>,
+}
+
+impl AzureConfig {
+    /// Creates a configuration for the resource at `endpoint`, such as
+    /// `https://my-resource.openai.azure.com`, using the given `api-version`.
+    pub fn new(endpoint: impl Into<String>, api_version: impl Into<String>) -> Self {
+        let mut endpoint = endpoint.into();
+        if !endpoint.ends_with('/') {
+            endpoint += "/";
+        }
+        AzureConfig {
+            endpoint,
+            api_version: api_version.into(),
+            deployments: HashMap::new(),
+        }
+    }
+
+    /// Sends requests for `model` to the given deployment.
+    /// Models without a deployment of their own are sent to a deployment of the same name.
+    pub fn with_deployment(
+        mut self,
+        model: impl Into<String>,
+        deployment: impl Into<String>,
+    ) -> Self {
+        self.deployments.insert(model.into(), deployment.into());
+        self
+    }
+
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    pub fn api_version(&self) -> &str {
+        &self.api_version
+    }
+
+    /// The deployment requests for `model` are sent to.
+    pub fn deployment<'a>(&'a self, model: &'a str) -> &'a str {
+        self.deployments
+            .get(model)
+            .map(String::as_str)
+            .unwrap_or(model)
+    }
+
+    /// The base url of the resource's API, under which routes such as `files` are found.
+    pub(crate) fn base_url(&self) -> String {
+        self.endpoint.clone() + "openai/"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deployments() {
+        let config = AzureConfig::new("https://my-resource.openai.azure.com", "2024-02-01")
+            .with_deployment("gpt-4", "my-gpt-4");
+
+        assert_eq!(config.endpoint(), "https://my-resource.openai.azure.com/");
+        assert_eq!(
+            config.base_url(),
+            "https://my-resource.openai.azure.com/openai/"
+        );
+        assert_eq!(config.deployment("gpt-4"), "my-gpt-4");
+        assert_eq!(config.deployment("gpt-35-turbo"), "gpt-35-turbo");
+    }
+}
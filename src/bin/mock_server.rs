@@ -0,0 +1,22 @@
+//! Serves a mock of the OpenAI API, see [`openai::mock`].
+//!
+//! Usage: `openai-mock-server [ADDRESS]`, where the address defaults to `127.0.0.1:8080`.
+
+use std::net::SocketAddr;
+
+use openai::mock::MockServer;
+
+const DEFAULT_ADDRESS: &str = "127.0.0.1:8080";
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let address: SocketAddr = std::env::args()
+        .nth(1)
+        .as_deref()
+        .unwrap_or(DEFAULT_ADDRESS)
+        .parse()?;
+    let server = MockServer::bind(address).await?;
+    println!("Mock OpenAI API listening on {}", server.base_url());
+    tokio::signal::ctrl_c().await?;
+    Ok(())
+}
@@ -0,0 +1,126 @@
+//! Synchronous versions of the API calls, for programs that don't use async.
+//!
+//! With the `blocking` feature, the most common calls get a `_blocking` twin that takes the same
+//! requests and builders, and waits for the response on the current thread:
+//!
+//! - [`ChatCompletionBuilder::create_blocking`](crate::chat::ChatCompletionBuilder::create_blocking),
+//!   and [`create_stream_blocking`](crate::chat::ChatCompletionBuilder::create_stream_blocking),
+//!   which returns an iterator over the streamed deltas
+//! - [`CompletionBuilder::create_blocking`](crate::completions::CompletionBuilder::create_blocking)
+//! - [`Embeddings::create_blocking`](crate::embeddings::Embeddings::create_blocking) and
+//!   [`EmbeddingsBuilder::create_blocking`](crate::embeddings::EmbeddingsBuilder::create_blocking)
+//! - [`FileUploadBuilder::create_blocking`](crate::files::FileUploadBuilder::create_blocking),
+//!   [`File::get_content_bytes_blocking`](crate::files::File::get_content_bytes_blocking) and
+//!   [`File::download_content_to_file_blocking`](crate::files::File::download_content_to_file_blocking)
+//! - [`ModerationBuilder::create_blocking`](crate::moderations::ModerationBuilder::create_blocking)
+//! - [`Model::from_blocking`](crate::models::Model::from_blocking)
+//!
+//! Any other call can be made synchronous with [`block_on`].
+//!
+//! Requests are run on a runtime shared by the whole program, which is started on first use.
+//! Blocking calls must not be made from async code, where they panic.
+//!
+//! ```no_run
+//! use openai::chat::{ChatCompletion, ChatCompletionMessage};
+//!
+//! # fn example() -> openai::ApiResponseOrError<()> {
+//! let messages = [ChatCompletionMessage::user("Hello!")];
+//! for delta in ChatCompletion::builder("gpt-3.5-turbo", messages).create_stream_blocking()? {
+//!     let delta = delta?;
+//!     print!("{}", delta.choices[0].delta.content.as_deref().unwrap_or_default());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::future::Future;
+use std::sync::OnceLock;
+
+use tokio::runtime::{Builder, Runtime};
+
+/// Runs `future` to completion on the shared runtime, blocking the current thread.
+///
+/// # Panics
+///
+/// When called from async code.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    runtime().block_on(future)
+}
+
+fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    // A worker thread keeps streams flowing between calls to their iterators.
+    RUNTIME.get_or_init(|| {
+        Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("openai-blocking")
+            .enable_all()
+            .build()
+            .expect("failed to start the runtime of the blocking API")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::{ChatCompletion, ChatCompletionMessage};
+    use crate::embeddings::Embeddings;
+    use crate::tests::{chat_completion_response, http_response, serve_responses};
+    use crate::OpenAiClient;
+
+    fn message() -> ChatCompletionMessage {
+        ChatCompletionMessage::user("Hello!")
+    }
+
+    #[test]
+    fn blocking_requests() {
+        let (base_url, requests) = block_on(serve_responses(vec![
+            chat_completion_response("gpt-3.5-turbo", "Hi!"),
+            http_response(
+                200,
+                &[],
+                r#"{"data":[{"embedding":[1.0]}],"model":"text-embedding-ada-002","usage":{"prompt_tokens":1,"total_tokens":1}}"#,
+            ),
+        ]));
+        let client = OpenAiClient::new("key").with_base_url(base_url);
+
+        let chat_completion = ChatCompletion::builder("gpt-3.5-turbo", [message()])
+            .client(&client)
+            .create_blocking()
+            .unwrap();
+        assert_eq!(chat_completion.choices[0].message.text(), Some("Hi!"));
+
+        let embeddings = Embeddings::builder("text-embedding-ada-002", ["Hi!"])
+            .client(&client)
+            .create_blocking()
+            .unwrap();
+        assert_eq!(embeddings.data[0].vec, [1.0]);
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn blocking_stream_iterator() {
+        let events = [
+            r#"data: {"id":"chatcmpl-1","object":"chat.completion.chunk","created":0,"model":"gpt-3.5-turbo","choices":[{"index":0,"finish_reason":null,"delta":{"role":"assistant","content":"Hello"}}]}"#,
+            r#"data: {"id":"chatcmpl-1","object":"chat.completion.chunk","created":0,"model":"gpt-3.5-turbo","choices":[{"index":0,"finish_reason":"stop","delta":{"content":" there!"}}]}"#,
+            "data: [DONE]",
+        ]
+        .join("\n\n")
+            + "\n\n";
+        let (base_url, _) = block_on(serve_responses(vec![http_response(
+            200,
+            &[("content-type", "text/event-stream")],
+            &events,
+        )]));
+        let client = OpenAiClient::new("key").with_base_url(base_url);
+
+        let content: String = ChatCompletion::builder("gpt-3.5-turbo", [message()])
+            .client(&client)
+            .create_stream_blocking()
+            .unwrap()
+            .filter_map(|delta| delta.unwrap().choices[0].delta.content.clone())
+            .collect();
+
+        assert_eq!(content, "Hello there!");
+    }
+}
@@ -0,0 +1,410 @@
+//! Recording API traffic to files, and replaying it without network access.
+//!
+//! A [`Cassette`] is a [`Middleware`] that either records every request and its response to a
+//! JSON file, or answers requests from a file recorded earlier. Requests are matched on their
+//! method, route and body, with JSON bodies compared regardless of key order and whitespace.
+//! Identical requests are answered in the order they were recorded.
+//!
+//! Streams are recorded once they have finished, so while recording, events arrive all at once.
+//! Cassettes are not available on `wasm32`.
+//!
+//! ```no_run
+//! use openai::cassette::Cassette;
+//! use openai::OpenAiClient;
+//!
+//! # fn example() -> openai::ApiResponseOrError<()> {
+//! // Record once against the real API...
+//! let client = OpenAiClient::new("sk-...").with_middleware(Cassette::record("tests/chat.json"));
+//! // ...then replay offline.
+//! let client = OpenAiClient::new("").with_middleware(Cassette::replay("tests/chat.json")?);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use futures_util::future::BoxFuture;
+use futures_util::lock::Mutex as AsyncMutex;
+use reqwest::{Request, Response};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::middleware::{Middleware, Next};
+use crate::runtime;
+use crate::{ApiResponseOrError, OpenAiError};
+
+/// Response headers that describe the encoding of the original body rather than its content.
+const SKIPPED_HEADERS: [&str; 3] = ["content-length", "transfer-encoding", "connection"];
+
+pub struct Cassette {
+    path: PathBuf,
+    mode: CassetteMode,
+    interactions: Mutex<Vec<Interaction>>,
+    /// Held while the file is written, so recordings are saved in order.
+    write_lock: AsyncMutex<()>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CassetteMode {
+    /// Sends requests and saves them along with their responses, replacing the file.
+    Record,
+    /// Answers requests from the file, without sending them.
+    Replay,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct CassetteFile {
+    interactions: Vec<Interaction>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Interaction {
+    request: RecordedRequest,
+    response: RecordedResponse,
+    #[serde(skip)]
+    replayed: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+struct RecordedRequest {
+    method: String,
+    /// The path and query of the url, so that recordings can be replayed against any host.
+    route: String,
+    #[serde(default, skip_serializing_if = "RecordedBody::is_empty")]
+    body: RecordedBody,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct RecordedResponse {
+    status: u16,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "RecordedBody::is_empty")]
+    body: RecordedBody,
+}
+
+/// JSON bodies are kept as JSON, so they can be compared and edited by hand.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+enum RecordedBody {
+    #[default]
+    Empty,
+    Json(Value),
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+impl Cassette {
+    /// Records requests and responses to `path`, overwriting it.
+    pub fn record(path: impl Into<PathBuf>) -> Self {
+        Cassette {
+            path: path.into(),
+            mode: CassetteMode::Record,
+            interactions: Mutex::new(Vec::new()),
+            write_lock: AsyncMutex::new(()),
+        }
+    }
+
+    /// Answers requests from the recording at `path`.
+    pub fn replay(path: impl Into<PathBuf>) -> ApiResponseOrError<Self> {
+        let path = path.into();
+        let file: CassetteFile =
+            serde_json::from_slice(&std::fs::read(&path)?).map_err(io::Error::from)?;
+        Ok(Cassette {
+            path,
+            mode: CassetteMode::Replay,
+            interactions: Mutex::new(file.interactions),
+            write_lock: AsyncMutex::new(()),
+        })
+    }
+
+    /// Replays `path` if it exists, and records to it otherwise.
+    pub fn replay_or_record(path: impl Into<PathBuf>) -> ApiResponseOrError<Self> {
+        let path = path.into();
+        match path.exists() {
+            true => Cassette::replay(path),
+            false => Ok(Cassette::record(path)),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn mode(&self) -> CassetteMode {
+        self.mode
+    }
+
+    async fn record_response(
+        &self,
+        recorded_request: RecordedRequest,
+        response: Response,
+    ) -> ApiResponseOrError<Response> {
+        let status = response.status().as_u16();
+        let headers = response
+            .headers()
+            .iter()
+            .filter(|(name, _)| !SKIPPED_HEADERS.contains(&name.as_str()))
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        let body = response.bytes().await?;
+        let recorded_response = RecordedResponse {
+            status,
+            headers,
+            body: RecordedBody::from_bytes(&body),
+        };
+        let response = self.to_response(&recorded_response)?;
+
+        let _write_lock = self.write_lock.lock().await;
+        let file = {
+            let mut interactions = self.interactions.lock().unwrap();
+            interactions.push(Interaction {
+                request: recorded_request,
+                response: recorded_response,
+                replayed: false,
+            });
+            CassetteFile {
+                interactions: interactions.clone(),
+            }
+        };
+        let contents = serde_json::to_vec_pretty(&file).map_err(io::Error::from)?;
+        runtime::write_file(&self.path, contents).await?;
+        Ok(response)
+    }
+
+    fn replay_response(&self, recorded_request: &RecordedRequest) -> ApiResponseOrError<Response> {
+        let mut interactions = self.interactions.lock().unwrap();
+        let interaction = interactions
+            .iter_mut()
+            .find(|interaction| !interaction.replayed && interaction.request == *recorded_request)
+            .ok_or_else(|| {
+                self.error(format!(
+                    "no recorded response for {} {}",
+                    recorded_request.method, recorded_request.route,
+                ))
+            })?;
+        interaction.replayed = true;
+        self.to_response(&interaction.response)
+    }
+
+    fn to_response(&self, recorded_response: &RecordedResponse) -> ApiResponseOrError<Response> {
+        recorded_response
+            .to_response()
+            .map_err(|error| self.error(format!("invalid recorded response: {error}")))
+    }
+
+    fn error(&self, message: String) -> OpenAiError {
+        OpenAiError::Cassette {
+            path: self.path.clone(),
+            message,
+        }
+    }
+}
+
+impl Middleware for Cassette {
+    fn handle<'a>(
+        &'a self,
+        request: Request,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, ApiResponseOrError<Response>> {
+        Box::pin(async move {
+            let recorded_request = RecordedRequest::from_request(&request);
+            match self.mode {
+                CassetteMode::Record => {
+                    let response = next.run(request).await?;
+                    self.record_response(recorded_request, response).await
+                }
+                CassetteMode::Replay => self.replay_response(&recorded_request),
+            }
+        })
+    }
+}
+
+impl RecordedRequest {
+    fn from_request(request: &Request) -> Self {
+        let url = request.url();
+        let route = match url.query() {
+            Some(query) => format!("{}?{query}", url.path()),
+            None => url.path().to_string(),
+        };
+        RecordedRequest {
+            method: request.method().to_string(),
+            route,
+            // Streamed bodies, such as file uploads, can't be read and are not compared.
+            body: request
+                .body()
+                .and_then(|body| body.as_bytes())
+                .map(RecordedBody::from_bytes)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl RecordedResponse {
+    fn to_response(&self) -> Result<Response, http::Error> {
+        let mut response = http::Response::builder().status(self.status);
+        for (name, value) in &self.headers {
+            response = response.header(name, value);
+        }
+        Ok(response.body(self.body.to_bytes())?.into())
+    }
+}
+
+impl RecordedBody {
+    fn is_empty(&self) -> bool {
+        *self == RecordedBody::Empty
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        if bytes.is_empty() {
+            return RecordedBody::Empty;
+        }
+        if let Ok(value) = serde_json::from_slice(bytes) {
+            return RecordedBody::Json(value);
+        }
+        match std::str::from_utf8(bytes) {
+            Ok(text) => RecordedBody::Text(text.to_string()),
+            Err(_) => RecordedBody::Bytes(bytes.to_vec()),
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            RecordedBody::Empty => Vec::new(),
+            RecordedBody::Json(value) => serde_json::to_vec(value).unwrap(),
+            RecordedBody::Text(text) => text.as_bytes().to_vec(),
+            RecordedBody::Bytes(bytes) => bytes.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::{ChatCompletion, ChatCompletionMessage};
+    use crate::models::Model;
+    use crate::tests::{http_response, serve_responses};
+    use crate::OpenAiClient;
+    use futures_util::StreamExt;
+
+    fn cassette_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "openai-cassette-{}-{name}.json",
+            std::process::id()
+        ))
+    }
+
+    fn message(content: &str) -> ChatCompletionMessage {
+        ChatCompletionMessage::user(content)
+    }
+
+    #[tokio::test]
+    async fn records_and_replays_streams() {
+        let path = cassette_path("stream");
+        let events = [
+            r#"data: {"id":"chatcmpl-1","object":"chat.completion.chunk","created":0,"model":"gpt-3.5-turbo","choices":[{"index":0,"finish_reason":null,"delta":{"role":"assistant","content":"Hello"}}]}"#,
+            r#"data: {"id":"chatcmpl-1","object":"chat.completion.chunk","created":0,"model":"gpt-3.5-turbo","choices":[{"index":0,"finish_reason":"stop","delta":{"content":" there!"}}]}"#,
+            "data: [DONE]",
+        ]
+        .join("\n\n")
+            + "\n\n";
+        let (base_url, _) = serve_responses(vec![http_response(
+            200,
+            &[
+                ("content-type", "text/event-stream"),
+                ("x-request-id", "req_1"),
+            ],
+            &events,
+        )])
+        .await;
+        let client = OpenAiClient::new("key")
+            .with_base_url(base_url)
+            .with_middleware(Cassette::record(&path));
+        let recorded = collect_stream(&client).await;
+        assert_eq!(recorded, "Hello there!");
+
+        // Replayed against a port nothing listens on.
+        let client = OpenAiClient::new("key")
+            .with_base_url("http://127.0.0.1:9/v1")
+            .with_middleware(Cassette::replay(&path).unwrap());
+        let replayed = collect_stream(&client).await;
+        assert_eq!(replayed, "Hello there!");
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    async fn collect_stream(client: &OpenAiClient) -> String {
+        let mut stream = ChatCompletion::builder("gpt-3.5-turbo", [message("Hello!")])
+            .client(client)
+            .create_delta_stream()
+            .await
+            .unwrap();
+        let mut content = String::new();
+        while let Some(delta) = stream.next().await {
+            if let Some(text) = &delta.unwrap().choices[0].delta.content {
+                content += text;
+            }
+        }
+        content
+    }
+
+    #[tokio::test]
+    async fn matches_normalized_bodies_in_order() {
+        let path = cassette_path("match");
+        std::fs::write(
+            &path,
+            r#"{
+                "interactions": [
+                    {
+                        "request": {"method": "GET", "route": "/v1/models/gpt-4"},
+                        "response": {"status": 200, "headers": {"x-request-id": "req_1"}, "body": {"json": {"id": "gpt-4", "object": "model", "created": 0, "owned_by": "openai"}}}
+                    },
+                    {
+                        "request": {"method": "GET", "route": "/v1/models/gpt-4"},
+                        "response": {"status": 404, "body": {"json": {"error": {"message": "gone", "type": "invalid_request_error", "param": null, "code": "model_not_found"}}}}
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let client = OpenAiClient::new("key")
+            .with_base_url("http://127.0.0.1:9/v1")
+            .with_middleware(Cassette::replay(&path).unwrap());
+
+        let model = Model::from_with_client(&client, "gpt-4").await.unwrap();
+        assert_eq!(model.meta.unwrap().request_id.as_deref(), Some("req_1"));
+
+        let error = Model::from_with_client(&client, "gpt-4")
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(error.code(), Some(&crate::ApiErrorCode::ModelNotFound));
+
+        let error = Model::from_with_client(&client, "gpt-4")
+            .await
+            .err()
+            .unwrap();
+        assert!(
+            matches!(&error, OpenAiError::Cassette { path: error_path, .. } if *error_path == path)
+        );
+        assert!(error
+            .to_string()
+            .ends_with("no recorded response for GET /v1/models/gpt-4"));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn normalizes_json_bodies() {
+        assert!(
+            RecordedBody::from_bytes(br#"{"b": 1, "a": [true]}"#)
+                == RecordedBody::from_bytes(br#"{"a":[true],"b":1}"#)
+        );
+        assert!(RecordedBody::from_bytes(b"data: x") == RecordedBody::Text("data: x".to_string()));
+        assert!(RecordedBody::from_bytes(&[0xff, 0x00]) == RecordedBody::Bytes(vec![0xff, 0x00]));
+        assert!(RecordedBody::from_bytes(b"") == RecordedBody::Empty);
+    }
+}
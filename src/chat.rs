@@ -1,14 +1,32 @@
 //! Given a chat conversation, the model will return a chat completion response.
 
-use super::{openai_post, ApiResponseOrError, Usage};
-use crate::openai_request_stream;
+use super::{ApiResponseOrError, Usage};
+use crate::client::{request_client, RequestHeaders};
+use crate::meta::HasResponseMeta;
+use crate::models::{model_info, ModelInfo};
+use crate::pricing::{price_table, Cost, TokenCounts};
+use crate::rate_limit::{estimate_text_tokens, TokenEstimate, TokenUsage};
+use crate::runtime::BoxStream;
+use crate::schema::{parse_arguments, schema_name, strict_schema, ArgumentsError, JsonSchema};
+use crate::ResponseMeta;
+use crate::{
+    check_budget, json_body, openai_post_metered, openai_request_stream, record_usage,
+    wait_for_rate_limit, OpenAiClient, OpenAiError,
+};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
 use derive_builder::Builder;
-use futures_util::StreamExt;
-use reqwest::Method;
-use reqwest_eventsource::{CannotCloneRequestError, Event, EventSource};
+use eventsource_stream::{Event, EventStreamError};
+use futures_util::{future, Stream, StreamExt};
+use reqwest::header::HeaderMap;
+use reqwest::{Method, StatusCode};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{Map, Value};
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::path::Path;
+#[cfg(feature = "tokio")]
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 
 /// A full chat completion.
@@ -17,6 +35,9 @@ pub type ChatCompletion = ChatCompletionGeneric<ChatCompletionChoice>;
 /// A delta chat completion, which is streamed token by token.
 pub type ChatCompletionDelta = ChatCompletionGeneric<ChatCompletionChoiceDelta>;
 
+/// The deltas of a streamed chat completion, read as they arrive.
+pub type ChatCompletionDeltaStream = BoxStream<'static, ApiResponseOrError<ChatCompletionDelta>>;
+
 #[derive(Deserialize, Clone, Debug)]
 pub struct ChatCompletionGeneric<C> {
     pub id: String,
@@ -25,6 +46,12 @@ pub struct ChatCompletionGeneric<C> {
     pub model: String,
     pub choices: Vec<C>,
     pub usage: Option<Usage>,
+    /// Fields of the response this crate doesn't know about.
+    #[serde(flatten)]
+    pub extra_fields: Map<String, Value>,
+    /// Metadata of the response this was parsed from.
+    #[serde(skip)]
+    pub meta: Option<ResponseMeta>,
 }
 
 #[derive(Deserialize, Clone, Debug)]
@@ -41,15 +68,15 @@ pub struct ChatCompletionChoiceDelta {
     pub delta: ChatCompletionMessageDelta,
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
 pub struct ChatCompletionMessage {
     /// The role of the author of this message.
     pub role: ChatCompletionMessageRole,
     /// The contents of the message
     ///
     /// This is always required for all messages, except for when ChatGPT calls
-    /// a function.
-    pub content: Option<String>,
+    /// a function. Only user messages may contain images or audio.
+    pub content: Option<ChatCompletionContent>,
     /// The name of the user in a multi-user chat
     #[serde(skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
@@ -58,6 +85,17 @@ pub struct ChatCompletionMessage {
     /// [API Reference](https://platform.openai.com/docs/api-reference/chat/create#chat/create-function_call)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub function_call: Option<ChatCompletionFunctionCall>,
+    /// The tools the assistant called, each answered by a message with the `Tool` role.
+    ///
+    /// [API Reference](https://platform.openai.com/docs/api-reference/chat/create#chat-create-messages)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ChatCompletionToolCall>>,
+    /// The id of the tool call a `Tool` message answers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+    /// The explanation of the assistant when it refuses to answer with a structured output.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refusal: Option<String>,
 }
 
 /// Same as ChatCompletionMessage, but received during a response stream.
@@ -75,6 +113,76 @@ pub struct ChatCompletionMessageDelta {
     /// [API Reference](https://platform.openai.com/docs/api-reference/chat/create#chat/create-function_call)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub function_call: Option<ChatCompletionFunctionCallDelta>,
+    /// The parts of the tool calls received in this delta, identified by their index.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ChatCompletionToolCallDelta>>,
+    /// The part of the refusal received in this delta.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refusal: Option<String>,
+}
+
+/// The content of a message: either plain text, or a list of parts mixing text with images and
+/// audio.
+///
+/// [API Reference](https://platform.openai.com/docs/api-reference/chat/create#chat-create-messages)
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum ChatCompletionContent {
+    Text(String),
+    Parts(Vec<ChatCompletionContentPart>),
+}
+
+/// A part of the content of a message.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChatCompletionContentPart {
+    Text {
+        text: String,
+    },
+    ImageUrl {
+        image_url: ChatCompletionImageUrl,
+    },
+    InputAudio {
+        input_audio: ChatCompletionInputAudio,
+    },
+}
+
+/// An image, given by a URL or a base64 data URL.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ChatCompletionImageUrl {
+    pub url: String,
+    #[serde(default)]
+    pub detail: ChatCompletionImageDetail,
+}
+
+/// The resolution the model sees an image at.
+///
+/// [Guide](https://platform.openai.com/docs/guides/vision#low-or-high-fidelity-image-understanding)
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatCompletionImageDetail {
+    /// The model picks between low and high, depending on the size of the image.
+    #[default]
+    Auto,
+    /// A 512x512 version of the image, for a fixed 85 tokens.
+    Low,
+    /// The image in 512x512 tiles, each costing 170 more tokens.
+    High,
+}
+
+/// Base64 encoded audio.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ChatCompletionInputAudio {
+    /// The base64 encoded bytes of the audio file.
+    pub data: String,
+    pub format: ChatCompletionAudioFormat,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatCompletionAudioFormat {
+    Wav,
+    Mp3,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -101,7 +209,7 @@ pub struct ChatCompletionFunctionCall {
 }
 
 /// Same as ChatCompletionFunctionCall, but received during a response stream.
-#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Default)]
 pub struct ChatCompletionFunctionCallDelta {
     /// The name of the function ChatGPT called
     pub name: Option<String>,
@@ -110,25 +218,138 @@ pub struct ChatCompletionFunctionCallDelta {
     pub arguments: Option<String>,
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+/// A tool the model may call.
+///
+/// [API Reference](https://platform.openai.com/docs/api-reference/chat/create#chat-create-tools)
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ChatCompletionTool {
+    Function {
+        function: ChatCompletionFunctionDefinition,
+    },
+}
+
+/// The type of a tool call. Only functions are supported.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatCompletionToolType {
+    #[default]
+    Function,
+}
+
+/// Controls which tool, if any, the model calls.
+///
+/// [API Reference](https://platform.openai.com/docs/api-reference/chat/create#chat-create-tool_choice)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChatCompletionToolChoice {
+    /// The model picks between replying and calling tools. The default when tools are present.
+    Auto,
+    /// The model replies without calling a tool. The default when no tools are present.
+    None,
+    /// The model calls one or more tools.
+    Required,
+    /// The model calls the function with this name.
+    Function(String),
+}
+
+/// A tool call made by the assistant.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ChatCompletionToolCall {
+    /// The id of the call, repeated as the `tool_call_id` of the message with its result.
+    pub id: String,
+    #[serde(rename = "type", default)]
+    pub tool_type: ChatCompletionToolType,
+    /// The function the assistant called.
+    pub function: ChatCompletionFunctionCall,
+}
+
+/// Same as ChatCompletionToolCall, but received during a response stream.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ChatCompletionToolCallDelta {
+    /// The position of the call among the tool calls of the message.
+    pub index: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub tool_type: Option<ChatCompletionToolType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub function: Option<ChatCompletionFunctionCallDelta>,
+}
+
+/// Options of a streamed chat completion.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChatCompletionStreamOptions {
+    /// Sends the usage of the request in a last chunk, whose `choices` are empty.
+    pub include_usage: bool,
+}
+
+/// The format of the model's output.
+///
+/// [API Reference](https://platform.openai.com/docs/api-reference/chat/create#chat-create-response_format)
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChatCompletionResponseFormat {
+    /// Plain text, the default.
+    Text,
+    /// Any valid JSON object. The messages must ask for JSON too.
+    JsonObject,
+    /// JSON matching a schema.
+    JsonSchema {
+        json_schema: ChatCompletionJsonSchema,
+    },
+}
+
+/// The schema of a [`ChatCompletionResponseFormat::JsonSchema`] output.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ChatCompletionJsonSchema {
+    /// The name of the format, made of letters, digits, underscores and dashes.
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// The JSON Schema of the output.
+    pub schema: Value,
+    /// Whether the output must follow the schema exactly, which only supports a subset of JSON
+    /// Schema. See [`strict_schema`](crate::schema::strict_schema).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
+}
+
+impl ChatCompletionResponseFormat {
+    /// A strict JSON Schema format, with the schema of `T`.
+    pub fn json_schema<T: JsonSchema + ?Sized>() -> Self {
+        ChatCompletionResponseFormat::JsonSchema {
+            json_schema: ChatCompletionJsonSchema {
+                name: schema_name::<T>(),
+                description: None,
+                schema: strict_schema(T::json_schema()),
+                strict: Some(true),
+            },
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum ChatCompletionMessageRole {
     System,
+    #[default]
     User,
     Assistant,
     Function,
+    Tool,
 }
 
 #[derive(Serialize, Builder, Debug, Clone)]
 #[builder(pattern = "owned")]
 #[builder(name = "ChatCompletionBuilder")]
+#[builder(build_fn(error = "OpenAiError", validate = "Self::validate"))]
 #[builder(setter(strip_option, into))]
 pub struct ChatCompletionRequest {
     /// ID of the model to use. Currently, only `gpt-3.5-turbo`, `gpt-3.5-turbo-0301` and `gpt-4`
     /// are supported.
     model: String,
     /// The messages to generate chat completions for, in the [chat format](https://platform.openai.com/docs/guides/chat/introduction).
-    messages: Vec<ChatCompletionMessage>,
+    pub(crate) messages: Vec<ChatCompletionMessage>,
     /// What sampling temperature to use, between 0 and 2. Higher values like 0.8 will make the output more random, while lower values like 0.2 will make it more focused and deterministic.
     ///
     /// We generally recommend altering this or `top_p` but not both.
@@ -148,6 +369,12 @@ pub struct ChatCompletionRequest {
     #[builder(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     stream: Option<bool>,
+    /// Options of a streamed response. When the client has a
+    /// [`UsageTracker`](crate::accounting::UsageTracker), streams include their usage
+    /// unless this is set.
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<ChatCompletionStreamOptions>,
     /// Up to 4 sequences where the API will stop generating further tokens.
     #[builder(default)]
     #[serde(skip_serializing_if = "Vec::is_empty")]
@@ -203,8 +430,43 @@ pub struct ChatCompletionRequest {
     #[builder(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     function_call: Option<Value>,
+    /// The tools the model may call. Replaces `functions` on newer models.
+    ///
+    /// [API Reference](https://platform.openai.com/docs/api-reference/chat/create#chat-create-tools)
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<ChatCompletionTool>,
+    /// Controls which tool, if any, the model calls.
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<ChatCompletionToolChoice>,
+    /// Whether the model may call several tools in one reply. Defaults to `true`.
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parallel_tool_calls: Option<bool>,
+    /// The format of the output, such as JSON matching a schema.
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<ChatCompletionResponseFormat>,
+    /// The client to send the request with. Uses the default client if not set.
+    #[builder(default)]
+    #[serde(skip)]
+    client: Option<OpenAiClient>,
+    /// Headers sent with this request, replacing the client's headers of the same name.
+    #[serde(skip)]
+    #[builder(
+        setter(custom),
+        field(type = "RequestHeaders", build = "self.headers.build()?")
+    )]
+    headers: HeaderMap,
+    /// Extra parameters sent in the body of the request.
+    #[serde(flatten)]
+    #[builder(default, setter(custom))]
+    extra_body: Map<String, Value>,
 }
 
+request_builder_setters!(ChatCompletionBuilder);
+
 impl<C> ChatCompletionGeneric<C> {
     pub fn builder(
         model: &str,
@@ -214,25 +476,118 @@ impl<C> ChatCompletionGeneric<C> {
             .model(model)
             .messages(messages)
     }
+
+    /// The cost of the completion with the prices of
+    /// [`price_table`](crate::pricing::price_table), or `None` if the model has no price or the
+    /// usage wasn't reported.
+    pub fn cost(&self) -> Option<Cost> {
+        price_table().cost(&self.model, self.usage?.into())
+    }
 }
 
 impl ChatCompletion {
     pub async fn create(request: &ChatCompletionRequest) -> ApiResponseOrError<Self> {
-        openai_post("chat/completions", request).await
+        let client = request_client(&request.client, &request.headers);
+        let route = client.model_route(&request.model, "chat/completions");
+        openai_post_metered(&client, "chat/completions", &route, request).await
+    }
+}
+
+impl ChatCompletion {
+    /// Parses the content of the first choice as JSON, such as a structured output.
+    ///
+    /// Fails with [`OpenAiError::Refusal`] if the model refused to answer, and with
+    /// [`OpenAiError::Truncated`] if the output was cut off.
+    pub fn parse_content<T: DeserializeOwned>(&self) -> ApiResponseOrError<T> {
+        let choice = self.choices.first();
+        let message = choice.map(|choice| &choice.message);
+        if let Some(refusal) = message.and_then(|message| message.refusal.clone()) {
+            return Err(OpenAiError::Refusal(refusal));
+        }
+        let content = message
+            .and_then(|message| message.content.as_ref())
+            .map(|content| content.to_text().into_owned())
+            .unwrap_or_default();
+        if let Some(choice) = choice
+            .filter(|choice| matches!(choice.finish_reason.as_str(), "length" | "content_filter"))
+        {
+            return Err(OpenAiError::Truncated {
+                finish_reason: choice.finish_reason.clone(),
+                content,
+            });
+        }
+        serde_json::from_str(&content).map_err(|source| OpenAiError::Decode {
+            status: self
+                .meta
+                .as_ref()
+                .map_or(StatusCode::OK, |meta| meta.status),
+            body: content,
+            source,
+        })
     }
 }
 
 impl ChatCompletionDelta {
+    /// Streams the deltas of a chat completion into a channel, from a spawned tokio task.
+    /// An error ending the stream is sent as its last item.
+    #[cfg(feature = "tokio")]
     pub async fn create(
         request: &ChatCompletionRequest,
-    ) -> Result<Receiver<Self>, CannotCloneRequestError> {
-        let stream =
-            openai_request_stream(Method::POST, "chat/completions", |r| r.json(request)).await?;
-        let (tx, rx) = channel::<Self>(32);
-        tokio::spawn(forward_deserialized_chat_response_stream(stream, tx));
+    ) -> ApiResponseOrError<Receiver<ApiResponseOrError<Self>>> {
+        let deltas = ChatCompletionDelta::create_delta_stream(request).await?;
+        let (tx, rx) = channel(32);
+        tokio::spawn(forward_chat_completion_deltas(deltas, tx));
         Ok(rx)
     }
 
+    /// Streams the deltas of a chat completion without spawning a task,
+    /// so it can be used with any executor.
+    pub async fn create_delta_stream(
+        request: &ChatCompletionRequest,
+    ) -> ApiResponseOrError<ChatCompletionDeltaStream> {
+        let client = request_client(&request.client, &request.headers);
+        check_budget(&client)?;
+        let mut reservation = wait_for_rate_limit(&client, request).await;
+        let route = client.model_route(&request.model, "chat/completions");
+        let mut body = json_body(request)?;
+        if client.usage_tracker().is_some() && body.get("stream_options").is_none() {
+            body["stream_options"] = serde_json::to_value(ChatCompletionStreamOptions {
+                include_usage: true,
+            })
+            .expect("stream options serialize");
+        }
+        let (meta, events) =
+            openai_request_stream(&client, Method::POST, &route, |r| r.json(&body)).await?;
+        let model = request.model.clone();
+        // The usage is only sent when requested, with the last chunk, which it is when the client
+        // tracks usage. The reservation moves into the stream, so it is settled when the stream
+        // ends or is dropped.
+        let deltas = deserialize_chat_response_stream(meta, events).inspect(move |delta| {
+            let Ok(delta) = delta else {
+                return;
+            };
+            match delta.token_counts() {
+                Some(tokens) => {
+                    let model = if delta.model.is_empty() {
+                        &model
+                    } else {
+                        &delta.model
+                    };
+                    record_usage(&client, model, "chat/completions", tokens);
+                    if let Some(reservation) = &mut reservation {
+                        reservation.set_used(tokens.input + tokens.output);
+                    }
+                }
+                None => {
+                    if let Some(reservation) = &mut reservation {
+                        reservation.count_chunk(delta.choices.len());
+                    }
+                }
+            }
+        });
+        Ok(Box::pin(deltas))
+    }
+
     /// Merges the input delta completion into `self`.
     pub fn merge(
         &mut self,
@@ -249,6 +604,12 @@ impl ChatCompletionDelta {
                 choice.merge(other_choice)?;
             }
         }
+        if other.usage.is_some() {
+            self.usage = other.usage;
+        }
+        for (name, value) in other.extra_fields {
+            self.extra_fields.entry(name).or_insert(value);
+        }
         Ok(())
     }
 }
@@ -267,6 +628,9 @@ impl ChatCompletionChoiceDelta {
                 self.delta.role = Some(other_role);
             }
         }
+        if other.finish_reason.is_some() {
+            self.finish_reason = other.finish_reason.clone();
+        }
         if self.delta.name.is_none() {
             if let Some(other_name) = &other.delta.name {
                 // Set name to other_name.
@@ -276,21 +640,15 @@ impl ChatCompletionChoiceDelta {
         // Merge contents.
         match self.delta.content.as_mut() {
             Some(content) => {
-                match &other.delta.content {
-                    Some(other_content) => {
-                        // Push other content into this one.
-                        content.push_str(other_content)
-                    }
-                    None => {}
+                if let Some(other_content) = &other.delta.content {
+                    // Push other content into this one.
+                    content.push_str(other_content)
                 }
             }
             None => {
-                match &other.delta.content {
-                    Some(other_content) => {
-                        // Set this content to other content.
-                        self.delta.content = Some(other_content.clone());
-                    }
-                    None => {}
+                if let Some(other_content) = &other.delta.content {
+                    // Set this content to other content.
+                    self.delta.content = Some(other_content.clone());
                 }
             }
         };
@@ -300,36 +658,388 @@ impl ChatCompletionChoiceDelta {
         // arguments are merged by concatenating them
         match self.delta.function_call.as_mut() {
             Some(function_call) => {
-                match &other.delta.function_call {
-                    Some(other_function_call) => {
-                        // push the arguments string of the other function call into this one
-                        match (&mut function_call.arguments, &other_function_call.arguments) {
-                            (Some(function_call), Some(other_function_call)) => {
-                                function_call.push_str(&other_function_call);
-                            }
-                            (None, Some(other_function_call)) => {
-                                function_call.arguments = Some(other_function_call.clone());
-                            }
-                            _ => {}
+                if let Some(other_function_call) = &other.delta.function_call {
+                    // push the arguments string of the other function call into this one
+                    match (&mut function_call.arguments, &other_function_call.arguments) {
+                        (Some(function_call), Some(other_function_call)) => {
+                            function_call.push_str(other_function_call);
+                        }
+                        (None, Some(other_function_call)) => {
+                            function_call.arguments = Some(other_function_call.clone());
                         }
+                        _ => {}
                     }
-                    None => {}
                 }
             }
             None => {
-                match &other.delta.function_call {
-                    Some(other_function_call) => {
-                        // Set this content to other content.
-                        self.delta.function_call = Some(other_function_call.clone());
-                    }
-                    None => {}
+                if let Some(other_function_call) = &other.delta.function_call {
+                    // Set this content to other content.
+                    self.delta.function_call = Some(other_function_call.clone());
                 }
             }
         };
+
+        if let Some(other_refusal) = &other.delta.refusal {
+            self.delta
+                .refusal
+                .get_or_insert_with(String::new)
+                .push_str(other_refusal);
+        }
+
+        // Tool calls are streamed in parts, each naming the index of the call it belongs to.
+        if let Some(other_tool_calls) = &other.delta.tool_calls {
+            let tool_calls = self.delta.tool_calls.get_or_insert_with(Vec::new);
+            for other_tool_call in other_tool_calls {
+                match tool_calls
+                    .iter_mut()
+                    .find(|tool_call| tool_call.index == other_tool_call.index)
+                {
+                    Some(tool_call) => tool_call.merge(other_tool_call),
+                    None => tool_calls.push(other_tool_call.clone()),
+                }
+            }
+        }
         Ok(())
     }
 }
 
+impl ChatCompletionMessage {
+    /// A message from the system, instructing the assistant.
+    pub fn system(content: impl Into<ChatCompletionContent>) -> Self {
+        Self::with_content(ChatCompletionMessageRole::System, content)
+    }
+
+    /// A message from the user.
+    pub fn user(content: impl Into<ChatCompletionContent>) -> Self {
+        Self::with_content(ChatCompletionMessageRole::User, content)
+    }
+
+    /// A message from the assistant, such as one of its earlier replies.
+    pub fn assistant(content: impl Into<ChatCompletionContent>) -> Self {
+        Self::with_content(ChatCompletionMessageRole::Assistant, content)
+    }
+
+    /// The result of the tool call with the id `tool_call_id`.
+    pub fn tool(
+        tool_call_id: impl Into<String>,
+        content: impl Into<ChatCompletionContent>,
+    ) -> Self {
+        ChatCompletionMessage {
+            tool_call_id: Some(tool_call_id.into()),
+            ..Self::with_content(ChatCompletionMessageRole::Tool, content)
+        }
+    }
+
+    fn with_content(
+        role: ChatCompletionMessageRole,
+        content: impl Into<ChatCompletionContent>,
+    ) -> Self {
+        ChatCompletionMessage {
+            role,
+            content: Some(content.into()),
+            ..Default::default()
+        }
+    }
+
+    /// The text content of the message, or `None` if it has no content or its content is parts.
+    pub fn text(&self) -> Option<&str> {
+        self.content
+            .as_ref()
+            .and_then(ChatCompletionContent::as_text)
+    }
+
+    /// The text the model reads: the content, or the text parts joined by newlines.
+    pub(crate) fn prompt_text(&self) -> Cow<'_, str> {
+        self.content
+            .as_ref()
+            .map(ChatCompletionContent::to_text)
+            .unwrap_or_default()
+    }
+
+    /// The tokens taken by the images of the message. The size of the images isn't read, so
+    /// images not in low detail are counted as 1024x1024 images: 4 tiles and the base 85 tokens.
+    /// Audio isn't counted.
+    pub(crate) fn image_tokens(&self) -> u32 {
+        let Some(ChatCompletionContent::Parts(parts)) = &self.content else {
+            return 0;
+        };
+        parts
+            .iter()
+            .map(|part| match part {
+                ChatCompletionContentPart::ImageUrl { image_url } => match image_url.detail {
+                    ChatCompletionImageDetail::Low => 85,
+                    ChatCompletionImageDetail::Auto | ChatCompletionImageDetail::High => {
+                        85 + 4 * 170
+                    }
+                },
+                _ => 0,
+            })
+            .sum()
+    }
+}
+
+impl ChatCompletionContent {
+    /// The content, if it is text rather than parts.
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            ChatCompletionContent::Text(text) => Some(text),
+            ChatCompletionContent::Parts(_) => None,
+        }
+    }
+
+    /// The text of the content, with text parts joined by newlines and other parts left out.
+    pub fn to_text(&self) -> Cow<'_, str> {
+        match self {
+            ChatCompletionContent::Text(text) => Cow::Borrowed(text),
+            ChatCompletionContent::Parts(parts) => Cow::Owned(
+                parts
+                    .iter()
+                    .filter_map(|part| match part {
+                        ChatCompletionContentPart::Text { text } => Some(text.as_str()),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            ),
+        }
+    }
+}
+
+impl From<String> for ChatCompletionContent {
+    fn from(text: String) -> Self {
+        ChatCompletionContent::Text(text)
+    }
+}
+
+impl From<&str> for ChatCompletionContent {
+    fn from(text: &str) -> Self {
+        ChatCompletionContent::Text(text.to_string())
+    }
+}
+
+impl From<Vec<ChatCompletionContentPart>> for ChatCompletionContent {
+    fn from(parts: Vec<ChatCompletionContentPart>) -> Self {
+        ChatCompletionContent::Parts(parts)
+    }
+}
+
+impl ChatCompletionContentPart {
+    /// A text part.
+    pub fn text(text: impl Into<String>) -> Self {
+        ChatCompletionContentPart::Text { text: text.into() }
+    }
+
+    /// An image at the given URL, which may be a base64 data URL.
+    pub fn image_url(url: impl Into<String>, detail: ChatCompletionImageDetail) -> Self {
+        ChatCompletionContentPart::ImageUrl {
+            image_url: ChatCompletionImageUrl {
+                url: url.into(),
+                detail,
+            },
+        }
+    }
+
+    /// An image read from a local PNG, JPEG, GIF or WebP file, sent as a base64 data URL.
+    pub fn image_file(
+        path: impl AsRef<Path>,
+        detail: ChatCompletionImageDetail,
+    ) -> ApiResponseOrError<Self> {
+        let path = path.as_ref();
+        let mime_type = match extension(path).as_str() {
+            "png" => "image/png",
+            "jpg" | "jpeg" => "image/jpeg",
+            "gif" => "image/gif",
+            "webp" => "image/webp",
+            _ => {
+                return Err(OpenAiError::Validation(format!(
+                    "{} is not a PNG, JPEG, GIF or WebP image",
+                    path.display()
+                )))
+            }
+        };
+        let data = BASE64.encode(std::fs::read(path)?);
+        Ok(ChatCompletionContentPart::image_url(
+            format!("data:{mime_type};base64,{data}"),
+            detail,
+        ))
+    }
+
+    /// Audio in the given format.
+    pub fn input_audio(bytes: impl AsRef<[u8]>, format: ChatCompletionAudioFormat) -> Self {
+        ChatCompletionContentPart::InputAudio {
+            input_audio: ChatCompletionInputAudio {
+                data: BASE64.encode(bytes),
+                format,
+            },
+        }
+    }
+
+    /// Audio read from a local WAV or MP3 file.
+    pub fn audio_file(path: impl AsRef<Path>) -> ApiResponseOrError<Self> {
+        let path = path.as_ref();
+        let format = match extension(path).as_str() {
+            "wav" => ChatCompletionAudioFormat::Wav,
+            "mp3" => ChatCompletionAudioFormat::Mp3,
+            _ => {
+                return Err(OpenAiError::Validation(format!(
+                    "{} is not a WAV or MP3 file",
+                    path.display()
+                )))
+            }
+        };
+        Ok(ChatCompletionContentPart::input_audio(
+            std::fs::read(path)?,
+            format,
+        ))
+    }
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+}
+
+impl ChatCompletionFunctionCall {
+    /// Parses the JSON arguments of the call, with the path of the value in error if they don't
+    /// match `T`. See
+    /// [`FunctionArguments::from_call`](crate::schema::FunctionArguments::from_call) to also check the name of the function.
+    pub fn parse_arguments<T: DeserializeOwned>(&self) -> Result<T, ArgumentsError> {
+        parse_arguments(&self.name, &self.arguments)
+    }
+}
+
+impl ChatCompletionTool {
+    /// A tool calling the given function.
+    pub fn function(function: ChatCompletionFunctionDefinition) -> Self {
+        ChatCompletionTool::Function { function }
+    }
+}
+
+impl From<ChatCompletionFunctionDefinition> for ChatCompletionTool {
+    fn from(function: ChatCompletionFunctionDefinition) -> Self {
+        ChatCompletionTool::function(function)
+    }
+}
+
+impl ChatCompletionToolChoice {
+    /// Forces the model to call the function with this name.
+    pub fn function(name: impl Into<String>) -> Self {
+        ChatCompletionToolChoice::Function(name.into())
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum ToolChoiceRepr {
+    Mode(String),
+    Named {
+        #[serde(rename = "type")]
+        tool_type: ChatCompletionToolType,
+        function: NamedFunction,
+    },
+}
+
+#[derive(Deserialize, Serialize)]
+struct NamedFunction {
+    name: String,
+}
+
+impl Serialize for ChatCompletionToolChoice {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let repr = match self {
+            ChatCompletionToolChoice::Auto => ToolChoiceRepr::Mode("auto".to_string()),
+            ChatCompletionToolChoice::None => ToolChoiceRepr::Mode("none".to_string()),
+            ChatCompletionToolChoice::Required => ToolChoiceRepr::Mode("required".to_string()),
+            ChatCompletionToolChoice::Function(name) => ToolChoiceRepr::Named {
+                tool_type: ChatCompletionToolType::Function,
+                function: NamedFunction { name: name.clone() },
+            },
+        };
+        repr.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ChatCompletionToolChoice {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match ToolChoiceRepr::deserialize(deserializer)? {
+            ToolChoiceRepr::Mode(mode) => match mode.as_str() {
+                "auto" => Ok(ChatCompletionToolChoice::Auto),
+                "none" => Ok(ChatCompletionToolChoice::None),
+                "required" => Ok(ChatCompletionToolChoice::Required),
+                _ => Err(serde::de::Error::unknown_variant(
+                    &mode,
+                    &["auto", "none", "required"],
+                )),
+            },
+            ToolChoiceRepr::Named { function, .. } => {
+                Ok(ChatCompletionToolChoice::Function(function.name))
+            }
+        }
+    }
+}
+
+impl ChatCompletionToolCall {
+    /// A call of the function `name` with the JSON `arguments`.
+    pub fn function(
+        id: impl Into<String>,
+        name: impl Into<String>,
+        arguments: impl Into<String>,
+    ) -> Self {
+        ChatCompletionToolCall {
+            id: id.into(),
+            tool_type: ChatCompletionToolType::Function,
+            function: ChatCompletionFunctionCall {
+                name: name.into(),
+                arguments: arguments.into(),
+            },
+        }
+    }
+}
+
+impl ChatCompletionToolCallDelta {
+    /// Merges a later part of the same tool call into `self`.
+    /// The id, type and function name are sent once, while the arguments are concatenated.
+    fn merge(&mut self, other: &ChatCompletionToolCallDelta) {
+        if self.id.is_none() {
+            self.id = other.id.clone();
+        }
+        if self.tool_type.is_none() {
+            self.tool_type = other.tool_type;
+        }
+        let Some(other_function) = &other.function else {
+            return;
+        };
+        let function = self.function.get_or_insert_with(Default::default);
+        if function.name.is_none() {
+            function.name = other_function.name.clone();
+        }
+        if let Some(other_arguments) = &other_function.arguments {
+            function
+                .arguments
+                .get_or_insert_with(String::new)
+                .push_str(other_arguments);
+        }
+    }
+}
+
+impl From<ChatCompletionToolCallDelta> for ChatCompletionToolCall {
+    fn from(delta: ChatCompletionToolCallDelta) -> Self {
+        ChatCompletionToolCall {
+            id: delta.id.unwrap_or_default(),
+            tool_type: delta.tool_type.unwrap_or_default(),
+            function: delta
+                .function
+                .map(ChatCompletionFunctionCall::from)
+                .unwrap_or(ChatCompletionFunctionCall {
+                    name: String::new(),
+                    arguments: String::new(),
+                }),
+        }
+    }
+}
+
 impl From<ChatCompletionDelta> for ChatCompletion {
     fn from(delta: ChatCompletionDelta) -> Self {
         ChatCompletion {
@@ -338,6 +1048,8 @@ impl From<ChatCompletionDelta> for ChatCompletion {
             created: delta.created,
             model: delta.model,
             usage: delta.usage,
+            extra_fields: delta.extra_fields,
+            meta: delta.meta,
             choices: delta
                 .choices
                 .iter()
@@ -348,10 +1060,16 @@ impl From<ChatCompletionDelta> for ChatCompletion {
                         role: choice
                             .delta
                             .role
-                            .unwrap_or_else(|| ChatCompletionMessageRole::System),
-                        content: choice.delta.content.clone(),
+                            .unwrap_or(ChatCompletionMessageRole::System),
+                        content: choice.delta.content.clone().map(Into::into),
                         name: choice.delta.name.clone(),
                         function_call: choice.delta.function_call.clone().map(|f| f.into()),
+                        tool_calls: choice.delta.tool_calls.clone().map(|mut tool_calls| {
+                            tool_calls.sort_by_key(|tool_call| tool_call.index);
+                            tool_calls.into_iter().map(Into::into).collect()
+                        }),
+                        tool_call_id: None,
+                        refusal: choice.delta.refusal.clone(),
                     },
                 })
                 .collect(),
@@ -375,33 +1093,256 @@ pub enum ChatCompletionDeltaMergeError {
     FunctionCallArgumentTypeMismatch,
 }
 
-async fn forward_deserialized_chat_response_stream(
-    mut stream: EventSource,
-    tx: Sender<ChatCompletionDelta>,
-) -> anyhow::Result<()> {
-    while let Some(event) = stream.next().await {
-        let event = event?;
-        match event {
-            Event::Message(event) => {
-                let completion = serde_json::from_str::<ChatCompletionDelta>(&event.data)?;
-                tx.send(completion).await?;
-            }
-            _ => {}
+/// Deserializes deltas from the event stream until `[DONE]`,
+/// attaching the response metadata to the first one.
+fn deserialize_chat_response_stream(
+    meta: ResponseMeta,
+    events: impl Stream<Item = Result<Event, EventStreamError<OpenAiError>>>,
+) -> impl Stream<Item = ApiResponseOrError<ChatCompletionDelta>> {
+    let status = meta.status;
+    let mut meta = Some(meta);
+    events
+        .take_while(|event| future::ready(!matches!(event, Ok(event) if event.data == "[DONE]")))
+        .map(move |event| {
+            let event = event.map_err(|error| match error {
+                EventStreamError::Transport(error) => error,
+                error => OpenAiError::MalformedStream(error.to_string()),
+            })?;
+            let mut completion =
+                serde_json::from_str::<ChatCompletionDelta>(&event.data).map_err(|source| {
+                    OpenAiError::Decode {
+                        status,
+                        body: event.data,
+                        source,
+                    }
+                })?;
+            completion.meta = meta.take();
+            Ok(completion)
+        })
+}
+
+/// Forwards deltas into the channel, until the stream ends or fails with a forwarded error.
+#[cfg(feature = "tokio")]
+async fn forward_chat_completion_deltas(
+    mut deltas: ChatCompletionDeltaStream,
+    tx: Sender<ApiResponseOrError<ChatCompletionDelta>>,
+) {
+    while let Some(delta) = deltas.next().await {
+        let failed = delta.is_err();
+        if tx.send(delta).await.is_err() || failed {
+            break;
         }
     }
-    Ok(())
+}
+
+#[cfg(feature = "tokenizer")]
+impl ChatCompletionRequest {
+    /// The number of prompt tokens of the request, including the formatting of messages and
+    /// function definitions, or `None` if the model's encoding isn't known. Text is counted
+    /// exactly, but the count of messages whose content is [`Parts`](ChatCompletionContent::Parts)
+    /// is an estimate: images take a fixed number of tokens for their detail and audio isn't
+    /// counted.
+    pub fn count_prompt_tokens(&self) -> Option<usize> {
+        let encoding = crate::tokenizer::Encoding::for_model(&self.model)?;
+        let (functions, function_call) = prompt_functions(
+            &self.functions,
+            &self.tools,
+            self.function_call.as_ref(),
+            self.tool_choice.as_ref(),
+        );
+        Some(crate::tokenizer::count_chat_prompt(
+            encoding,
+            &self.messages,
+            &functions,
+            function_call.as_ref(),
+        ))
+    }
+}
+
+/// The functions shown to the model, from both `functions` and `tools`, and how it is told to
+/// call them, in the form of the `function_call` parameter.
+#[cfg(feature = "tokenizer")]
+fn prompt_functions(
+    functions: &[ChatCompletionFunctionDefinition],
+    tools: &[ChatCompletionTool],
+    function_call: Option<&Value>,
+    tool_choice: Option<&ChatCompletionToolChoice>,
+) -> (Vec<ChatCompletionFunctionDefinition>, Option<Value>) {
+    let tool_functions = tools.iter().map(|tool| match tool {
+        ChatCompletionTool::Function { function } => function.clone(),
+    });
+    let functions = functions.iter().cloned().chain(tool_functions).collect();
+    let function_call = function_call.cloned().or_else(|| match tool_choice? {
+        ChatCompletionToolChoice::None => Some(Value::from("none")),
+        ChatCompletionToolChoice::Function(name) => Some(serde_json::json!({ "name": name })),
+        ChatCompletionToolChoice::Auto | ChatCompletionToolChoice::Required => None,
+    });
+    (functions, function_call)
 }
 
 impl ChatCompletionBuilder {
+    /// Checks the request against the model's entry in the
+    /// [`model_registry`](crate::models::model_registry), if it has one.
+    fn validate(&self) -> Result<(), String> {
+        let has_tools = self.tools.as_ref().is_some_and(|tools| !tools.is_empty());
+        if !has_tools {
+            if self.tool_choice.as_ref().is_some_and(Option::is_some) {
+                return Err("tool_choice requires tools".to_string());
+            }
+            if self.parallel_tool_calls.flatten().is_some() {
+                return Err("parallel_tool_calls requires tools".to_string());
+            }
+        }
+        if let Some(Some(ChatCompletionResponseFormat::JsonSchema { json_schema })) =
+            &self.response_format
+        {
+            if json_schema.schema.get("type") != Some(&Value::from("object")) {
+                return Err(format!(
+                    "the schema of the {} response format must be an object at its root",
+                    json_schema.name
+                ));
+            }
+        }
+        let Some((model, info)) = self
+            .model
+            .as_deref()
+            .and_then(|model| Some((model, model_info(model)?)))
+        else {
+            return Ok(());
+        };
+        let max_tokens = self.max_tokens.flatten();
+        if let Some(max_tokens) = max_tokens {
+            info.check_max_tokens(model, max_tokens)?;
+        }
+        if self
+            .functions
+            .as_ref()
+            .is_some_and(|functions| !functions.is_empty())
+        {
+            ModelInfo::check_support(model, info.supports_tools, "function calling")?;
+        }
+        if has_tools {
+            ModelInfo::check_support(model, info.supports_tools, "tools")?;
+        }
+        match &self.response_format {
+            Some(Some(ChatCompletionResponseFormat::JsonObject)) => {
+                ModelInfo::check_support(model, info.supports_json_mode, "JSON outputs")?;
+            }
+            Some(Some(ChatCompletionResponseFormat::JsonSchema { .. })) => {
+                ModelInfo::check_support(
+                    model,
+                    info.supports_structured_outputs,
+                    "structured outputs",
+                )?;
+            }
+            _ => {}
+        }
+        if self.stream.flatten() == Some(true) {
+            ModelInfo::check_support(model, info.supports_streaming, "streaming")?;
+        }
+        let has_images = self.messages.iter().flatten().any(|message| {
+            matches!(&message.content, Some(ChatCompletionContent::Parts(parts)) if parts
+                .iter()
+                .any(|part| matches!(part, ChatCompletionContentPart::ImageUrl { .. })))
+        });
+        if has_images {
+            ModelInfo::check_support(model, info.supports_vision, "images")?;
+        }
+        #[cfg(feature = "tokenizer")]
+        if let (Some(encoding), Some(messages)) = (info.encoding, &self.messages) {
+            let (functions, function_call) = prompt_functions(
+                self.functions.as_deref().unwrap_or_default(),
+                self.tools.as_deref().unwrap_or_default(),
+                self.function_call.as_ref().and_then(Option::as_ref),
+                self.tool_choice.as_ref().and_then(Option::as_ref),
+            );
+            let prompt_tokens = crate::tokenizer::count_chat_prompt(
+                encoding,
+                messages,
+                &functions,
+                function_call.as_ref(),
+            );
+            // Images and audio are only estimated, so a request with them isn't rejected on its count.
+            if !messages
+                .iter()
+                .any(|message| matches!(message.content, Some(ChatCompletionContent::Parts(_))))
+            {
+                info.check_context_window(model, prompt_tokens as u64, max_tokens.unwrap_or(0))?;
+            }
+        }
+        Ok(())
+    }
+
     pub async fn create(self) -> ApiResponseOrError<ChatCompletion> {
-        ChatCompletion::create(&self.build().unwrap()).await
+        ChatCompletion::create(&self.build()?).await
+    }
+
+    /// Asks for a structured output with the schema of `T`, and parses it with
+    /// [`ChatCompletion::parse_content`]. The schema of `T` must be an object, such as that of a
+    /// struct, or the request fails without being sent.
+    pub async fn create_typed<T: JsonSchema + DeserializeOwned>(self) -> ApiResponseOrError<T> {
+        self.response_format(ChatCompletionResponseFormat::json_schema::<T>())
+            .create()
+            .await?
+            .parse_content()
     }
 
+    #[cfg(feature = "tokio")]
     pub async fn create_stream(
         mut self,
-    ) -> Result<Receiver<ChatCompletionDelta>, CannotCloneRequestError> {
+    ) -> ApiResponseOrError<Receiver<ApiResponseOrError<ChatCompletionDelta>>> {
         self.stream = Some(Some(true));
-        ChatCompletionDelta::create(&self.build().unwrap()).await
+        ChatCompletionDelta::create(&self.build()?).await
+    }
+
+    /// Like [`create_stream`](ChatCompletionBuilder::create_stream), but returns the deltas as a
+    /// [`Stream`] without spawning a task, so it can be used with any executor.
+    pub async fn create_delta_stream(mut self) -> ApiResponseOrError<ChatCompletionDeltaStream> {
+        self.stream = Some(Some(true));
+        ChatCompletionDelta::create_delta_stream(&self.build()?).await
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl ChatCompletion {
+    /// Blocking version of [`ChatCompletion::create`].
+    pub fn create_blocking(request: &ChatCompletionRequest) -> ApiResponseOrError<Self> {
+        crate::blocking::block_on(ChatCompletion::create(request))
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl ChatCompletionBuilder {
+    /// Blocking version of [`ChatCompletionBuilder::create`].
+    pub fn create_blocking(self) -> ApiResponseOrError<ChatCompletion> {
+        crate::blocking::block_on(self.create())
+    }
+
+    /// Blocking version of [`ChatCompletionBuilder::create_typed`].
+    pub fn create_typed_blocking<T: JsonSchema + DeserializeOwned>(self) -> ApiResponseOrError<T> {
+        crate::blocking::block_on(self.create_typed())
+    }
+
+    /// Blocking version of [`ChatCompletionBuilder::create_stream`],
+    /// returning an iterator over the streamed deltas.
+    pub fn create_stream_blocking(self) -> ApiResponseOrError<ChatCompletionDeltaIter> {
+        let receiver = crate::blocking::block_on(self.create_stream())?;
+        Ok(ChatCompletionDeltaIter { receiver })
+    }
+}
+
+/// An iterator over the deltas of a streamed chat completion, blocking until each one arrives.
+#[cfg(feature = "blocking")]
+pub struct ChatCompletionDeltaIter {
+    receiver: Receiver<ApiResponseOrError<ChatCompletionDelta>>,
+}
+
+#[cfg(feature = "blocking")]
+impl Iterator for ChatCompletionDeltaIter {
+    type Item = ApiResponseOrError<ChatCompletionDelta>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.receiver.blocking_recv()
     }
 }
 
@@ -412,31 +1353,102 @@ fn clone_default_unwrapped_option_string(string: &Option<String>) -> String {
     }
 }
 
+impl<C> HasResponseMeta for ChatCompletionGeneric<C> {
+    fn set_meta(&mut self, meta: ResponseMeta) {
+        self.meta = Some(meta);
+    }
+}
+
+impl<C> TokenUsage for ChatCompletionGeneric<C> {
+    fn token_counts(&self) -> Option<TokenCounts> {
+        self.usage.map(TokenCounts::from)
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+}
+
+impl TokenEstimate for ChatCompletionRequest {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn estimate_prompt_tokens(&self) -> u32 {
+        #[cfg(feature = "tokenizer")]
+        if let Some(tokens) = self.count_prompt_tokens() {
+            return tokens as u32;
+        }
+        // Each message is wrapped in a few formatting tokens, and the reply is primed with 3 more.
+        let messages: u32 = self
+            .messages
+            .iter()
+            .map(|message| {
+                4 + message.image_tokens()
+                    + [
+                        Some(message.prompt_text().as_ref()),
+                        message.name.as_deref(),
+                        message
+                            .function_call
+                            .as_ref()
+                            .map(|call| call.name.as_str()),
+                        message
+                            .function_call
+                            .as_ref()
+                            .map(|call| call.arguments.as_str()),
+                    ]
+                    .into_iter()
+                    .flatten()
+                    .chain(message.tool_calls.iter().flatten().flat_map(|call| {
+                        [
+                            call.function.name.as_str(),
+                            call.function.arguments.as_str(),
+                        ]
+                    }))
+                    .map(estimate_text_tokens)
+                    .sum::<u32>()
+            })
+            .sum::<u32>()
+            + 3;
+        let functions = match self.functions.is_empty() {
+            true => 0,
+            false => estimate_text_tokens(&serde_json::to_string(&self.functions).unwrap()),
+        };
+        let tools = match self.tools.is_empty() {
+            true => 0,
+            false => estimate_text_tokens(&serde_json::to_string(&self.tools).unwrap()),
+        };
+        messages + functions + tools
+    }
+
+    fn estimate_completion_tokens(&self) -> u32 {
+        self.max_tokens.unwrap_or(0) as u32 * self.n.unwrap_or(1) as u32
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::set_key;
-    use dotenvy::dotenv;
-    use std::env;
+    use crate::azure::AzureConfig;
+    #[cfg(feature = "tokio")]
+    use crate::tests::test_retry_policy;
+    use crate::tests::{
+        cassette_client, chat_completion_json, chat_completion_response, http_response,
+        request_body, serve_responses,
+    };
+    use reqwest::header::{HeaderName, HeaderValue};
 
     #[tokio::test]
     async fn chat() {
-        dotenv().ok();
-        set_key(env::var("OPENAI_KEY").unwrap());
+        let client = cassette_client("chat");
 
-        let chat_completion = ChatCompletion::builder(
-            "gpt-3.5-turbo",
-            [ChatCompletionMessage {
-                role: ChatCompletionMessageRole::User,
-                content: Some("Hello!".to_string()),
-                name: None,
-                function_call: None,
-            }],
-        )
-        .temperature(0.0)
-        .create()
-        .await
-        .unwrap();
+        let chat_completion =
+            ChatCompletion::builder("gpt-3.5-turbo", [ChatCompletionMessage::user("Hello!")])
+                .temperature(0.0)
+                .client(&client)
+                .create()
+                .await
+                .unwrap();
 
         assert_eq!(
             chat_completion
@@ -444,8 +1456,7 @@ mod tests {
                 .first()
                 .unwrap()
                 .message
-                .content
-                .as_ref()
+                .text()
                 .unwrap(),
             "Hello! How can I assist you today?"
         );
@@ -455,24 +1466,18 @@ mod tests {
     // ensure that passing a seed still results in a valid response.
     #[tokio::test]
     async fn chat_seed() {
-        dotenv().ok();
-        set_key(env::var("OPENAI_KEY").unwrap());
+        let client = cassette_client("chat_seed");
 
         let chat_completion = ChatCompletion::builder(
             "gpt-3.5-turbo",
-            [ChatCompletionMessage {
-                role: ChatCompletionMessageRole::User,
-                content: Some(
-                    "What type of seed does Mr. England sow in the song? Reply with 1 word."
-                        .to_string(),
-                ),
-                name: None,
-                function_call: None,
-            }],
+            [ChatCompletionMessage::user(
+                "What type of seed does Mr. England sow in the song? Reply with 1 word.",
+            )],
         )
         // Determinism currently comes from temperature 0, not seed.
         .temperature(0.0)
         .seed(1337u64)
+        .client(&client)
         .create()
         .await
         .unwrap();
@@ -483,31 +1488,24 @@ mod tests {
                 .first()
                 .unwrap()
                 .message
-                .content
-                .as_ref()
+                .text()
                 .unwrap(),
             "Love"
         );
     }
 
+    #[cfg(feature = "tokio")]
     #[tokio::test]
     async fn chat_stream() {
-        dotenv().ok();
-        set_key(env::var("OPENAI_KEY").unwrap());
+        let client = cassette_client("chat_stream");
 
-        let chat_stream = ChatCompletion::builder(
-            "gpt-3.5-turbo",
-            [ChatCompletionMessage {
-                role: ChatCompletionMessageRole::User,
-                content: Some("Hello!".to_string()),
-                name: None,
-                function_call: None,
-            }],
-        )
-        .temperature(0.0)
-        .create_stream()
-        .await
-        .unwrap();
+        let chat_stream =
+            ChatCompletion::builder("gpt-3.5-turbo", [ChatCompletionMessage::user("Hello!")])
+                .temperature(0.0)
+                .client(&client)
+                .create_stream()
+                .await
+                .unwrap();
 
         let chat_completion = stream_to_completion(chat_stream).await;
 
@@ -517,27 +1515,21 @@ mod tests {
                 .first()
                 .unwrap()
                 .message
-                .content
-                .as_ref()
+                .text()
                 .unwrap(),
             "Hello! How can I assist you today?"
         );
     }
 
+    #[cfg(feature = "tokio")]
     #[tokio::test]
     async fn chat_function() {
-        dotenv().ok();
-        set_key(env::var("OPENAI_KEY").unwrap());
+        let client = cassette_client("chat_function");
 
         let chat_stream = ChatCompletion::builder(
             "gpt-3.5-turbo-0613",
             [
-                ChatCompletionMessage {
-                    role: ChatCompletionMessageRole::User,
-                    content: Some("What is the weather in Boston?".to_string()),
-                    name: None,
-                    function_call: None,
-                }
+                ChatCompletionMessage::user("What is the weather in Boston?")
             ]
         ).functions([ChatCompletionFunctionDefinition {
             description: Some("Get the current weather in a given location.".to_string()),
@@ -554,6 +1546,7 @@ mod tests {
             })),
         }])
         .temperature(0.2)
+        .client(&client)
         .create_stream()
         .await
         .unwrap();
@@ -592,11 +1585,536 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn chat_stream_retries_before_first_byte() {
+        let events = [
+            r#"data: {"id":"chatcmpl-1","object":"chat.completion.chunk","created":0,"model":"gpt-3.5-turbo","choices":[{"index":0,"finish_reason":null,"delta":{"role":"assistant","content":"Hello"}}]}"#,
+            r#"data: {"id":"chatcmpl-1","object":"chat.completion.chunk","created":0,"model":"gpt-3.5-turbo","choices":[{"index":0,"finish_reason":"stop","delta":{"content":" there!"}}]}"#,
+            "data: [DONE]",
+        ]
+        .join("\n\n")
+            + "\n\n";
+        let (base_url, requests) = serve_responses(vec![
+            http_response(503, &[], r#"{"error":{"message":"overloaded","type":"server_error","param":null,"code":null}}"#),
+            http_response(
+                200,
+                &[
+                    ("content-type", "text/event-stream"),
+                    ("x-request-id", "req_2"),
+                ],
+                &events,
+            ),
+        ])
+        .await;
+        let client = OpenAiClient::new("key")
+            .with_base_url(base_url)
+            .with_retry_policy(test_retry_policy());
+
+        let chat_stream =
+            ChatCompletion::builder("gpt-3.5-turbo", [ChatCompletionMessage::user("Hello!")])
+                .client(&client)
+                .create_stream()
+                .await
+                .unwrap();
+        let chat_completion = stream_to_completion(chat_stream).await;
+
+        assert_eq!(
+            chat_completion.choices[0].message.text(),
+            Some("Hello there!")
+        );
+        assert_eq!(
+            chat_completion.meta.unwrap().request_id.as_deref(),
+            Some("req_2")
+        );
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1].contains(r#""stream":true"#));
+    }
+
+    #[tokio::test]
+    async fn chat_on_azure_deployment() {
+        let (base_url, requests) =
+            serve_responses(vec![chat_completion_response("gpt-4", "Hi!")]).await;
+        let endpoint = base_url.trim_end_matches("v1/");
+        let client = OpenAiClient::new("azure-key").with_azure(
+            AzureConfig::new(endpoint, "2024-02-01").with_deployment("gpt-4", "my-gpt-4"),
+        );
+
+        let chat_completion =
+            ChatCompletion::builder("gpt-4", [ChatCompletionMessage::user("Hello!")])
+                .client(&client)
+                .create()
+                .await
+                .unwrap();
+
+        assert_eq!(chat_completion.choices[0].message.text(), Some("Hi!"));
+        let request = requests.lock().unwrap()[0].to_lowercase();
+        assert!(request.starts_with(
+            "post /openai/deployments/my-gpt-4/chat/completions?api-version=2024-02-01 "
+        ));
+        assert!(request.contains("api-key: azure-key\r\n"));
+        assert!(!request.contains("authorization:"));
+    }
+
+    #[tokio::test]
+    async fn per_request_headers() {
+        let response = chat_completion_response("gpt-4", "Hi!");
+        let (base_url, requests) = serve_responses(vec![response.clone(), response]).await;
+        let client = OpenAiClient::new("key")
+            .with_base_url(base_url)
+            .with_organization("org-client")
+            .with_project("proj_client");
+        let request = || {
+            ChatCompletion::builder("gpt-4", [ChatCompletionMessage::user("Hello!")])
+                .client(&client)
+        };
+
+        request()
+            .organization("org-request")
+            .header(
+                HeaderName::from_static("openai-beta"),
+                HeaderValue::from_static("assistants=v2"),
+            )
+            .create()
+            .await
+            .unwrap();
+        request().create().await.unwrap();
+
+        let requests = requests.lock().unwrap();
+        let first = requests[0].to_lowercase();
+        assert!(first.contains("openai-organization: org-request\r\n"));
+        assert!(!first.contains("org-client"));
+        assert!(first.contains("openai-project: proj_client\r\n"));
+        assert!(first.contains("openai-beta: assistants=v2\r\n"));
+        let second = requests[1].to_lowercase();
+        assert!(second.contains("openai-organization: org-client\r\n"));
+        assert!(!second.contains("openai-beta"));
+    }
+
+    #[test]
+    fn invalid_request_headers_fail_to_build() {
+        let error = ChatCompletion::builder("gpt-4", [ChatCompletionMessage::user("Hello!")])
+            .project("proj\n123")
+            .build()
+            .unwrap_err();
+
+        assert!(
+            matches!(error, OpenAiError::Validation(message) if message.contains("openai-project"))
+        );
+    }
+
+    #[tokio::test]
+    async fn extra_body_and_fields() {
+        let mut body = chat_completion_json(
+            "gpt-4",
+            "stop",
+            serde_json::json!({"role": "assistant", "content": "Hi!"}),
+        );
+        body["system_fingerprint"] = "fp_1".into();
+        let (base_url, requests) =
+            serve_responses(vec![http_response(200, &[], &body.to_string())]).await;
+        let client = OpenAiClient::new("key").with_base_url(base_url);
+
+        let chat_completion =
+            ChatCompletion::builder("gpt-4", [ChatCompletionMessage::user("Hello!")])
+                .client(&client)
+                .temperature(0.5)
+                .extra_param("seed", 42)
+                .extra_param("top_k", 5)
+                .extra_param("temperature", 0.25)
+                .create()
+                .await
+                .unwrap();
+
+        assert_eq!(chat_completion.extra_fields["system_fingerprint"], "fp_1");
+        let request = requests.lock().unwrap()[0].clone();
+        // Extra parameters replace the request's own fields of the same name.
+        assert_eq!(request.matches(r#""temperature""#).count(), 1);
+        let body = request_body(&request);
+        assert_eq!(body["temperature"], 0.25);
+        assert_eq!(body["seed"], 42);
+        assert_eq!(body["top_k"], 5);
+        assert_eq!(body["model"], "gpt-4");
+    }
+
+    #[tokio::test]
+    async fn chat_delta_stream_reports_malformed_events() {
+        let events = [
+            r#"data: {"id":"chatcmpl-1","object":"chat.completion.chunk","created":0,"model":"gpt-3.5-turbo","choices":[{"index":0,"finish_reason":null,"delta":{"role":"assistant","content":"Hello"}}]}"#,
+            r#"data: {"id":"chatcmpl-1","object":"chat.completion.chunk"}"#,
+            "data: [DONE]",
+        ]
+        .join("\n\n")
+            + "\n\n";
+        let (base_url, _) = serve_responses(vec![http_response(
+            200,
+            &[("content-type", "text/event-stream")],
+            &events,
+        )])
+        .await;
+        let client = OpenAiClient::new("key").with_base_url(base_url);
+
+        let deltas: Vec<_> =
+            ChatCompletion::builder("gpt-3.5-turbo", [ChatCompletionMessage::user("Hello!")])
+                .client(&client)
+                .create_delta_stream()
+                .await
+                .unwrap()
+                .collect()
+                .await;
+
+        assert_eq!(deltas.len(), 2);
+        let first = deltas[0].as_ref().unwrap();
+        assert_eq!(first.choices[0].delta.content.as_deref(), Some("Hello"));
+        assert!(first.meta.is_some());
+        assert!(matches!(
+            &deltas[1],
+            Err(OpenAiError::Decode { body, .. }) if body.contains("chat.completion.chunk")
+        ));
+    }
+
+    fn weather_tool() -> ChatCompletionTool {
+        ChatCompletionTool::function(ChatCompletionFunctionDefinition {
+            name: "get_weather".to_string(),
+            description: Some("Get the current weather in a given city.".to_string()),
+            parameters: Some(serde_json::json!({
+                "type": "object",
+                "properties": { "city": { "type": "string" } },
+                "required": ["city"]
+            })),
+        })
+    }
+
+    #[tokio::test]
+    async fn chat_tools() {
+        let body = chat_completion_json(
+            "gpt-4o",
+            "tool_calls",
+            serde_json::json!({
+                "role": "assistant",
+                "content": null,
+                "tool_calls": [ChatCompletionToolCall::function("call_1", "get_weather", r#"{"city":"Paris"}"#)]
+            }),
+        );
+        let (base_url, requests) =
+            serve_responses(vec![http_response(200, &[], &body.to_string())]).await;
+        let client = OpenAiClient::new("key").with_base_url(base_url);
+        let question = ChatCompletionMessage::user("What is the weather in Paris?");
+
+        let chat_completion = ChatCompletion::builder("gpt-4o", [question.clone()])
+            .client(&client)
+            .tools([weather_tool()])
+            .tool_choice(ChatCompletionToolChoice::function("get_weather"))
+            .parallel_tool_calls(false)
+            .create()
+            .await
+            .unwrap();
+
+        let message = &chat_completion.choices[0].message;
+        assert_eq!(
+            message.tool_calls.as_deref(),
+            Some(
+                &[ChatCompletionToolCall::function(
+                    "call_1",
+                    "get_weather",
+                    r#"{"city":"Paris"}"#
+                )][..]
+            )
+        );
+        let request = requests.lock().unwrap()[0].clone();
+        let body = request_body(&request);
+        assert_eq!(body["tools"][0]["type"], "function");
+        assert_eq!(body["tools"][0]["function"]["name"], "get_weather");
+        assert_eq!(
+            body["tool_choice"],
+            serde_json::json!({"type": "function", "function": {"name": "get_weather"}})
+        );
+        assert_eq!(body["parallel_tool_calls"], false);
+        assert!(body["messages"][0].get("tool_calls").is_none());
+
+        let result = ChatCompletionMessage::tool("call_1", "22 degrees");
+        assert_eq!(
+            serde_json::to_value(&result).unwrap(),
+            serde_json::json!({"role": "tool", "content": "22 degrees", "tool_call_id": "call_1"})
+        );
+        assert!(matches!(
+            ChatCompletion::builder("gpt-4o", [question])
+                .tool_choice(ChatCompletionToolChoice::Required)
+                .build(),
+            Err(OpenAiError::Validation(message)) if message.contains("tool_choice")
+        ));
+    }
+
+    #[tokio::test]
+    async fn chat_content_parts() {
+        let (base_url, requests) =
+            serve_responses(vec![chat_completion_response("gpt-4o", "A cat.")]).await;
+        let client = OpenAiClient::new("key").with_base_url(base_url);
+        let directory = std::env::temp_dir().join(format!("openai-parts-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("cat.PNG"), b"png").unwrap();
+        std::fs::write(directory.join("meow.wav"), b"wav").unwrap();
+        std::fs::write(directory.join("cat.bmp"), b"bmp").unwrap();
+        let question = ChatCompletionMessage::user(vec![
+            ChatCompletionContentPart::text("What is this?"),
+            ChatCompletionContentPart::image_file(
+                directory.join("cat.PNG"),
+                ChatCompletionImageDetail::Low,
+            )
+            .unwrap(),
+            ChatCompletionContentPart::audio_file(directory.join("meow.wav")).unwrap(),
+        ]);
+
+        let chat_completion = ChatCompletion::builder("gpt-4o", [question.clone()])
+            .client(&client)
+            .create()
+            .await
+            .unwrap();
+
+        assert_eq!(chat_completion.choices[0].message.text(), Some("A cat."));
+        let request = requests.lock().unwrap()[0].clone();
+        let body = request_body(&request);
+        assert_eq!(
+            body["messages"][0]["content"],
+            serde_json::json!([
+                {"type": "text", "text": "What is this?"},
+                {"type": "image_url", "image_url": {"url": "data:image/png;base64,cG5n", "detail": "low"}},
+                {"type": "input_audio", "input_audio": {"data": "d2F2", "format": "wav"}},
+            ])
+        );
+        let parsed: ChatCompletionMessage =
+            serde_json::from_value(body["messages"][0].clone()).unwrap();
+        assert_eq!(parsed.content, question.content);
+        assert_eq!(parsed.text(), None);
+        assert_eq!(question.prompt_text(), "What is this?");
+        let hello = ChatCompletionMessage::user("Hello!");
+        assert_eq!(
+            hello.content,
+            Some(ChatCompletionContent::Text("Hello!".to_string()))
+        );
+        assert_eq!(hello.text(), Some("Hello!"));
+
+        assert!(matches!(
+            ChatCompletionContentPart::image_file(directory.join("cat.bmp"), ChatCompletionImageDetail::Auto),
+            Err(OpenAiError::Validation(message)) if message.contains("cat.bmp")
+        ));
+        assert!(matches!(
+            ChatCompletionContentPart::audio_file(directory.join("missing.wav")),
+            Err(OpenAiError::Io(_))
+        ));
+        assert!(matches!(
+            ChatCompletion::builder("gpt-4", [question]).build(),
+            Err(OpenAiError::Validation(message)) if message == "gpt-4 doesn't support images"
+        ));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn tool_choice_serialization() {
+        for (choice, json) in [
+            (ChatCompletionToolChoice::Auto, serde_json::json!("auto")),
+            (ChatCompletionToolChoice::None, serde_json::json!("none")),
+            (
+                ChatCompletionToolChoice::Required,
+                serde_json::json!("required"),
+            ),
+            (
+                ChatCompletionToolChoice::function("get_weather"),
+                serde_json::json!({"type": "function", "function": {"name": "get_weather"}}),
+            ),
+        ] {
+            assert_eq!(serde_json::to_value(&choice).unwrap(), json);
+            assert_eq!(
+                serde_json::from_value::<ChatCompletionToolChoice>(json).unwrap(),
+                choice
+            );
+        }
+        assert!(
+            serde_json::from_value::<ChatCompletionToolChoice>(serde_json::json!("always"))
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn chat_typed_outputs() {
+        #[derive(Deserialize, openai_derive::JsonSchema, Debug, PartialEq)]
+        struct Event {
+            name: String,
+            day: Option<u8>,
+        }
+
+        let response = |finish_reason: &str, message: Value| {
+            let body = chat_completion_json("gpt-4o", finish_reason, message);
+            http_response(200, &[], &body.to_string())
+        };
+        let (base_url, requests) = serve_responses(vec![
+            response(
+                "stop",
+                serde_json::json!({"role": "assistant", "content": r#"{"name":"Science fair","day":null}"#}),
+            ),
+            response(
+                "stop",
+                serde_json::json!({"role": "assistant", "content": null, "refusal": "I can't help with that."}),
+            ),
+            response(
+                "length",
+                serde_json::json!({"role": "assistant", "content": r#"{"name":"Scie"#}),
+            ),
+            response(
+                "stop",
+                serde_json::json!({"role": "assistant", "content": r#"{"name":3}"#}),
+            ),
+        ])
+        .await;
+        let client = OpenAiClient::new("key").with_base_url(base_url);
+        let request = || {
+            ChatCompletion::builder(
+                "gpt-4o",
+                [ChatCompletionMessage::user(
+                    "Alice and Bob are going to a science fair.",
+                )],
+            )
+            .client(&client)
+        };
+
+        let event: Event = request().create_typed().await.unwrap();
+        assert_eq!(
+            event,
+            Event {
+                name: "Science fair".to_string(),
+                day: None
+            }
+        );
+        let refusal = request().create_typed::<Event>().await.unwrap_err();
+        assert!(
+            matches!(refusal, OpenAiError::Refusal(refusal) if refusal == "I can't help with that.")
+        );
+        let truncated = request().create_typed::<Event>().await.unwrap_err();
+        assert!(matches!(
+            truncated,
+            OpenAiError::Truncated { finish_reason, content }
+                if finish_reason == "length" && content == r#"{"name":"Scie"#
+        ));
+        let mismatch = request().create_typed::<Event>().await.unwrap_err();
+        assert!(matches!(mismatch, OpenAiError::Decode { body, .. } if body == r#"{"name":3}"#));
+
+        let request = requests.lock().unwrap()[0].clone();
+        let body = request_body(&request);
+        assert_eq!(
+            body["response_format"],
+            serde_json::json!({
+                "type": "json_schema",
+                "json_schema": {
+                    "name": "Event",
+                    "strict": true,
+                    "schema": {
+                        "type": "object",
+                        "properties": {
+                            "name": { "type": "string" },
+                            "day": { "anyOf": [{ "type": "integer", "minimum": 0 }, { "type": "null" }] }
+                        },
+                        "required": ["name", "day"],
+                        "additionalProperties": false
+                    }
+                }
+            })
+        );
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn chat_stream_merges_tool_calls() {
+        let chunk = |delta: &str| {
+            format!(
+                r#"data: {{"id":"chatcmpl-1","object":"chat.completion.chunk","created":0,"model":"gpt-4o","choices":[{{"index":0,"finish_reason":null,"delta":{delta}}}]}}"#
+            )
+        };
+        let events = [
+            chunk(r#"{"role":"assistant","content":null,"tool_calls":[{"index":0,"id":"call_1","type":"function","function":{"name":"get_weather","arguments":""}}]}"#),
+            chunk(r#"{"tool_calls":[{"index":0,"function":{"arguments":"{\"city\":"}}]}"#),
+            chunk(r#"{"tool_calls":[{"index":1,"id":"call_2","type":"function","function":{"name":"get_weather","arguments":"{\"city\":\"Rome\"}"}}]}"#),
+            chunk(r#"{"tool_calls":[{"index":0,"function":{"arguments":"\"Paris\"}"}}]}"#),
+            r#"data: {"id":"chatcmpl-1","object":"chat.completion.chunk","created":0,"model":"gpt-4o","choices":[{"index":0,"finish_reason":"tool_calls","delta":{}}]}"#.to_string(),
+            "data: [DONE]".to_string(),
+        ]
+        .join("\n\n")
+            + "\n\n";
+        let (base_url, _) = serve_responses(vec![http_response(
+            200,
+            &[("content-type", "text/event-stream")],
+            &events,
+        )])
+        .await;
+        let client = OpenAiClient::new("key").with_base_url(base_url);
+
+        let chat_stream = ChatCompletion::builder(
+            "gpt-4o",
+            [ChatCompletionMessage::user(
+                "What is the weather in Paris and Rome?",
+            )],
+        )
+        .client(&client)
+        .tools([weather_tool()])
+        .create_stream()
+        .await
+        .unwrap();
+        let chat_completion = stream_to_completion(chat_stream).await;
+
+        let choice = &chat_completion.choices[0];
+        assert_eq!(choice.finish_reason, "tool_calls");
+        assert_eq!(
+            choice.message.tool_calls.as_deref(),
+            Some(
+                &[
+                    ChatCompletionToolCall::function(
+                        "call_1",
+                        "get_weather",
+                        r#"{"city":"Paris"}"#
+                    ),
+                    ChatCompletionToolCall::function("call_2", "get_weather", r#"{"city":"Rome"}"#),
+                ][..]
+            )
+        );
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn chat_stream_forwards_errors() {
+        let events = [
+            r#"data: {"id":"chatcmpl-1","object":"chat.completion.chunk","created":0,"model":"gpt-4o","choices":[{"index":0,"finish_reason":null,"delta":{"role":"assistant","content":"Hel"}}]}"#,
+            "data: {not json",
+            "data: [DONE]",
+        ]
+        .join("\n\n")
+            + "\n\n";
+        let (base_url, _) = serve_responses(vec![http_response(
+            200,
+            &[("content-type", "text/event-stream")],
+            &events,
+        )])
+        .await;
+        let client = OpenAiClient::new("key").with_base_url(base_url);
+
+        let mut chat_stream =
+            ChatCompletion::builder("gpt-4o", [ChatCompletionMessage::user("Hello!")])
+                .client(&client)
+                .create_stream()
+                .await
+                .unwrap();
+
+        assert!(chat_stream.recv().await.unwrap().is_ok());
+        assert!(chat_stream.recv().await.unwrap().is_err());
+        assert!(chat_stream.recv().await.is_none());
+    }
+
+    #[cfg(feature = "tokio")]
     async fn stream_to_completion(
-        mut chat_stream: Receiver<ChatCompletionDelta>,
+        mut chat_stream: Receiver<ApiResponseOrError<ChatCompletionDelta>>,
     ) -> ChatCompletion {
         let mut merged: Option<ChatCompletionDelta> = None;
         while let Some(delta) = chat_stream.recv().await {
+            let delta = delta.unwrap();
             match merged.as_mut() {
                 Some(c) => {
                     c.merge(delta).unwrap();
@@ -0,0 +1,746 @@
+//! A configured connection to the OpenAI API.
+//!
+//! Every request in this crate is sent through an [`OpenAiClient`], which holds the API key,
+//! base url, organization, project, default headers and a pooled [`reqwest::Client`], built
+//! from an [`HttpConfig`] or passed in directly.
+//! Clients are cheap to clone, so several of them can be kept around to talk to
+//! different accounts or base urls at the same time.
+//!
+//! Requests that are not given a client explicitly use the default client, which is
+//! configured through [`set_key`](crate::set_key) and [`set_base_url`](crate::set_base_url).
+//!
+//! Clients can also talk to Azure OpenAI, see [`with_azure`](OpenAiClient::with_azure).
+//!
+//! [`OpenAiClient::from_env`] configures a client from the same environment variables as the
+//! official SDKs, such as `OPENAI_API_KEY` and `OPENAI_BASE_URL`.
+//!
+//! ```
+//! use openai::chat::{ChatCompletion, ChatCompletionMessage};
+//! use openai::OpenAiClient;
+//!
+//! let client = OpenAiClient::new("sk-...")
+//!     .with_base_url("http://localhost:8080/v1")
+//!     .with_organization("org-...");
+//!
+//! let request = ChatCompletion::builder(
+//!     "gpt-3.5-turbo",
+//!     [ChatCompletionMessage::user("Hello!")],
+//! )
+//! .client(&client);
+//! ```
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+#[cfg(not(target_arch = "wasm32"))]
+use reqwest::{Certificate, Proxy};
+use reqwest::{Client, Method, RequestBuilder};
+
+use crate::accounting::UsageTracker;
+use crate::azure::AzureConfig;
+use crate::middleware::Middleware;
+use crate::rate_limit::RateLimiter;
+use crate::retry::RetryPolicy;
+use crate::{ApiResponseOrError, OpenAiError};
+
+/// The base url used when none is configured.
+pub const DEFAULT_BASE_URL: &str = "https://api.openai.com/v1/";
+
+pub(crate) const ORGANIZATION_HEADER: &str = "openai-organization";
+pub(crate) const PROJECT_HEADER: &str = "openai-project";
+const AZURE_API_KEY_HEADER: &str = "api-key";
+const API_KEY_VAR: &str = "OPENAI_API_KEY";
+const LEGACY_API_KEY_VAR: &str = "OPENAI_KEY";
+const BASE_URL_VAR: &str = "OPENAI_BASE_URL";
+const ORG_ID_VAR: &str = "OPENAI_ORG_ID";
+const PROJECT_ID_VAR: &str = "OPENAI_PROJECT_ID";
+const TIMEOUT_VAR: &str = "OPENAI_TIMEOUT";
+const CONNECT_TIMEOUT_VAR: &str = "OPENAI_CONNECT_TIMEOUT";
+const READ_TIMEOUT_VAR: &str = "OPENAI_READ_TIMEOUT";
+const MAX_RETRIES_VAR: &str = "OPENAI_MAX_RETRIES";
+#[cfg(not(target_arch = "wasm32"))]
+const PROXY_VAR: &str = "OPENAI_PROXY";
+const AZURE_API_VERSION_PARAM: &str = "api-version";
+
+static DEFAULT_CLIENT: Mutex<Option<OpenAiClient>> = Mutex::new(None);
+
+#[derive(Clone)]
+pub struct OpenAiClient {
+    api_key: String,
+    base_url: String,
+    organization: Option<String>,
+    project: Option<String>,
+    headers: HeaderMap,
+    http: Client,
+    http_config: HttpConfig,
+    retry_policy: RetryPolicy,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    usage_tracker: Option<Arc<UsageTracker>>,
+    usage_tag: Option<String>,
+    azure: Option<AzureConfig>,
+    middlewares: Vec<Arc<dyn Middleware>>,
+}
+
+/// Settings of the HTTP connection to the API.
+///
+/// Connection settings, such as the proxy and root certificates, are used to build the
+/// [`reqwest::Client`] of an [`OpenAiClient`]. Timeouts are applied to each request, and also
+/// apply to clients passed to [`OpenAiClient::with_http_client`].
+///
+/// ```
+/// use openai::client::HttpConfig;
+/// use openai::OpenAiClient;
+/// use std::time::Duration;
+///
+/// # fn example() -> openai::ApiResponseOrError<()> {
+/// let client = OpenAiClient::new("sk-...").with_http_config(HttpConfig {
+///     connect_timeout: Some(Duration::from_secs(5)),
+///     timeout: Some(Duration::from_secs(60)),
+///     stream_read_timeout: Some(Duration::from_secs(30)),
+///     proxies: vec![reqwest::Proxy::https("http://proxy.internal:3128")?],
+///     user_agent: Some("my-app/1.0".to_string()),
+///     ..HttpConfig::default()
+/// })?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct HttpConfig {
+    /// The longest wait for a connection to be established.
+    /// Not supported on `wasm32`, where connections are made by the browser.
+    pub connect_timeout: Option<Duration>,
+    /// The longest wait for the response to start arriving, and then between two chunks of its body.
+    pub read_timeout: Option<Duration>,
+    /// The longest a whole request may take, from connecting to reading the response body.
+    /// Does not apply to streams, nor on `wasm32`.
+    pub timeout: Option<Duration>,
+    /// The longest wait for a stream to start, and then between two of its chunks.
+    pub stream_read_timeout: Option<Duration>,
+    /// The longest a whole stream may take. Does not apply on `wasm32`.
+    pub stream_timeout: Option<Duration>,
+    /// Proxies requests are sent through, in order of preference.
+    /// Proxies set in the environment, such as `HTTPS_PROXY`, are used if empty.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub proxies: Vec<Proxy>,
+    /// Certificate authorities trusted in addition to, or instead of, the built-in ones.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub root_certificates: Vec<Certificate>,
+    /// Whether the root certificates of the TLS backend are trusted.
+    /// Not supported on `wasm32`, where the browser's are always used.
+    pub built_in_root_certificates: bool,
+    /// The `User-Agent` header sent with every request.
+    pub user_agent: Option<String>,
+}
+
+impl HttpConfig {
+    /// Builds a [`reqwest::Client`] with these settings.
+    pub fn build_client(&self) -> ApiResponseOrError<Client> {
+        let mut builder = Client::builder();
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            builder = builder.tls_built_in_root_certs(self.built_in_root_certificates);
+            if let Some(connect_timeout) = self.connect_timeout {
+                builder = builder.connect_timeout(connect_timeout);
+            }
+            for proxy in &self.proxies {
+                builder = builder.proxy(proxy.clone());
+            }
+            for certificate in &self.root_certificates {
+                builder = builder.add_root_certificate(certificate.clone());
+            }
+        }
+        if let Some(user_agent) = &self.user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+        Ok(builder.build()?)
+    }
+}
+
+impl Default for HttpConfig {
+    /// No timeouts, the proxies set in the environment and the built-in root certificates.
+    fn default() -> Self {
+        HttpConfig {
+            connect_timeout: None,
+            read_timeout: None,
+            timeout: None,
+            stream_read_timeout: None,
+            stream_timeout: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            proxies: Vec::new(),
+            #[cfg(not(target_arch = "wasm32"))]
+            root_certificates: Vec::new(),
+            built_in_root_certificates: true,
+            user_agent: None,
+        }
+    }
+}
+
+impl OpenAiClient {
+    /// Creates a client for the official API using the given key.
+    pub fn new(api_key: impl Into<String>) -> Self {
+        OpenAiClient {
+            api_key: api_key.into(),
+            base_url: DEFAULT_BASE_URL.to_string(),
+            organization: None,
+            project: None,
+            headers: HeaderMap::new(),
+            http: Client::new(),
+            http_config: HttpConfig::default(),
+            retry_policy: RetryPolicy::never(),
+            rate_limiter: None,
+            usage_tracker: None,
+            usage_tag: None,
+            azure: None,
+            middlewares: Vec::new(),
+        }
+    }
+
+    /// Creates a client configured from environment variables:
+    ///
+    /// - `OPENAI_API_KEY`, or `OPENAI_KEY`, for the API key, which is required
+    /// - `OPENAI_BASE_URL` for the base url
+    /// - `OPENAI_ORG_ID` and `OPENAI_PROJECT_ID` for the organization and project
+    /// - `OPENAI_TIMEOUT`, `OPENAI_CONNECT_TIMEOUT` and `OPENAI_READ_TIMEOUT`, in seconds,
+    ///   for the matching timeouts of the [`HttpConfig`]
+    /// - `OPENAI_MAX_RETRIES` for how many times failed requests are retried,
+    ///   with the default [`RetryPolicy`]
+    /// - `OPENAI_PROXY` for a proxy every request is sent through, instead of those set in
+    ///   `HTTPS_PROXY` and similar variables
+    ///
+    /// Empty variables are treated as unset. A missing key or a malformed value is reported
+    /// as an [`OpenAiError::Env`](crate::OpenAiError::Env) naming the variable.
+    ///
+    /// ```no_run
+    /// use openai::client::set_default_client;
+    /// use openai::OpenAiClient;
+    ///
+    /// # fn example() -> openai::ApiResponseOrError<()> {
+    /// set_default_client(OpenAiClient::from_env()?);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn from_env() -> ApiResponseOrError<Self> {
+        OpenAiClient::from_vars(|name| std::env::var(name).ok())
+    }
+
+    /// Creates a client from the variables returned by `var`, as [`from_env`](Self::from_env).
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> ApiResponseOrError<Self> {
+        let var = |name: &str| var(name).filter(|value| !value.trim().is_empty());
+        let api_key = var(API_KEY_VAR)
+            .or_else(|| var(LEGACY_API_KEY_VAR))
+            .ok_or_else(|| env_error(API_KEY_VAR, "is not set, nor is OPENAI_KEY"))?;
+        let mut client = OpenAiClient::new(api_key);
+        if let Some(base_url) = var(BASE_URL_VAR) {
+            if let Err(error) = reqwest::Url::parse(&base_url) {
+                return Err(env_error(
+                    BASE_URL_VAR,
+                    format!("is not a valid url ({error}): {base_url:?}"),
+                ));
+            }
+            client = client.with_base_url(base_url);
+        }
+        if let Some(organization) = var(ORG_ID_VAR) {
+            client = client.with_organization(organization);
+        }
+        if let Some(project) = var(PROJECT_ID_VAR) {
+            client = client.with_project(project);
+        }
+        if let Some(max_retries) = var(MAX_RETRIES_VAR) {
+            let max_retries: u32 = max_retries.trim().parse().map_err(|_| {
+                env_error(
+                    MAX_RETRIES_VAR,
+                    format!("is not a whole number: {max_retries:?}"),
+                )
+            })?;
+            client = client.with_retry_policy(RetryPolicy {
+                max_attempts: max_retries.saturating_add(1),
+                ..RetryPolicy::default()
+            });
+        }
+        let seconds = |name: &str| match var(name) {
+            Some(value) => value
+                .trim()
+                .parse()
+                .ok()
+                .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                .map(Some)
+                .ok_or_else(|| env_error(name, format!("is not a number of seconds: {value:?}"))),
+            None => Ok(None),
+        };
+        #[allow(unused_mut)]
+        let mut http_config = HttpConfig {
+            timeout: seconds(TIMEOUT_VAR)?,
+            connect_timeout: seconds(CONNECT_TIMEOUT_VAR)?,
+            read_timeout: seconds(READ_TIMEOUT_VAR)?,
+            ..HttpConfig::default()
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(proxy) = var(PROXY_VAR) {
+            let proxy = Proxy::all(&proxy).map_err(|error| {
+                env_error(
+                    PROXY_VAR,
+                    format!("is not a valid proxy url ({error}): {proxy:?}"),
+                )
+            })?;
+            http_config.proxies.push(proxy);
+        }
+        client.with_http_config(http_config)
+    }
+
+    /// Sets the base url requests are sent to.
+    /// A trailing slash is appended if missing, and an empty value keeps the current url.
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.set_base_url(base_url.into());
+        self
+    }
+
+    /// Sets the organization sent in the `OpenAI-Organization` header.
+    pub fn with_organization(mut self, organization: impl Into<String>) -> Self {
+        self.organization = Some(organization.into());
+        self
+    }
+
+    /// Sets the project sent in the `OpenAI-Project` header.
+    pub fn with_project(mut self, project: impl Into<String>) -> Self {
+        self.project = Some(project.into());
+        self
+    }
+
+    /// Adds a header sent with every request, replacing any header of the same name.
+    pub fn with_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Adds headers sent with every request, replacing any headers of the same names.
+    pub fn with_headers(mut self, headers: HeaderMap) -> Self {
+        self.headers.extend(headers);
+        self
+    }
+
+    /// Uses the given [`reqwest::Client`] to send requests, instead of a new one.
+    /// The timeouts of the [`HttpConfig`] still apply.
+    pub fn with_http_client(mut self, http: Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    /// Sends requests with a new [`reqwest::Client`] built from `http_config`.
+    pub fn with_http_config(mut self, http_config: HttpConfig) -> ApiResponseOrError<Self> {
+        self.http = http_config.build_client()?;
+        self.http_config = http_config;
+        Ok(self)
+    }
+
+    /// Sets how failed requests are retried. Requests are not retried by default.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Makes requests wait for capacity in the given limiter before being sent.
+    /// Pass an `Arc<RateLimiter>` to share one limiter between several clients.
+    pub fn with_rate_limiter(mut self, rate_limiter: impl Into<Arc<RateLimiter>>) -> Self {
+        self.rate_limiter = Some(rate_limiter.into());
+        self
+    }
+
+    /// Counts the usage of requests in the given tracker, and refuses them once its budget is
+    /// reached. Pass an `Arc<UsageTracker>` to read the totals, or to share one tracker between
+    /// several clients.
+    pub fn with_usage_tracker(mut self, usage_tracker: impl Into<Arc<UsageTracker>>) -> Self {
+        self.usage_tracker = Some(usage_tracker.into());
+        self
+    }
+
+    /// Counts the usage of this client's requests under `tag`, in addition to their model and
+    /// endpoint.
+    pub fn with_usage_tag(mut self, tag: impl Into<String>) -> Self {
+        self.usage_tag = Some(tag.into());
+        self
+    }
+
+    /// Sends requests to Azure OpenAI, as configured by `azure`.
+    /// This also sets the base url to the `openai/` path of the Azure endpoint.
+    pub fn with_azure(mut self, azure: AzureConfig) -> Self {
+        self.base_url = azure.base_url();
+        self.azure = Some(azure);
+        self
+    }
+
+    /// Adds a middleware to the end of the chain every request is sent through.
+    pub fn with_middleware(mut self, middleware: impl Middleware) -> Self {
+        self.middlewares.push(Arc::new(middleware));
+        self
+    }
+
+    pub fn api_key(&self) -> &str {
+        &self.api_key
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub fn organization(&self) -> Option<&str> {
+        self.organization.as_deref()
+    }
+
+    pub fn project(&self) -> Option<&str> {
+        self.project.as_deref()
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    pub fn http_client(&self) -> &Client {
+        &self.http
+    }
+
+    pub fn http_config(&self) -> &HttpConfig {
+        &self.http_config
+    }
+
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+
+    pub fn rate_limiter(&self) -> Option<&Arc<RateLimiter>> {
+        self.rate_limiter.as_ref()
+    }
+
+    pub fn usage_tracker(&self) -> Option<&Arc<UsageTracker>> {
+        self.usage_tracker.as_ref()
+    }
+
+    pub fn usage_tag(&self) -> Option<&str> {
+        self.usage_tag.as_deref()
+    }
+
+    pub fn azure(&self) -> Option<&AzureConfig> {
+        self.azure.as_ref()
+    }
+
+    pub fn middlewares(&self) -> &[Arc<dyn Middleware>] {
+        &self.middlewares
+    }
+
+    fn set_base_url(&mut self, base_url: String) {
+        if base_url.is_empty() {
+            return;
+        }
+        self.base_url = base_url;
+        if !self.base_url.ends_with('/') {
+            self.base_url += "/";
+        }
+    }
+
+    /// The route of a request served by `model`.
+    /// On Azure, these are scoped to the deployment of the model.
+    pub(crate) fn model_route(&self, model: &str, route: &str) -> String {
+        match &self.azure {
+            Some(azure) => format!("deployments/{}/{route}", azure.deployment(model)),
+            None => route.to_string(),
+        }
+    }
+
+    /// Starts a request to `route`, relative to the base url, with authentication
+    /// and default headers applied.
+    pub(crate) fn request(&self, method: Method, route: &str) -> RequestBuilder {
+        let mut request = self.http.request(method, self.base_url.clone() + route);
+        request = match &self.azure {
+            Some(azure) => request
+                .query(&[(AZURE_API_VERSION_PARAM, azure.api_version())])
+                .header(AZURE_API_KEY_HEADER, &self.api_key),
+            None => request.header(AUTHORIZATION, format!("Bearer {}", self.api_key)),
+        };
+        if let Some(organization) = &self.organization {
+            request = request.header(ORGANIZATION_HEADER, organization);
+        }
+        if let Some(project) = &self.project {
+            request = request.header(PROJECT_HEADER, project);
+        }
+        // Added last, so headers set explicitly replace the organization and project.
+        request.headers(self.headers.clone())
+    }
+}
+
+impl Default for OpenAiClient {
+    fn default() -> Self {
+        OpenAiClient::new("")
+    }
+}
+
+impl From<&OpenAiClient> for OpenAiClient {
+    fn from(client: &OpenAiClient) -> Self {
+        client.clone()
+    }
+}
+
+impl fmt::Debug for OpenAiClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OpenAiClient")
+            .field("base_url", &self.base_url)
+            .field("organization", &self.organization)
+            .field("project", &self.project)
+            .field("headers", &self.headers)
+            .field("http_config", &self.http_config)
+            .field("retry_policy", &self.retry_policy)
+            .field("rate_limiter", &self.rate_limiter)
+            .field("usage_tracker", &self.usage_tracker)
+            .field("usage_tag", &self.usage_tag)
+            .field("azure", &self.azure)
+            .field("middlewares", &self.middlewares.len())
+            .finish_non_exhaustive()
+    }
+}
+
+fn env_error(variable: &str, message: impl Into<String>) -> OpenAiError {
+    OpenAiError::Env {
+        variable: variable.to_string(),
+        message: message.into(),
+    }
+}
+
+/// Returns a copy of the default client, used by requests that were not given one.
+pub fn default_client() -> OpenAiClient {
+    DEFAULT_CLIENT
+        .lock()
+        .unwrap()
+        .get_or_insert_with(OpenAiClient::default)
+        .clone()
+}
+
+/// Replaces the default client, used by requests that were not given one.
+pub fn set_default_client(client: OpenAiClient) {
+    *DEFAULT_CLIENT.lock().unwrap() = Some(client);
+}
+
+pub(crate) fn update_default_client(update: impl FnOnce(&mut OpenAiClient)) {
+    update(
+        DEFAULT_CLIENT
+            .lock()
+            .unwrap()
+            .get_or_insert_with(OpenAiClient::default),
+    );
+}
+
+pub(crate) fn set_default_key(api_key: String) {
+    update_default_client(|client| client.api_key = api_key);
+}
+
+pub(crate) fn set_default_base_url(base_url: String) {
+    update_default_client(|client| client.set_base_url(base_url));
+}
+
+/// Resolves the client a request should be sent with, adding the request's own headers.
+pub(crate) fn request_client(client: &Option<OpenAiClient>, headers: &HeaderMap) -> OpenAiClient {
+    let client = match client {
+        Some(client) => client.clone(),
+        None => default_client(),
+    };
+    if headers.is_empty() {
+        client
+    } else {
+        client.with_headers(headers.clone())
+    }
+}
+
+/// The headers set on a request builder, along with the first header value that was invalid,
+/// which fails the builder's `build()` with [`OpenAiError::Validation`].
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RequestHeaders {
+    headers: HeaderMap,
+    error: Option<String>,
+}
+
+impl RequestHeaders {
+    pub(crate) fn insert(&mut self, name: HeaderName, value: HeaderValue) {
+        self.headers.insert(name, value);
+    }
+
+    /// Inserts a header from a string, recording an error if it isn't a valid header value.
+    pub(crate) fn insert_str(&mut self, name: &'static str, value: &str) {
+        match HeaderValue::from_str(value) {
+            Ok(value) => self.insert(HeaderName::from_static(name), value),
+            Err(_) => {
+                self.error
+                    .get_or_insert_with(|| format!("{value:?} is not a valid {name} header"));
+            }
+        }
+    }
+
+    pub(crate) fn build(self) -> Result<HeaderMap, OpenAiError> {
+        match self.error {
+            Some(error) => Err(OpenAiError::Validation(error)),
+            None => Ok(self.headers),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn base_url_default() {
+        let client = OpenAiClient::new("key");
+        assert_eq!(client.base_url(), DEFAULT_BASE_URL);
+
+        // empty value
+        let client = client.with_base_url("");
+        assert_eq!(client.base_url(), DEFAULT_BASE_URL);
+
+        // appends slash
+        let client = client.with_base_url("https://api.openai.com/v2");
+        assert_eq!(client.base_url(), "https://api.openai.com/v2/");
+    }
+
+    #[test]
+    fn clients_are_independent() {
+        let first = OpenAiClient::new("first").with_base_url("http://localhost:1/v1");
+        let second = OpenAiClient::new("second")
+            .with_base_url("http://localhost:2/v1")
+            .with_organization("org-2");
+
+        assert_eq!(first.api_key(), "first");
+        assert_eq!(second.api_key(), "second");
+        assert_eq!(first.base_url(), "http://localhost:1/v1/");
+        assert_eq!(second.base_url(), "http://localhost:2/v1/");
+        assert_eq!(first.organization(), None);
+        assert_eq!(second.organization(), Some("org-2"));
+    }
+
+    #[test]
+    fn request_headers() {
+        let client = OpenAiClient::new("key")
+            .with_organization("org-123")
+            .with_project("proj_123")
+            .with_header(
+                HeaderName::from_static("x-custom"),
+                HeaderValue::from_static("value"),
+            );
+        let request = client.request(Method::GET, "models").build().unwrap();
+
+        assert_eq!(request.url().as_str(), "https://api.openai.com/v1/models");
+        assert_eq!(request.headers()[AUTHORIZATION], "Bearer key");
+        assert_eq!(request.headers()[ORGANIZATION_HEADER], "org-123");
+        assert_eq!(request.headers()[PROJECT_HEADER], "proj_123");
+        assert_eq!(request.headers()["x-custom"], "value");
+    }
+
+    #[test]
+    fn azure_requests() {
+        let client = OpenAiClient::new("key").with_azure(
+            AzureConfig::new("https://my-resource.openai.azure.com", "2024-02-01")
+                .with_deployment("gpt-4", "my-gpt-4"),
+        );
+        let route = client.model_route("gpt-4", "chat/completions");
+        let request = client.request(Method::POST, &route).build().unwrap();
+
+        assert_eq!(
+            request.url().as_str(),
+            "https://my-resource.openai.azure.com/openai/deployments/my-gpt-4/chat/completions?api-version=2024-02-01"
+        );
+        assert_eq!(request.headers()[AZURE_API_KEY_HEADER], "key");
+        assert!(!request.headers().contains_key(AUTHORIZATION));
+
+        let request = client.request(Method::GET, "files").build().unwrap();
+        assert_eq!(
+            request.url().as_str(),
+            "https://my-resource.openai.azure.com/openai/files?api-version=2024-02-01"
+        );
+        assert_eq!(
+            OpenAiClient::new("key").model_route("gpt-4", "embeddings"),
+            "embeddings"
+        );
+    }
+
+    #[test]
+    fn debug_hides_key() {
+        let client = OpenAiClient::new("sk-secret");
+        assert!(!format!("{client:?}").contains("sk-secret"));
+    }
+
+    fn from_vars(vars: &[(&str, &str)]) -> ApiResponseOrError<OpenAiClient> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        OpenAiClient::from_vars(|name| vars.get(name).cloned())
+    }
+
+    fn env_error_message(vars: &[(&str, &str)]) -> String {
+        match from_vars(vars) {
+            Err(error @ OpenAiError::Env { .. }) => error.to_string(),
+            other => panic!("expected an environment error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn from_env_vars() {
+        let client = from_vars(&[
+            ("OPENAI_API_KEY", "sk-new"),
+            ("OPENAI_KEY", "sk-old"),
+            ("OPENAI_BASE_URL", "http://localhost:8080/v1"),
+            ("OPENAI_ORG_ID", "org-123"),
+            ("OPENAI_PROJECT_ID", "proj_123"),
+            ("OPENAI_TIMEOUT", "60"),
+            ("OPENAI_READ_TIMEOUT", "2.5"),
+            ("OPENAI_CONNECT_TIMEOUT", ""),
+            ("OPENAI_MAX_RETRIES", "4"),
+            ("OPENAI_PROXY", "http://proxy.internal:3128"),
+        ])
+        .unwrap();
+
+        assert_eq!(client.api_key(), "sk-new");
+        assert_eq!(client.base_url(), "http://localhost:8080/v1/");
+        assert_eq!(client.organization(), Some("org-123"));
+        assert_eq!(client.project(), Some("proj_123"));
+        assert_eq!(client.http_config().timeout, Some(Duration::from_secs(60)));
+        assert_eq!(
+            client.http_config().read_timeout,
+            Some(Duration::from_millis(2500))
+        );
+        assert_eq!(client.http_config().connect_timeout, None);
+        assert_eq!(client.http_config().proxies.len(), 1);
+        assert_eq!(client.retry_policy().max_attempts, 5);
+
+        let client = from_vars(&[("OPENAI_KEY", "sk-old")]).unwrap();
+        assert_eq!(client.api_key(), "sk-old");
+        assert_eq!(client.base_url(), DEFAULT_BASE_URL);
+        assert_eq!(client.retry_policy().max_attempts, 1);
+    }
+
+    #[test]
+    fn from_env_errors() {
+        assert_eq!(
+            env_error_message(&[("OPENAI_API_KEY", " ")]),
+            "OPENAI_API_KEY is not set, nor is OPENAI_KEY"
+        );
+        assert_eq!(
+            env_error_message(&[("OPENAI_KEY", "sk"), ("OPENAI_TIMEOUT", "1m")]),
+            r#"OPENAI_TIMEOUT is not a number of seconds: "1m""#
+        );
+        assert_eq!(
+            env_error_message(&[("OPENAI_KEY", "sk"), ("OPENAI_READ_TIMEOUT", "-1")]),
+            r#"OPENAI_READ_TIMEOUT is not a number of seconds: "-1""#
+        );
+        assert_eq!(
+            env_error_message(&[("OPENAI_KEY", "sk"), ("OPENAI_MAX_RETRIES", "three")]),
+            r#"OPENAI_MAX_RETRIES is not a whole number: "three""#
+        );
+        assert!(
+            env_error_message(&[("OPENAI_KEY", "sk"), ("OPENAI_BASE_URL", "localhost/v1")])
+                .starts_with("OPENAI_BASE_URL is not a valid url")
+        );
+        assert!(
+            env_error_message(&[("OPENAI_KEY", "sk"), ("OPENAI_PROXY", "http://[::1")])
+                .starts_with("OPENAI_PROXY is not a valid proxy url")
+        );
+    }
+}
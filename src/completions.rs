@@ -1,9 +1,18 @@
 //! Given a prompt, the model will return one or more predicted completions,
 //! and can also return the probabilities of alternative tokens at each position.
 
-use super::{openai_post, ApiResponseOrError, Usage};
+use super::{openai_post_metered, ApiResponseOrError, Usage};
+use crate::client::{request_client, RequestHeaders};
+use crate::meta::HasResponseMeta;
+use crate::models::model_info;
+use crate::pricing::{price_table, Cost, TokenCounts};
+use crate::rate_limit::{estimate_model_tokens, TokenEstimate, TokenUsage};
+use crate::ResponseMeta;
+use crate::{OpenAiClient, OpenAiError};
 use derive_builder::Builder;
+use reqwest::header::HeaderMap;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use std::collections::HashMap;
 
 #[derive(Deserialize, Clone)]
@@ -13,6 +22,12 @@ pub struct Completion {
     pub model: String,
     pub choices: Vec<CompletionChoice>,
     pub usage: Usage,
+    /// Fields of the response this crate doesn't know about.
+    #[serde(flatten)]
+    pub extra_fields: Map<String, Value>,
+    /// Metadata of the response this was parsed from.
+    #[serde(skip)]
+    pub meta: Option<ResponseMeta>,
 }
 
 #[derive(Deserialize, Clone)]
@@ -26,6 +41,7 @@ pub struct CompletionChoice {
 #[derive(Serialize, Builder, Debug, Clone)]
 #[builder(pattern = "owned")]
 #[builder(name = "CompletionBuilder")]
+#[builder(build_fn(error = "OpenAiError", validate = "Self::validate"))]
 #[builder(setter(strip_option, into))]
 pub struct CompletionRequest {
     /// ID of the model to use.
@@ -144,42 +160,135 @@ pub struct CompletionRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[builder(default)]
     pub user: Option<String>,
+    /// The client to send the request with. Uses the default client if not set.
+    #[serde(skip)]
+    #[builder(default)]
+    pub client: Option<OpenAiClient>,
+    /// Headers sent with this request, replacing the client's headers of the same name.
+    #[serde(skip)]
+    #[builder(
+        setter(custom),
+        field(type = "RequestHeaders", build = "self.headers.build()?")
+    )]
+    pub headers: HeaderMap,
+    /// Extra parameters sent in the body of the request.
+    #[serde(flatten)]
+    #[builder(default, setter(custom))]
+    pub extra_body: Map<String, Value>,
 }
 
+request_builder_setters!(CompletionBuilder);
+
 impl Completion {
     /// Creates a completion for the provided prompt and parameters
     async fn create(request: &CompletionRequest) -> ApiResponseOrError<Self> {
-        openai_post("completions", request).await
+        let client = request_client(&request.client, &request.headers);
+        let route = client.model_route(&request.model, "completions");
+        openai_post_metered(&client, "completions", &route, request).await
     }
 
     pub fn builder(model: &str) -> CompletionBuilder {
         CompletionBuilder::create_empty().model(model)
     }
+
+    /// The cost of the completion with the prices of
+    /// [`price_table`](crate::pricing::price_table), or `None` if the model has no price.
+    pub fn cost(&self) -> Option<Cost> {
+        price_table().cost(&self.model, self.usage.into())
+    }
 }
 
 impl CompletionBuilder {
+    /// Checks the request against the model's entry in the
+    /// [`model_registry`](crate::models::model_registry), if it has one.
+    fn validate(&self) -> Result<(), String> {
+        let Some((model, info)) = self
+            .model
+            .as_deref()
+            .and_then(|model| Some((model, model_info(model)?)))
+        else {
+            return Ok(());
+        };
+        let max_tokens = self.max_tokens.flatten().map(u64::from);
+        if let Some(max_tokens) = max_tokens {
+            info.check_max_tokens(model, max_tokens)?;
+        }
+        #[cfg(feature = "tokenizer")]
+        if let (Some(encoding), Some(Some(prompt))) = (info.encoding, &self.prompt) {
+            // Without `max_tokens`, the API generates up to 16 tokens.
+            info.check_context_window(
+                model,
+                encoding.count(prompt) as u64,
+                max_tokens.unwrap_or(16),
+            )?;
+        }
+        Ok(())
+    }
+
     pub async fn create(self) -> ApiResponseOrError<Completion> {
-        Completion::create(&self.build().unwrap()).await
+        Completion::create(&self.build()?).await
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl CompletionBuilder {
+    /// Blocking version of [`CompletionBuilder::create`].
+    pub fn create_blocking(self) -> ApiResponseOrError<Completion> {
+        crate::blocking::block_on(self.create())
+    }
+}
+
+impl HasResponseMeta for Completion {
+    fn set_meta(&mut self, meta: ResponseMeta) {
+        self.meta = Some(meta);
+    }
+}
+
+impl TokenUsage for Completion {
+    fn token_counts(&self) -> Option<TokenCounts> {
+        Some(self.usage.into())
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+}
+
+impl TokenEstimate for CompletionRequest {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn estimate_prompt_tokens(&self) -> u32 {
+        [self.prompt.as_deref(), self.suffix.as_deref()]
+            .into_iter()
+            .flatten()
+            .map(|text| estimate_model_tokens(&self.model, text))
+            .sum()
+    }
+
+    fn estimate_completion_tokens(&self) -> u32 {
+        // The API generates `best_of` completions server-side when it's set.
+        let completions = self.best_of.or(self.n).unwrap_or(1) as u32;
+        // Without `max_tokens`, the API generates up to 16 tokens.
+        self.max_tokens.unwrap_or(16) as u32 * completions
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::set_key;
-    use crate::tests::DEFAULT_LEGACY_MODEL;
-    use dotenvy::dotenv;
-    use std::env;
+    use crate::tests::{cassette_client, DEFAULT_LEGACY_MODEL};
 
     #[tokio::test]
     async fn completion() {
-        dotenv().ok();
-        set_key(env::var("OPENAI_KEY").unwrap());
+        let client = cassette_client("completion");
 
         let completion = Completion::builder(DEFAULT_LEGACY_MODEL)
             .prompt("Say this is a test")
             .max_tokens(7)
             .temperature(0.0)
+            .client(&client)
             .create()
             .await
             .unwrap();
@@ -1,17 +1,34 @@
 //! Given a prompt and an instruction, the model will return an edited version of the prompt.
 
-use super::{openai_post, ApiResponseOrError, OpenAiError, Usage};
+use super::{openai_post_metered, ApiResponseOrError, OpenAiError, Usage};
+use crate::client::{request_client, RequestHeaders};
+use crate::meta::HasResponseMeta;
+use crate::pricing::{price_table, Cost, TokenCounts};
+use crate::rate_limit::{estimate_text_tokens, TokenEstimate, TokenUsage};
+use crate::OpenAiClient;
+use crate::ResponseMeta;
 use derive_builder::Builder;
+use reqwest::header::HeaderMap;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 
 #[derive(Deserialize, Clone)]
 pub struct Edit {
     pub created: u32,
+    /// The model that made the edit, taken from the request.
+    #[serde(default)]
+    pub model: String,
     #[serde(skip_deserializing)]
     pub choices: Vec<String>,
     pub usage: Usage,
     #[serde(rename = "choices")]
     choices_bad: Vec<EditChoice>,
+    /// Fields of the response this crate doesn't know about.
+    #[serde(flatten)]
+    pub extra_fields: Map<String, Value>,
+    /// Metadata of the response this was parsed from.
+    #[serde(skip)]
+    pub meta: Option<ResponseMeta>,
 }
 
 #[derive(Deserialize, Clone)]
@@ -22,6 +39,7 @@ struct EditChoice {
 #[derive(Serialize, Builder, Debug, Clone)]
 #[builder(pattern = "owned")]
 #[builder(name = "EditBuilder")]
+#[builder(build_fn(error = "OpenAiError"))]
 #[builder(setter(strip_option, into))]
 pub struct EditRequest {
     /// ID of the model to use.
@@ -53,22 +71,43 @@ pub struct EditRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[builder(default)]
     pub top_p: Option<f32>,
+    /// The client to send the request with. Uses the default client if not set.
+    #[serde(skip)]
+    #[builder(default)]
+    pub client: Option<OpenAiClient>,
+    /// Headers sent with this request, replacing the client's headers of the same name.
+    #[serde(skip)]
+    #[builder(
+        setter(custom),
+        field(type = "RequestHeaders", build = "self.headers.build()?")
+    )]
+    pub headers: HeaderMap,
+    /// Extra parameters sent in the body of the request.
+    #[serde(flatten)]
+    #[builder(default, setter(custom))]
+    pub extra_body: Map<String, Value>,
 }
 
+request_builder_setters!(EditBuilder);
+
 impl Edit {
     async fn create(request: &EditRequest) -> ApiResponseOrError<Self> {
-        let response: Result<Self, OpenAiError> = openai_post("edits", request).await?;
+        let mut edit: Self = openai_post_metered(
+            &request_client(&request.client, &request.headers),
+            "edits",
+            "edits",
+            request,
+        )
+        .await?;
 
-        match response {
-            Ok(mut edit) => {
-                for choice in &edit.choices_bad {
-                    edit.choices.push(choice.text.clone());
-                }
-
-                Ok(edit)
-            }
-            Err(_) => response,
+        if edit.model.is_empty() {
+            edit.model = request.model.clone();
+        }
+        for choice in &edit.choices_bad {
+            edit.choices.push(choice.text.clone());
         }
+
+        Ok(edit)
     }
 
     pub fn builder(model: &str, instruction: impl Into<String>) -> EditBuilder {
@@ -76,11 +115,48 @@ impl Edit {
             .model(model)
             .instruction(instruction)
     }
+
+    /// The cost of the edit with the prices of [`price_table`](crate::pricing::price_table),
+    /// or `None` if the model has no price.
+    pub fn cost(&self) -> Option<Cost> {
+        price_table().cost(&self.model, self.usage.into())
+    }
 }
 
 impl EditBuilder {
     pub async fn create(self) -> ApiResponseOrError<Edit> {
-        Edit::create(&self.build().unwrap()).await
+        Edit::create(&self.build()?).await
+    }
+}
+
+impl TokenUsage for Edit {
+    fn token_counts(&self) -> Option<TokenCounts> {
+        Some(self.usage.into())
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+}
+
+impl TokenEstimate for EditRequest {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn estimate_prompt_tokens(&self) -> u32 {
+        self.input.as_deref().map_or(0, estimate_text_tokens)
+            + estimate_text_tokens(&self.instruction)
+    }
+
+    fn estimate_completion_tokens(&self) -> u32 {
+        0
+    }
+}
+
+impl HasResponseMeta for Edit {
+    fn set_meta(&mut self, meta: ResponseMeta) {
+        self.meta = Some(meta);
     }
 }
 
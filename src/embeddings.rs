@@ -2,25 +2,70 @@
 //!
 //! Related guide: [Embeddings](https://beta.openai.com/docs/guides/embeddings)
 
-use super::{openai_post, ApiResponseOrError};
+use super::{openai_post_metered, ApiResponseOrError};
+use crate::client::{request_client, RequestHeaders};
+use crate::meta::HasResponseMeta;
+use crate::pricing::{price_table, Cost, TokenCounts};
+use crate::rate_limit::{estimate_model_tokens, TokenEstimate, TokenUsage};
+use crate::ResponseMeta;
+use crate::{OpenAiClient, OpenAiError};
+use derive_builder::Builder;
+use reqwest::header::HeaderMap;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 
-#[derive(Serialize, Clone)]
-struct CreateEmbeddingsRequestBody<'a> {
-    model: &'a str,
-    input: Vec<&'a str>,
-    #[serde(skip_serializing_if = "str::is_empty")]
-    user: &'a str,
+#[derive(Serialize, Builder, Debug, Clone)]
+#[builder(pattern = "owned")]
+#[builder(name = "EmbeddingsBuilder")]
+#[builder(build_fn(error = "OpenAiError"))]
+#[builder(setter(strip_option, into))]
+pub struct EmbeddingsRequest {
+    /// ID of the model to use.
+    /// You can use the [List models](https://beta.openai.com/docs/api-reference/models/list)
+    /// API to see all of your available models, or see our [Model overview](https://beta.openai.com/docs/models/overview)
+    /// for descriptions of them.
+    pub model: String,
+    /// Input text to get embeddings for.
+    /// Each input must not exceed 8192 tokens in length.
+    pub input: Vec<String>,
+    /// A unique identifier representing your end-user, which can help OpenAI to monitor and detect abuse.
+    /// [Learn more](https://beta.openai.com/docs/guides/safety-best-practices/end-user-ids).
+    #[serde(skip_serializing_if = "String::is_empty")]
+    #[builder(default)]
+    pub user: String,
+    /// The client to send the request with. Uses the default client if not set.
+    #[serde(skip)]
+    #[builder(default)]
+    pub client: Option<OpenAiClient>,
+    /// Headers sent with this request, replacing the client's headers of the same name.
+    #[serde(skip)]
+    #[builder(
+        setter(custom),
+        field(type = "RequestHeaders", build = "self.headers.build()?")
+    )]
+    pub headers: HeaderMap,
+    /// Extra parameters sent in the body of the request.
+    #[serde(flatten)]
+    #[builder(default, setter(custom))]
+    pub extra_body: Map<String, Value>,
 }
 
+request_builder_setters!(EmbeddingsBuilder);
+
 #[derive(Deserialize, Clone)]
 pub struct Embeddings {
     pub data: Vec<Embedding>,
     pub model: String,
     pub usage: EmbeddingsUsage,
+    /// Fields of the response this crate doesn't know about.
+    #[serde(flatten)]
+    pub extra_fields: Map<String, Value>,
+    /// Metadata of the response this was parsed from.
+    #[serde(skip)]
+    pub meta: Option<ResponseMeta>,
 }
 
-#[derive(Deserialize, Clone, Copy)]
+#[derive(Deserialize, Clone, Copy, Debug)]
 pub struct EmbeddingsUsage {
     pub prompt_tokens: u32,
     pub total_tokens: u32,
@@ -47,11 +92,22 @@ impl Embeddings {
     /// * `user` - A unique identifier representing your end-user, which can help OpenAI to monitor and detect abuse.
     ///   [Learn more](https://beta.openai.com/docs/guides/safety-best-practices/end-user-ids).
     pub async fn create(model: &str, input: Vec<&str>, user: &str) -> ApiResponseOrError<Self> {
-        openai_post(
-            "embeddings",
-            &CreateEmbeddingsRequestBody { model, input, user },
-        )
-        .await
+        Embeddings::builder(model, input).user(user).create().await
+    }
+
+    pub fn builder<S: Into<String>>(
+        model: &str,
+        input: impl IntoIterator<Item = S>,
+    ) -> EmbeddingsBuilder {
+        EmbeddingsBuilder::create_empty()
+            .model(model)
+            .input(input.into_iter().map(Into::into).collect::<Vec<String>>())
+    }
+
+    async fn create_from_request(request: &EmbeddingsRequest) -> ApiResponseOrError<Self> {
+        let client = request_client(&request.client, &request.headers);
+        let route = client.model_route(&request.model, "embeddings");
+        openai_post_metered(&client, "embeddings", &route, request).await
     }
 
     pub fn distances(&self) -> Vec<f64> {
@@ -70,6 +126,28 @@ impl Embeddings {
     }
 }
 
+impl EmbeddingsBuilder {
+    pub async fn create(self) -> ApiResponseOrError<Embeddings> {
+        Embeddings::create_from_request(&self.build()?).await
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl Embeddings {
+    /// Blocking version of [`Embeddings::create`].
+    pub fn create_blocking(model: &str, input: Vec<&str>, user: &str) -> ApiResponseOrError<Self> {
+        crate::blocking::block_on(Embeddings::create(model, input, user))
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl EmbeddingsBuilder {
+    /// Blocking version of [`EmbeddingsBuilder::create`].
+    pub fn create_blocking(self) -> ApiResponseOrError<Embeddings> {
+        crate::blocking::block_on(self.create())
+    }
+}
+
 impl Embedding {
     pub async fn create(model: &str, input: &str, user: &str) -> ApiResponseOrError<Self> {
         let mut embeddings = Embeddings::create(model, vec![input], user).await?;
@@ -89,23 +167,66 @@ impl Embedding {
     }
 }
 
+impl Embeddings {
+    /// The cost of the embeddings with the prices of
+    /// [`price_table`](crate::pricing::price_table), or `None` if the model has no price.
+    pub fn cost(&self) -> Option<Cost> {
+        price_table().cost(&self.model, self.usage.into())
+    }
+}
+
+impl HasResponseMeta for Embeddings {
+    fn set_meta(&mut self, meta: ResponseMeta) {
+        self.meta = Some(meta);
+    }
+}
+
+impl TokenUsage for Embeddings {
+    fn token_counts(&self) -> Option<TokenCounts> {
+        Some(self.usage.into())
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+}
+
+impl TokenEstimate for EmbeddingsRequest {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn estimate_prompt_tokens(&self) -> u32 {
+        self.input
+            .iter()
+            .map(|input| estimate_model_tokens(&self.model, input))
+            .sum()
+    }
+
+    fn estimate_completion_tokens(&self) -> u32 {
+        0
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::set_key;
-    use dotenvy::dotenv;
-    use std::env;
+    use crate::rate_limit::{RateLimit, RateLimiter};
+    use crate::tests::{cassette_client, http_response, serve_responses};
+    use std::sync::Arc;
+    use std::time::Duration;
 
     #[tokio::test]
     async fn embeddings() {
-        dotenv().ok();
-        set_key(env::var("OPENAI_KEY").unwrap());
+        let client = cassette_client("embeddings");
 
-        let embeddings = Embeddings::create(
+        let embeddings = Embeddings::builder(
             "text-embedding-ada-002",
-            vec!["The food was delicious and the waiter..."],
-            "",
+            ["The food was delicious and the waiter..."],
         )
+        .user("")
+        .client(&client)
+        .create()
         .await
         .unwrap();
 
@@ -114,20 +235,84 @@ mod tests {
 
     #[tokio::test]
     async fn embedding() {
-        dotenv().ok();
-        set_key(env::var("OPENAI_KEY").unwrap());
+        let client = cassette_client("embedding");
 
-        let embedding = Embedding::create(
+        let embedding = Embeddings::builder(
             "text-embedding-ada-002",
-            "The food was delicious and the waiter...",
-            "",
+            ["The food was delicious and the waiter..."],
         )
+        .user("")
+        .client(&client)
+        .create()
         .await
-        .unwrap();
+        .unwrap()
+        .data
+        .swap_remove(0);
 
         assert!(!embedding.vec.is_empty());
     }
 
+    #[tokio::test]
+    async fn rate_limited_embeddings_correct_estimate() {
+        let (base_url, requests) = serve_responses(vec![http_response(
+            200,
+            &[],
+            r#"{"data":[{"embedding":[1.0]}],"model":"text-embedding-ada-002","usage":{"prompt_tokens":10,"total_tokens":10}}"#,
+        )])
+        .await;
+        let limiter = Arc::new(
+            RateLimiter::new().with_limit("text-embedding-ada-002", RateLimit::new(100, 100)),
+        );
+        let client = OpenAiClient::new("key")
+            .with_base_url(base_url)
+            .with_rate_limiter(limiter.clone());
+
+        // Estimated at 80 tokens, of which only 10 are used.
+        Embeddings::builder("text-embedding-ada-002", ["x".repeat(320)])
+            .client(&client)
+            .create()
+            .await
+            .unwrap();
+
+        tokio::time::timeout(
+            Duration::from_secs(1),
+            limiter.acquire("text-embedding-ada-002", 90),
+        )
+        .await
+        .expect("unused tokens should be given back");
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn rate_limited_failures_give_back_tokens() {
+        let (base_url, _) = serve_responses(vec![http_response(
+            400,
+            &[],
+            r#"{"error":{"message":"bad input","type":"invalid_request_error","param":null,"code":null}}"#,
+        )])
+        .await;
+        let limiter = Arc::new(
+            RateLimiter::new().with_limit("text-embedding-ada-002", RateLimit::new(100, 100)),
+        );
+        let client = OpenAiClient::new("key")
+            .with_base_url(base_url)
+            .with_rate_limiter(limiter.clone());
+
+        Embeddings::builder("text-embedding-ada-002", ["x".repeat(320)])
+            .client(&client)
+            .create()
+            .await
+            .err()
+            .unwrap();
+
+        tokio::time::timeout(
+            Duration::from_secs(1),
+            limiter.acquire("text-embedding-ada-002", 100),
+        )
+        .await
+        .expect("the tokens of a failed request should be given back");
+    }
+
     #[test]
     fn right_angle() {
         let embeddings = Embeddings {
@@ -144,6 +329,8 @@ mod tests {
                 prompt_tokens: 0,
                 total_tokens: 0,
             },
+            extra_fields: Map::new(),
+            meta: None,
         };
 
         assert_eq!(embeddings.distances()[0], 0.0);
@@ -165,6 +352,8 @@ mod tests {
                 prompt_tokens: 0,
                 total_tokens: 0,
             },
+            extra_fields: Map::new(),
+            meta: None,
         };
 
         assert_ne!(embeddings.distances()[0], 0.0);
@@ -0,0 +1,457 @@
+//! Errors returned by the API and by this crate.
+//!
+//! Every call returns an [`OpenAiError`], which can be matched on without parsing strings:
+//!
+//! ```
+//! use openai::{ApiErrorCode, OpenAiError};
+//!
+//! fn should_shorten_prompt(error: &OpenAiError) -> bool {
+//!     matches!(error.code(), Some(ApiErrorCode::ContextLengthExceeded))
+//! }
+//! ```
+
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use derive_builder::UninitializedFieldError;
+use reqwest::{Response, StatusCode};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::accounting::BudgetPeriod;
+
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+#[derive(Debug)]
+pub enum OpenAiError {
+    /// The API answered with an error.
+    Api(Box<ApiError>),
+    /// The request could not be sent, or the response could not be read.
+    Transport(reqwest::Error),
+    /// The request timed out.
+    Timeout(reqwest::Error),
+    /// No data arrived from the API within the configured read timeout.
+    ReadTimeout(Duration),
+    /// The response body did not have the expected shape.
+    Decode {
+        /// The HTTP status of the response.
+        status: StatusCode,
+        /// The raw response body.
+        body: String,
+        source: serde_json::Error,
+    },
+    /// The events of a streamed response could not be read.
+    MalformedStream(String),
+    /// A local file could not be read or written.
+    Io(std::io::Error),
+    /// The request is missing a required field or has an invalid value,
+    /// and was not sent.
+    Validation(String),
+    /// An environment variable read by
+    /// [`OpenAiClient::from_env`](crate::OpenAiClient::from_env) is missing or malformed.
+    Env {
+        /// The name of the variable.
+        variable: String,
+        /// What is wrong with it.
+        message: String,
+    },
+    /// A [`Cassette`](crate::cassette::Cassette) replaying a recording could not answer a
+    /// request, because no recorded response is left for it or the recorded one is invalid.
+    Cassette {
+        /// The file of the recording.
+        path: PathBuf,
+        message: String,
+    },
+    /// The budget of the client's [`UsageTracker`](crate::accounting::UsageTracker) has been
+    /// reached, and the request was not sent.
+    BudgetExceeded {
+        period: BudgetPeriod,
+        /// The limit of the period, in US dollars.
+        limit: f64,
+        /// The amount spent in the period, in US dollars.
+        spent: f64,
+    },
+    /// The model refused to give a structured output, with this explanation.
+    Refusal(String),
+    /// The output was cut off before it was complete, so it couldn't be parsed.
+    Truncated {
+        /// Why the output stopped: `length` when it reached `max_tokens` or the context window,
+        /// or `content_filter` when it was filtered.
+        finish_reason: String,
+        /// The output up to where it stopped.
+        content: String,
+    },
+}
+
+/// An error response from the API.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiError {
+    /// The HTTP status of the response.
+    pub status: StatusCode,
+    /// The `x-request-id` header of the response, useful for support requests.
+    pub request_id: Option<String>,
+    /// A human-readable description of the error.
+    /// For responses that are not JSON, such as a proxy error page, this is the raw body.
+    pub message: String,
+    /// The kind of error, such as `invalid_request_error` or `server_error`.
+    pub error_type: Option<String>,
+    /// The request parameter the error relates to.
+    pub param: Option<String>,
+    /// A machine-readable error code.
+    pub code: Option<ApiErrorCode>,
+}
+
+/// A machine-readable code identifying an [`ApiError`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ApiErrorCode {
+    /// The prompt and `max_tokens` exceed the model's context window.
+    ContextLengthExceeded,
+    /// Too many requests or tokens were sent in a short period.
+    RateLimitExceeded,
+    /// The account is out of credits or has hit its spending limit.
+    InsufficientQuota,
+    /// The API key is missing, malformed or revoked.
+    InvalidApiKey,
+    /// The model does not exist or the account has no access to it.
+    ModelNotFound,
+    /// A code not known to this crate.
+    Other(String),
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: ErrorBody,
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    #[serde(default)]
+    message: String,
+    #[serde(rename = "type", default)]
+    error_type: Option<String>,
+    #[serde(default)]
+    param: Option<Value>,
+    #[serde(default)]
+    code: Option<Value>,
+}
+
+impl OpenAiError {
+    /// Reads an error response into an [`OpenAiError::Api`].
+    pub(crate) async fn from_response(response: Response) -> OpenAiError {
+        let status = response.status();
+        let request_id = response
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        match response.text().await {
+            Ok(body) => OpenAiError::Api(Box::new(ApiError::from_body(status, request_id, &body))),
+            Err(error) => error.into(),
+        }
+    }
+
+    /// Parses a successful response body, turning error objects and unexpected bodies into errors.
+    pub(crate) fn parse_body<T: serde::de::DeserializeOwned>(
+        status: StatusCode,
+        request_id: Option<String>,
+        body: String,
+    ) -> Result<T, OpenAiError> {
+        match serde_json::from_str(&body) {
+            Ok(value) => Ok(value),
+            Err(source) => match serde_json::from_str::<ErrorResponse>(&body) {
+                Ok(response) => Err(OpenAiError::Api(Box::new(ApiError::from_error_body(
+                    status,
+                    request_id,
+                    response.error,
+                )))),
+                Err(_) => Err(OpenAiError::Decode {
+                    status,
+                    body,
+                    source,
+                }),
+            },
+        }
+    }
+
+    /// The HTTP status of the response that caused this error, if any.
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            OpenAiError::Api(error) => Some(error.status),
+            OpenAiError::Decode { status, .. } => Some(*status),
+            OpenAiError::Transport(error) | OpenAiError::Timeout(error) => error.status(),
+            OpenAiError::ReadTimeout(_)
+            | OpenAiError::MalformedStream(_)
+            | OpenAiError::Io(_)
+            | OpenAiError::Validation(_)
+            | OpenAiError::Env { .. }
+            | OpenAiError::Cassette { .. }
+            | OpenAiError::BudgetExceeded { .. }
+            | OpenAiError::Refusal(_)
+            | OpenAiError::Truncated { .. } => None,
+        }
+    }
+
+    /// The API error code, if the API answered with one.
+    pub fn code(&self) -> Option<&ApiErrorCode> {
+        match self {
+            OpenAiError::Api(error) => error.code.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// The `x-request-id` of the response that caused this error, if any.
+    pub fn request_id(&self) -> Option<&str> {
+        match self {
+            OpenAiError::Api(error) => error.request_id.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+impl ApiError {
+    fn from_body(status: StatusCode, request_id: Option<String>, body: &str) -> ApiError {
+        match serde_json::from_str::<ErrorResponse>(body) {
+            Ok(response) => ApiError::from_error_body(status, request_id, response.error),
+            Err(_) => ApiError {
+                status,
+                request_id,
+                message: match body.trim() {
+                    "" => status.to_string(),
+                    body => body.to_string(),
+                },
+                error_type: None,
+                param: None,
+                code: None,
+            },
+        }
+    }
+
+    fn from_error_body(status: StatusCode, request_id: Option<String>, body: ErrorBody) -> Self {
+        ApiError {
+            status,
+            request_id,
+            message: body.message,
+            error_type: body.error_type,
+            param: body.param.and_then(value_to_string),
+            code: body.code.and_then(value_to_string).map(ApiErrorCode::from),
+        }
+    }
+}
+
+/// Compatible servers don't always send strings for `param` and `code`.
+fn value_to_string(value: Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::String(string) => Some(string),
+        value => Some(value.to_string()),
+    }
+}
+
+impl ApiErrorCode {
+    pub fn as_str(&self) -> &str {
+        match self {
+            ApiErrorCode::ContextLengthExceeded => "context_length_exceeded",
+            ApiErrorCode::RateLimitExceeded => "rate_limit_exceeded",
+            ApiErrorCode::InsufficientQuota => "insufficient_quota",
+            ApiErrorCode::InvalidApiKey => "invalid_api_key",
+            ApiErrorCode::ModelNotFound => "model_not_found",
+            ApiErrorCode::Other(code) => code,
+        }
+    }
+}
+
+impl From<String> for ApiErrorCode {
+    fn from(code: String) -> Self {
+        match code.as_str() {
+            "context_length_exceeded" => ApiErrorCode::ContextLengthExceeded,
+            "rate_limit_exceeded" => ApiErrorCode::RateLimitExceeded,
+            "insufficient_quota" => ApiErrorCode::InsufficientQuota,
+            "invalid_api_key" => ApiErrorCode::InvalidApiKey,
+            "model_not_found" => ApiErrorCode::ModelNotFound,
+            _ => ApiErrorCode::Other(code),
+        }
+    }
+}
+
+impl fmt::Display for ApiErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.status)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl fmt::Display for OpenAiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OpenAiError::Api(error) => write!(f, "{error}"),
+            OpenAiError::Transport(error) => write!(f, "{error}"),
+            OpenAiError::Timeout(error) => write!(f, "request timed out: {error}"),
+            OpenAiError::ReadTimeout(timeout) => {
+                write!(f, "no data received from the API for {timeout:?}")
+            }
+            OpenAiError::Decode { status, source, .. } => {
+                write!(f, "could not decode response ({status}): {source}")
+            }
+            OpenAiError::MalformedStream(message) => write!(f, "malformed event stream: {message}"),
+            OpenAiError::Io(error) => write!(f, "{error}"),
+            OpenAiError::Validation(message) => write!(f, "invalid request: {message}"),
+            OpenAiError::Env { variable, message } => write!(f, "{variable} {message}"),
+            OpenAiError::Cassette { path, message } => {
+                write!(f, "cassette {}: {message}", path.display())
+            }
+            OpenAiError::BudgetExceeded {
+                period,
+                limit,
+                spent,
+            } => {
+                let period = match period {
+                    BudgetPeriod::Daily => "daily",
+                    BudgetPeriod::Total => "total",
+                };
+                write!(
+                    f,
+                    "{period} budget of ${limit:.2} reached (${spent:.2} spent)"
+                )
+            }
+            OpenAiError::Refusal(refusal) => write!(f, "the model refused to answer: {refusal}"),
+            OpenAiError::Truncated { finish_reason, .. } => {
+                write!(f, "the output was cut off (finish reason: {finish_reason})")
+            }
+        }
+    }
+}
+
+impl std::error::Error for OpenAiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            OpenAiError::Api(error) => Some(error),
+            OpenAiError::Transport(error) | OpenAiError::Timeout(error) => Some(error),
+            OpenAiError::Decode { source, .. } => Some(source),
+            OpenAiError::Io(error) => Some(error),
+            OpenAiError::ReadTimeout(_)
+            | OpenAiError::MalformedStream(_)
+            | OpenAiError::Validation(_)
+            | OpenAiError::Env { .. }
+            | OpenAiError::Cassette { .. }
+            | OpenAiError::BudgetExceeded { .. }
+            | OpenAiError::Refusal(_)
+            | OpenAiError::Truncated { .. } => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for OpenAiError {
+    fn from(value: reqwest::Error) -> Self {
+        if value.is_timeout() {
+            OpenAiError::Timeout(value)
+        } else {
+            OpenAiError::Transport(value)
+        }
+    }
+}
+
+impl From<std::io::Error> for OpenAiError {
+    fn from(value: std::io::Error) -> Self {
+        OpenAiError::Io(value)
+    }
+}
+
+impl From<UninitializedFieldError> for OpenAiError {
+    fn from(value: UninitializedFieldError) -> Self {
+        OpenAiError::Validation(value.to_string())
+    }
+}
+
+impl From<String> for OpenAiError {
+    fn from(value: String) -> Self {
+        OpenAiError::Validation(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn api_error_with_code() {
+        let error = ApiError::from_body(
+            StatusCode::BAD_REQUEST,
+            Some("req_123".to_string()),
+            r#"{"error":{"message":"This model's maximum context length is 4097 tokens.","type":"invalid_request_error","param":"messages","code":"context_length_exceeded"}}"#,
+        );
+        assert_eq!(error.status, StatusCode::BAD_REQUEST);
+        assert_eq!(error.request_id.as_deref(), Some("req_123"));
+        assert_eq!(error.error_type.as_deref(), Some("invalid_request_error"));
+        assert_eq!(error.param.as_deref(), Some("messages"));
+        assert_eq!(error.code, Some(ApiErrorCode::ContextLengthExceeded));
+    }
+
+    #[test]
+    fn api_error_with_unknown_and_numeric_code() {
+        let error = ApiError::from_body(
+            StatusCode::BAD_REQUEST,
+            None,
+            r#"{"error":{"message":"nope","type":"invalid_request_error","code":"something_new"}}"#,
+        );
+        assert_eq!(
+            error.code,
+            Some(ApiErrorCode::Other("something_new".to_string()))
+        );
+
+        let error = ApiError::from_body(
+            StatusCode::BAD_REQUEST,
+            None,
+            r#"{"error":{"message":"nope","code":400}}"#,
+        );
+        assert_eq!(error.code, Some(ApiErrorCode::Other("400".to_string())));
+    }
+
+    #[test]
+    fn api_error_from_html() {
+        let body = "<html><body><h1>502 Bad Gateway</h1></body></html>";
+        let error = ApiError::from_body(StatusCode::BAD_GATEWAY, None, body);
+        assert_eq!(error.status, StatusCode::BAD_GATEWAY);
+        assert_eq!(error.message, body);
+        assert_eq!(error.code, None);
+    }
+
+    #[test]
+    fn decode_error_keeps_body() {
+        #[derive(Deserialize, Debug)]
+        struct Expected {
+            #[allow(dead_code)]
+            id: String,
+        }
+
+        let error =
+            OpenAiError::parse_body::<Expected>(StatusCode::OK, None, "not json".to_string())
+                .unwrap_err();
+        match error {
+            OpenAiError::Decode { status, body, .. } => {
+                assert_eq!(status, StatusCode::OK);
+                assert_eq!(body, "not json");
+            }
+            error => panic!("unexpected error: {error:?}"),
+        }
+
+        let error = OpenAiError::parse_body::<Expected>(
+            StatusCode::OK,
+            Some("req_123".to_string()),
+            r#"{"error":{"message":"Incorrect API key provided","type":"invalid_request_error","code":"invalid_api_key"}}"#.to_string(),
+        )
+        .unwrap_err();
+        assert_eq!(error.code(), Some(&ApiErrorCode::InvalidApiKey));
+        match error {
+            OpenAiError::Api(error) => assert_eq!(error.request_id.as_deref(), Some("req_123")),
+            error => panic!("unexpected error: {error:?}"),
+        }
+    }
+}
@@ -5,11 +5,11 @@
 //!
 //! # Examples
 //!
-//! All examples and tests require the `OPENAI_KEY` environment variable
+//! All examples require the `OPENAI_KEY` environment variable
 //! be set with your personal openai platform API key.
 //!
 //! Upload a new file. [Reference API](https://platform.openai.com/docs/api-reference/files/upload)
-//! ```
+//! ```no_run
 //!use openai::files::File;
 //!use openai::ApiResponseOrError;
 //!use dotenvy::dotenv;
@@ -31,7 +31,7 @@
 //! ```
 //!
 //! List files. [Reference API](https://platform.openai.com/docs/api-reference/files/list)
-//! ```
+//! ```no_run
 //!use openai::files::Files;
 //!use openai::ApiResponseOrError;
 //!use dotenvy::dotenv;
@@ -119,12 +119,21 @@ use std::path::Path;
 
 use bytes::{BufMut, BytesMut};
 use derive_builder::Builder;
-use futures_util::StreamExt;
-use reqwest::multipart::{Form, Part};
+use futures_util::{Stream, StreamExt};
+use reqwest::header::HeaderMap;
+use reqwest::multipart::Form;
 use reqwest::Method;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 
-use crate::{openai_delete, openai_get, openai_post_multipart, openai_request};
+use crate::client::{default_client, request_client, RequestHeaders};
+use crate::meta::HasResponseMeta;
+use crate::pagination::{self, ListQuery, Page};
+use crate::runtime::UploadFile;
+use crate::ResponseMeta;
+use crate::{
+    openai_delete, openai_get, openai_post_multipart, openai_request, OpenAiClient, OpenAiError,
+};
 
 use super::ApiResponseOrError;
 
@@ -144,6 +153,12 @@ pub struct File {
     pub filename: String,
     /// The purpose of the file. ie: "fine-tine"
     pub purpose: String,
+    /// Fields of the response this crate doesn't know about.
+    #[serde(flatten)]
+    pub extra_fields: Map<String, Value>,
+    /// Metadata of the response this was parsed from.
+    #[serde(skip)]
+    pub meta: Option<ResponseMeta>,
 }
 
 #[derive(Deserialize, Serialize, Clone)]
@@ -151,27 +166,43 @@ pub struct DeletedFile {
     pub id: String,
     pub object: String,
     pub deleted: bool,
+    /// Metadata of the response this was parsed from.
+    #[serde(skip)]
+    pub meta: Option<ResponseMeta>,
 }
 
 /// List files in the openai platform.
-#[derive(Deserialize, Serialize, Clone)]
-pub struct Files {
-    data: Vec<File>,
-    pub object: String,
-}
+pub type Files = Page<File>;
 
 #[derive(Serialize, Builder, Debug, Clone)]
 #[builder(pattern = "owned")]
 #[builder(name = "FileUploadBuilder")]
+#[builder(build_fn(error = "OpenAiError"))]
 #[builder(setter(strip_option, into))]
 pub struct FileUploadRequest {
     file_name: String,
     purpose: String,
+    /// The client to send the request with. Uses the default client if not set.
+    #[serde(skip)]
+    #[builder(default)]
+    client: Option<OpenAiClient>,
+    /// Headers sent with this request, replacing the client's headers of the same name.
+    #[serde(skip)]
+    #[builder(
+        setter(custom),
+        field(type = "RequestHeaders", build = "self.headers.build()?")
+    )]
+    headers: HeaderMap,
+    /// Extra parameters sent in the body of the request.
+    #[serde(flatten)]
+    #[builder(default, setter(custom))]
+    extra_body: Map<String, Value>,
 }
 
+request_builder_setters!(FileUploadBuilder);
+
 impl File {
     async fn create(request: &FileUploadRequest) -> ApiResponseOrError<Self> {
-        let purpose = request.purpose.clone();
         let upload_file_path = Path::new(request.file_name.as_str());
         let upload_file_path = upload_file_path.canonicalize()?;
         let simple_name = upload_file_path
@@ -179,14 +210,36 @@ impl File {
             .unwrap()
             .to_str()
             .unwrap()
-            .to_string()
-            .clone();
-        let async_file = tokio::fs::File::open(upload_file_path).await?;
-        let file_part = Part::stream(async_file)
-            .file_name(simple_name)
-            .mime_str("application/jsonl")?;
-        let form = Form::new().part("file", file_part).text("purpose", purpose);
-        openai_post_multipart("files", form).await
+            .to_string();
+        let contents = UploadFile::open(&upload_file_path).await?;
+        let form = || {
+            // Extra parameters replace the request's own fields of the same name.
+            let mut form = Form::new();
+            if !request.extra_body.contains_key("file") {
+                let file_part = contents
+                    .part()
+                    .file_name(simple_name.clone())
+                    .mime_str("application/jsonl")
+                    .expect("application/jsonl is a valid mime type");
+                form = form.part("file", file_part);
+            }
+            if !request.extra_body.contains_key("purpose") {
+                form = form.text("purpose", request.purpose.clone());
+            }
+            request
+                .extra_body
+                .iter()
+                .fold(form, |form, (name, value)| match value {
+                    Value::String(text) => form.text(name.clone(), text.clone()),
+                    value => form.text(name.clone(), value.to_string()),
+                })
+        };
+        openai_post_multipart(
+            &request_client(&request.client, &request.headers),
+            "files",
+            form,
+        )
+        .await
     }
 
     /// New FileUploadBuilder
@@ -196,18 +249,40 @@ impl File {
 
     /// Delete a file from openai platform by id.
     pub async fn delete(id: &str) -> ApiResponseOrError<DeletedFile> {
-        openai_delete(format!("files/{}", id).as_str()).await
+        File::delete_with_client(&default_client(), id).await
+    }
+
+    /// Delete a file from openai platform by id, using the given client.
+    pub async fn delete_with_client(
+        client: &OpenAiClient,
+        id: &str,
+    ) -> ApiResponseOrError<DeletedFile> {
+        openai_delete(client, format!("files/{}", id).as_str()).await
     }
 
     /// Get a file from openai platform by id.
     pub async fn get(id: &str) -> ApiResponseOrError<File> {
-        openai_get(format!("files/{}", id).as_str()).await
+        File::get_with_client(&default_client(), id).await
+    }
+
+    /// Get a file from openai platform by id, using the given client.
+    pub async fn get_with_client(client: &OpenAiClient, id: &str) -> ApiResponseOrError<File> {
+        openai_get(client, format!("files/{}", id).as_str()).await
     }
 
     /// Download a file as bytes into memory by id.
     pub async fn get_content_bytes(id: &str) -> ApiResponseOrError<Vec<u8>> {
+        File::get_content_bytes_with_client(&default_client(), id).await
+    }
+
+    /// Download a file as bytes into memory by id, using the given client.
+    pub async fn get_content_bytes_with_client(
+        client: &OpenAiClient,
+        id: &str,
+    ) -> ApiResponseOrError<Vec<u8>> {
         let route = format!("files/{}/content", id);
-        let response = openai_request(Method::GET, route.as_str(), |request| request).await?;
+        let response =
+            openai_request(client, Method::GET, route.as_str(), |request| request).await?;
         let content_len = response.content_length().unwrap_or(1024) as usize;
         let mut file_bytes = BytesMut::with_capacity(content_len);
         let mut bytes_stream = response.bytes_stream();
@@ -219,9 +294,19 @@ impl File {
 
     /// Download a file to a new local file by id.
     pub async fn download_content_to_file(id: &str, file_path: &str) -> ApiResponseOrError<()> {
+        File::download_content_to_file_with_client(&default_client(), id, file_path).await
+    }
+
+    /// Download a file to a new local file by id, using the given client.
+    pub async fn download_content_to_file_with_client(
+        client: &OpenAiClient,
+        id: &str,
+        file_path: &str,
+    ) -> ApiResponseOrError<()> {
         let mut output_file = std::fs::File::create(file_path)?;
         let route = format!("files/{}/content", id);
-        let response = openai_request(Method::GET, route.as_str(), |request| request).await?;
+        let response =
+            openai_request(client, Method::GET, route.as_str(), |request| request).await?;
         let mut bytes_stream = response.bytes_stream();
         while let Some(Ok(bytes)) = bytes_stream.next().await {
             output_file.write_all(bytes.as_ref())?;
@@ -233,26 +318,78 @@ impl File {
 impl FileUploadBuilder {
     /// Upload the file to the openai platform.
     pub async fn create(self) -> ApiResponseOrError<File> {
-        File::create(&self.build().unwrap()).await
+        File::create(&self.build()?).await
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl FileUploadBuilder {
+    /// Blocking version of [`FileUploadBuilder::create`].
+    pub fn create_blocking(self) -> ApiResponseOrError<File> {
+        crate::blocking::block_on(self.create())
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl File {
+    /// Blocking version of [`File::get_content_bytes`].
+    pub fn get_content_bytes_blocking(id: &str) -> ApiResponseOrError<Vec<u8>> {
+        crate::blocking::block_on(File::get_content_bytes(id))
+    }
+
+    /// Blocking version of [`File::download_content_to_file`].
+    pub fn download_content_to_file_blocking(id: &str, file_path: &str) -> ApiResponseOrError<()> {
+        crate::blocking::block_on(File::download_content_to_file(id, file_path))
     }
 }
 
 impl Files {
     /// Get a list of all uploaded files in the openai platform.
     pub async fn list() -> ApiResponseOrError<Files> {
-        openai_get("files").await
+        Files::list_with_client(&default_client()).await
+    }
+
+    /// Get a list of all uploaded files in the openai platform, using the given client.
+    pub async fn list_with_client(client: &OpenAiClient) -> ApiResponseOrError<Files> {
+        openai_get(client, "files").await
     }
-    pub fn len(&self) -> usize {
-        self.data.len()
+
+    /// Get one page of the uploaded files in the openai platform.
+    pub async fn list_page(query: &ListQuery) -> ApiResponseOrError<Files> {
+        Files::list_page_with_client(&default_client(), query).await
+    }
+
+    /// Get one page of the uploaded files in the openai platform, using the given client.
+    pub async fn list_page_with_client(
+        client: &OpenAiClient,
+        query: &ListQuery,
+    ) -> ApiResponseOrError<Files> {
+        pagination::list_page(client, "files", query).await
+    }
+
+    /// Stream every uploaded file in the openai platform, fetching pages as needed.
+    pub fn list_all(query: ListQuery) -> impl Stream<Item = ApiResponseOrError<File>> {
+        Files::list_all_with_client(&default_client(), query)
+    }
+
+    /// Stream every uploaded file in the openai platform, using the given client.
+    pub fn list_all_with_client(
+        client: &OpenAiClient,
+        query: ListQuery,
+    ) -> impl Stream<Item = ApiResponseOrError<File>> {
+        pagination::list_all(client, "files", query)
     }
 }
 
-impl<'a> IntoIterator for &'a Files {
-    type Item = &'a File;
-    type IntoIter = core::slice::Iter<'a, File>;
+impl HasResponseMeta for File {
+    fn set_meta(&mut self, meta: ResponseMeta) {
+        self.meta = Some(meta);
+    }
+}
 
-    fn into_iter(self) -> Self::IntoIter {
-        self.data.as_slice().iter()
+impl HasResponseMeta for DeletedFile {
+    fn set_meta(&mut self, meta: ResponseMeta) {
+        self.meta = Some(meta);
     }
 }
 
@@ -262,9 +399,9 @@ mod tests {
     use std::io::Read;
     use std::time::Duration;
 
-    use dotenvy::dotenv;
-
-    use crate::set_key;
+    use crate::tests::{
+        cassette_client, http_response, recording, serve_responses, test_retry_policy,
+    };
 
     use super::*;
 
@@ -280,9 +417,8 @@ mod tests {
 
     #[tokio::test]
     async fn upload_file() {
-        dotenv().ok();
-        set_key(env::var("OPENAI_KEY").unwrap());
-        let file_upload = test_upload_builder().create().await.unwrap();
+        let client = cassette_client("upload_file");
+        let file_upload = test_upload_builder().client(client).create().await.unwrap();
         println!(
             "upload: {}",
             serde_json::to_string_pretty(&file_upload).unwrap()
@@ -292,28 +428,31 @@ mod tests {
 
     #[tokio::test]
     async fn missing_file() {
-        dotenv().ok();
-        set_key(env::var("OPENAI_KEY").unwrap());
+        // The file is read before anything is sent.
         let test_builder = File::builder()
+            .client(OpenAiClient::new("sk-test"))
             .file_name("test_data/missing_file.jsonl")
             .purpose("fine-tune");
         let response = test_builder.create().await;
         assert!(response.is_err());
         let openapi_err = response.err().unwrap();
-        assert_eq!(openapi_err.error_type, "io");
+        assert!(matches!(openapi_err, OpenAiError::Io(_)));
         assert_eq!(
-            openapi_err.message,
+            openapi_err.to_string(),
             "No such file or directory (os error 2)"
         )
     }
 
     #[tokio::test]
     async fn list_files() {
-        dotenv().ok();
-        set_key(env::var("OPENAI_KEY").unwrap());
+        let client = cassette_client("list_files");
         // ensure at least one file exists
-        test_upload_builder().create().await.unwrap();
-        let openai_files = Files::list().await.unwrap();
+        test_upload_builder()
+            .client(client.clone())
+            .create()
+            .await
+            .unwrap();
+        let openai_files = Files::list_with_client(&client).await.unwrap();
         let file_count = openai_files.len();
         assert!(file_count > 0);
         for openai_file in openai_files.into_iter() {
@@ -328,18 +467,25 @@ mod tests {
 
     #[tokio::test]
     async fn delete_files() {
-        dotenv().ok();
-        set_key(env::var("OPENAI_KEY").unwrap());
+        let client = cassette_client("delete_files");
         // ensure at least one file exists
-        test_upload_builder().create().await.unwrap();
+        test_upload_builder()
+            .client(client.clone())
+            .create()
+            .await
+            .unwrap();
         // wait to avoid recent upload still processing error
-        tokio::time::sleep(Duration::from_secs(7)).await;
-        let openai_files = Files::list().await.unwrap();
-        assert!(openai_files.data.len() > 0);
+        if recording() {
+            tokio::time::sleep(Duration::from_secs(7)).await;
+        }
+        let openai_files = Files::list_with_client(&client).await.unwrap();
+        assert!(!openai_files.is_empty());
         let mut files = openai_files.data;
-        files.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+        files.sort_by_key(|file| file.created_at);
         for file in files {
-            let deleted_file = File::delete(file.id.as_str()).await.unwrap();
+            let deleted_file = File::delete_with_client(&client, file.id.as_str())
+                .await
+                .unwrap();
             assert!(deleted_file.deleted);
             println!("deleted: {} {}", deleted_file.id, deleted_file.deleted)
         }
@@ -347,15 +493,22 @@ mod tests {
 
     #[tokio::test]
     async fn get_file_and_contents() {
-        dotenv().ok();
-        set_key(env::var("OPENAI_KEY").unwrap());
+        let client = cassette_client("get_file_and_contents");
 
-        let file = test_upload_builder().create().await.unwrap();
-        let file_get = File::get(file.id.as_str()).await.unwrap();
+        let file = test_upload_builder()
+            .client(client.clone())
+            .create()
+            .await
+            .unwrap();
+        let file_get = File::get_with_client(&client, file.id.as_str())
+            .await
+            .unwrap();
         assert_eq!(file.id, file_get.id);
 
         // get file as bytes
-        let body_bytes = File::get_content_bytes(file.id.as_str()).await.unwrap();
+        let body_bytes = File::get_content_bytes_with_client(&client, file.id.as_str())
+            .await
+            .unwrap();
         assert_eq!(body_bytes.len(), file.bytes);
 
         // download file to a file
@@ -363,25 +516,57 @@ mod tests {
         let test_dir = format!("{}/{}", manifest_dir, "target/files-test");
         std::fs::create_dir_all(test_dir.as_str()).unwrap();
         let test_file_save_path = format!("{}/{}", test_dir.as_str(), file.filename);
-        File::download_content_to_file(file.id.as_str(), test_file_save_path.as_str())
-            .await
-            .unwrap();
+        File::download_content_to_file_with_client(
+            &client,
+            file.id.as_str(),
+            test_file_save_path.as_str(),
+        )
+        .await
+        .unwrap();
         let mut local_file = std::fs::File::open(test_file_save_path.as_str()).unwrap();
         let mut local_bytes: Vec<u8> = Vec::new();
         local_file.read_to_end(&mut local_bytes).unwrap();
         assert_eq!(body_bytes, local_bytes)
     }
 
+    #[tokio::test]
+    async fn missing_file_name() {
+        let response = File::builder().purpose("fine-tune").create().await;
+        match response.err().unwrap() {
+            OpenAiError::Validation(message) => assert!(message.contains("file_name")),
+            error => panic!("unexpected error: {error:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn upload_file_retried() {
+        let uploaded = r#"{"id":"file-abc","object":"file","bytes":3,"created_at":0,"filename":"file_upload_test1.jsonl","purpose":"fine-tune"}"#;
+        let (base_url, requests) = serve_responses(vec![
+            http_response(502, &[], "{}"),
+            http_response(200, &[], uploaded),
+        ])
+        .await;
+        let client = OpenAiClient::new("key")
+            .with_base_url(base_url)
+            .with_retry_policy(test_retry_policy());
+
+        let file_upload = test_upload_builder().client(client).create().await.unwrap();
+
+        assert_eq!(file_upload.id, "file-abc");
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        let contents = std::fs::read_to_string("test_data/file_upload_test1.jsonl").unwrap();
+        for request in requests.iter() {
+            assert!(request.contains(r#"filename="file_upload_test1.jsonl""#));
+            assert!(request.contains(&contents));
+        }
+    }
+
     #[test]
     fn file_name_path_test() {
         let request = test_upload_request();
         let file_upload_path = Path::new(request.file_name.as_str());
-        let file_name = file_upload_path
-            .clone()
-            .file_name()
-            .unwrap()
-            .to_str()
-            .unwrap();
+        let file_name = file_upload_path.file_name().unwrap().to_str().unwrap();
         assert_eq!(file_name, "file_upload_test1.jsonl");
         let file_upload_path = file_upload_path.canonicalize().unwrap();
         let file_exists = file_upload_path.exists();
@@ -1,162 +1,453 @@
-use std::sync::Mutex;
+use std::future::Future;
+use std::time::Duration;
 
+use bytes::Bytes;
+use eventsource_stream::{EventStream, Eventsource};
+use futures_util::stream::{self, Stream};
+use futures_util::StreamExt;
 use reqwest::multipart::Form;
-use reqwest::{header::AUTHORIZATION, Client, Method, RequestBuilder, Response};
-use reqwest_eventsource::{CannotCloneRequestError, EventSource, RequestBuilderExt};
+use reqwest::{Method, RequestBuilder, Response};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
+use crate::meta::HasResponseMeta;
+use crate::middleware::Next;
+use crate::pricing::TokenCounts;
+use crate::rate_limit::{Reservation, TokenEstimate, TokenUsage};
+use crate::runtime::{BoxStream, MaybeSend};
+
+pub use client::OpenAiClient;
+pub use error::{ApiError, ApiErrorCode, OpenAiError};
+pub use meta::ResponseMeta;
+
+// Lets the derive macros of `openai-derive` refer to this crate as `::openai` in its own tests.
+#[cfg(test)]
+extern crate self as openai;
+
+/// Adds setters for the per-request options of a request builder: its `headers: HeaderMap` and
+/// `extra_body: serde_json::Map<String, Value>` fields, which have custom setters.
+/// The builder stores `headers` as [`RequestHeaders`](crate::client::RequestHeaders).
+macro_rules! request_builder_setters {
+    ($builder:ty) => {
+        impl $builder {
+            /// Sends this request for the given organization, instead of the client's.
+            ///
+            /// If `organization` is not a valid header value, `build()` fails with
+            /// [`OpenAiError::Validation`](crate::OpenAiError::Validation).
+            pub fn organization(mut self, organization: &str) -> Self {
+                self.headers
+                    .insert_str(crate::client::ORGANIZATION_HEADER, organization);
+                self
+            }
+
+            /// Sends this request for the given project, instead of the client's.
+            ///
+            /// If `project` is not a valid header value, `build()` fails with
+            /// [`OpenAiError::Validation`](crate::OpenAiError::Validation).
+            pub fn project(mut self, project: &str) -> Self {
+                self.headers
+                    .insert_str(crate::client::PROJECT_HEADER, project);
+                self
+            }
+
+            /// Adds a header to this request, replacing the client's header of the same name.
+            pub fn header(
+                mut self,
+                name: reqwest::header::HeaderName,
+                value: reqwest::header::HeaderValue,
+            ) -> Self {
+                self.headers.insert(name, value);
+                self
+            }
+
+            /// Adds parameters to the body of this request, such as parameters this crate
+            /// doesn't support yet, or those of compatible servers.
+            ///
+            /// They are sent alongside the request's own fields,
+            /// and replace the fields they share a name with.
+            pub fn extra_body(
+                mut self,
+                extra_body: serde_json::Map<String, serde_json::Value>,
+            ) -> Self {
+                self.extra_body
+                    .get_or_insert_with(serde_json::Map::new)
+                    .extend(extra_body);
+                self
+            }
+
+            /// Adds a parameter to the body of this request,
+            /// as [`extra_body`](Self::extra_body).
+            pub fn extra_param(
+                mut self,
+                name: impl Into<String>,
+                value: impl Into<serde_json::Value>,
+            ) -> Self {
+                self.extra_body
+                    .get_or_insert_with(serde_json::Map::new)
+                    .insert(name.into(), value.into());
+                self
+            }
+        }
+    };
+}
+
+pub mod accounting;
+pub mod agent;
+pub mod azure;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod cassette;
 pub mod chat;
+pub mod client;
 pub mod completions;
 pub mod edits;
 pub mod embeddings;
+pub mod error;
 pub mod files;
+pub mod meta;
+pub mod middleware;
+#[cfg(feature = "mock-server")]
+pub mod mock;
 pub mod models;
 pub mod moderations;
+pub mod pagination;
+pub mod pricing;
+pub mod rate_limit;
+pub mod retry;
+mod runtime;
+pub mod schema;
 pub mod threads;
-
-static API_KEY: Mutex<String> = Mutex::new(String::new());
-static BASE_URL: Mutex<String> = Mutex::new(String::new());
-
-#[derive(Deserialize, Debug, Clone)]
-pub struct OpenAiError {
-    pub message: String,
-    #[serde(rename = "type")]
-    pub error_type: String,
-    pub param: Option<String>,
-    pub code: Option<String>,
-}
-
-impl OpenAiError {
-    fn new(message: String, error_type: String) -> OpenAiError {
-        OpenAiError {
-            message,
-            error_type,
-            param: None,
-            code: None,
-        }
-    }
-}
-
-impl std::fmt::Display for OpenAiError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.message)
-    }
-}
-
-impl std::error::Error for OpenAiError {}
-
-#[derive(Deserialize, Clone)]
-#[serde(untagged)]
-pub enum ApiResponse<T> {
-    Ok(T),
-    Err { error: OpenAiError },
-}
+#[cfg(feature = "tokenizer")]
+pub mod tokenizer;
 
 #[derive(Deserialize, Clone, Copy, Debug)]
 pub struct Usage {
     pub prompt_tokens: u32,
     pub completion_tokens: u32,
     pub total_tokens: u32,
+    /// A breakdown of the prompt tokens, reported by newer models.
+    #[serde(default)]
+    pub prompt_tokens_details: Option<PromptTokensDetails>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default)]
+pub struct PromptTokensDetails {
+    /// Prompt tokens read from the prompt cache.
+    #[serde(default)]
+    pub cached_tokens: u32,
 }
 
 pub type ApiResponseOrError<T> = Result<T, OpenAiError>;
 
-impl From<reqwest::Error> for OpenAiError {
-    fn from(value: reqwest::Error) -> Self {
-        OpenAiError::new(value.to_string(), "reqwest".to_string())
-    }
+async fn openai_request_json<F, T>(
+    client: &OpenAiClient,
+    method: Method,
+    route: &str,
+    builder: F,
+) -> ApiResponseOrError<T>
+where
+    F: Fn(RequestBuilder) -> RequestBuilder,
+    T: DeserializeOwned + HasResponseMeta,
+{
+    let response = openai_request(client, method, route, builder).await?;
+    let meta = ResponseMeta::from_headers(response.status(), response.headers());
+    let body = read_body(response, client.http_config().read_timeout).await?;
+    let mut object: T = OpenAiError::parse_body(meta.status, meta.request_id.clone(), body)?;
+    object.set_meta(meta);
+    Ok(object)
 }
 
-impl From<std::io::Error> for OpenAiError {
-    fn from(value: std::io::Error) -> Self {
-        OpenAiError::new(value.to_string(), "io".to_string())
+/// Reads the body of `response`, failing with [`OpenAiError::ReadTimeout`]
+/// if no chunk of it arrives for `read_timeout`.
+async fn read_body(
+    response: Response,
+    read_timeout: Option<Duration>,
+) -> ApiResponseOrError<String> {
+    let chunks = response.bytes_stream().map(|chunk| Ok(chunk?));
+    let mut chunks: BoxStream<'static, _> = match read_timeout {
+        Some(read_timeout) => Box::pin(with_idle_timeout(chunks, read_timeout)),
+        None => Box::pin(chunks),
+    };
+    let mut body = Vec::new();
+    while let Some(chunk) = chunks.next().await {
+        body.extend_from_slice(&chunk?);
     }
+    Ok(String::from_utf8_lossy(&body).into_owned())
 }
 
-async fn openai_request_json<F, T>(method: Method, route: &str, builder: F) -> ApiResponseOrError<T>
+/// Sends a request through the client's [`Middleware`](middleware::Middleware) chain,
+/// retrying it according to the client's [`RetryPolicy`](retry::RetryPolicy).
+/// Responses with an error status are returned as [`OpenAiError::Api`].
+///
+/// `builder` is called once per attempt, so it must be able to rebuild the request body.
+async fn openai_request<F>(
+    client: &OpenAiClient,
+    method: Method,
+    route: &str,
+    builder: F,
+) -> ApiResponseOrError<Response>
 where
-    F: FnOnce(RequestBuilder) -> RequestBuilder,
-    T: DeserializeOwned,
+    F: Fn(RequestBuilder) -> RequestBuilder,
 {
-    let api_response = openai_request(method, route, builder).await?.json().await?;
-    match api_response {
-        ApiResponse::Ok(t) => Ok(t),
-        ApiResponse::Err { error } => Err(error),
-    }
+    let config = client.http_config();
+    send_request(
+        client,
+        method,
+        route,
+        builder,
+        config.timeout,
+        config.read_timeout,
+    )
+    .await
 }
 
-async fn openai_request<F>(method: Method, route: &str, builder: F) -> ApiResponseOrError<Response>
+/// Sends a request with the given total and read timeouts, retrying it as needed.
+async fn send_request<F>(
+    client: &OpenAiClient,
+    method: Method,
+    route: &str,
+    builder: F,
+    timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+) -> ApiResponseOrError<Response>
 where
-    F: FnOnce(RequestBuilder) -> RequestBuilder,
+    F: Fn(RequestBuilder) -> RequestBuilder,
 {
-    let client = Client::new();
-    let mut request = client.request(method, get_base_url().lock().unwrap().to_owned() + route);
+    let policy = client.retry_policy();
+    let mut attempt = 1;
+    loop {
+        let request = builder(client.request(method.clone(), route)).build()?;
+        let request = with_timeout(request, timeout);
+        let next = Next::new(client.http_client(), client.middlewares());
+        let delay = match with_read_timeout(read_timeout, next.run(request)).await {
+            Ok(response) => {
+                match policy.retry_response(attempt, response.status(), response.headers()) {
+                    Some(delay) => delay,
+                    None if response.status().is_success() => return Ok(response),
+                    None => return Err(OpenAiError::from_response(response).await),
+                }
+            }
+            Err(OpenAiError::Transport(error) | OpenAiError::Timeout(error)) => {
+                match policy.retry_error(attempt, &error) {
+                    Some(delay) => delay,
+                    None => return Err(error.into()),
+                }
+            }
+            Err(OpenAiError::ReadTimeout(timeout)) => match policy.retry_timeout(attempt) {
+                Some(delay) => delay,
+                None => return Err(OpenAiError::ReadTimeout(timeout)),
+            },
+            Err(error) => return Err(error),
+        };
+        runtime::sleep(delay).await;
+        attempt += 1;
+    }
+}
 
-    request = builder(request);
+/// Sets the total timeout of a request, which the browser's fetch can't enforce on `wasm32`.
+#[cfg(not(target_arch = "wasm32"))]
+fn with_timeout(mut request: reqwest::Request, timeout: Option<Duration>) -> reqwest::Request {
+    if timeout.is_some() {
+        *request.timeout_mut() = timeout;
+    }
+    request
+}
 
-    let response = request
-        .header(AUTHORIZATION, format!("Bearer {}", API_KEY.lock().unwrap()))
-        .send()
-        .await?;
-    Ok(response)
+#[cfg(target_arch = "wasm32")]
+fn with_timeout(request: reqwest::Request, _timeout: Option<Duration>) -> reqwest::Request {
+    request
 }
 
+/// Fails with [`OpenAiError::ReadTimeout`] if `future` takes longer than `read_timeout`.
+async fn with_read_timeout<T>(
+    read_timeout: Option<Duration>,
+    future: impl Future<Output = ApiResponseOrError<T>>,
+) -> ApiResponseOrError<T> {
+    match read_timeout {
+        Some(read_timeout) => runtime::timeout(read_timeout, future)
+            .await
+            .unwrap_or(Err(OpenAiError::ReadTimeout(read_timeout))),
+        None => future.await,
+    }
+}
+
+/// Sends a request and reads the response as server-sent events,
+/// along with the metadata of the response.
+///
+/// The request is only retried until a successful response arrives.
 async fn openai_request_stream<F>(
+    client: &OpenAiClient,
     method: Method,
     route: &str,
     builder: F,
-) -> Result<EventSource, CannotCloneRequestError>
+) -> ApiResponseOrError<(
+    ResponseMeta,
+    EventStream<BoxStream<'static, ApiResponseOrError<Bytes>>>,
+)>
 where
-    F: FnOnce(RequestBuilder) -> RequestBuilder,
+    F: Fn(RequestBuilder) -> RequestBuilder,
 {
-    let client = Client::new();
-    let mut request = client.request(method, get_base_url().lock().unwrap().to_owned() + route);
+    let config = client.http_config();
+    let response = send_request(
+        client,
+        method,
+        route,
+        builder,
+        config.stream_timeout,
+        config.stream_read_timeout,
+    )
+    .await?;
+    let meta = ResponseMeta::from_headers(response.status(), response.headers());
+    let chunks = response.bytes_stream().map(|chunk| Ok(chunk?));
+    let chunks: BoxStream<'static, _> = match config.stream_read_timeout {
+        Some(read_timeout) => Box::pin(with_idle_timeout(chunks, read_timeout)),
+        None => Box::pin(chunks),
+    };
+    Ok((meta, chunks.eventsource()))
+}
 
-    request = builder(request);
+/// Ends `chunks` with an [`OpenAiError::ReadTimeout`] if no chunk arrives for `read_timeout`.
+fn with_idle_timeout<S>(
+    chunks: S,
+    read_timeout: Duration,
+) -> impl Stream<Item = ApiResponseOrError<Bytes>>
+where
+    S: Stream<Item = ApiResponseOrError<Bytes>> + MaybeSend + 'static,
+{
+    let chunks: BoxStream<'static, _> = Box::pin(chunks);
+    stream::unfold(Some(chunks), move |chunks| async move {
+        let mut chunks = chunks?;
+        match runtime::timeout(read_timeout, chunks.next()).await {
+            Some(Some(chunk)) => Some((chunk, Some(chunks))),
+            Some(None) => None,
+            None => Some((Err(OpenAiError::ReadTimeout(read_timeout)), None)),
+        }
+    })
+}
 
-    let stream = request
-        .header(AUTHORIZATION, format!("Bearer {}", API_KEY.lock().unwrap()))
-        .eventsource()?;
+async fn openai_get<T>(client: &OpenAiClient, route: &str) -> ApiResponseOrError<T>
+where
+    T: DeserializeOwned + HasResponseMeta,
+{
+    openai_request_json(client, Method::GET, route, |request| request).await
+}
 
-    Ok(stream)
+async fn openai_delete<T>(client: &OpenAiClient, route: &str) -> ApiResponseOrError<T>
+where
+    T: DeserializeOwned + HasResponseMeta,
+{
+    openai_request_json(client, Method::DELETE, route, |request| request).await
 }
 
-async fn openai_get<T>(route: &str) -> ApiResponseOrError<T>
+async fn openai_post<J, T>(client: &OpenAiClient, route: &str, json: &J) -> ApiResponseOrError<T>
 where
-    T: DeserializeOwned,
+    J: Serialize + ?Sized,
+    T: DeserializeOwned + HasResponseMeta,
 {
-    openai_request_json(Method::GET, route, |request| request).await
+    let body = json_body(json)?;
+    openai_request_json(client, Method::POST, route, |request| request.json(&body)).await
+}
+
+/// Serializes the body of a request. A parameter of its flattened `extra_body` replaces the
+/// request's own field of the same name, instead of repeating the key in the JSON.
+fn json_body<J: Serialize + ?Sized>(json: &J) -> ApiResponseOrError<serde_json::Value> {
+    // Parsing keeps the last of repeated keys. Going through text, rather than `to_value`,
+    // keeps `f32` parameters such as `0.2` from being widened to `0.20000000298023224`.
+    serde_json::to_vec(json)
+        .and_then(|body| serde_json::from_slice(&body))
+        .map_err(|error| OpenAiError::Validation(error.to_string()))
 }
 
-async fn openai_delete<T>(route: &str) -> ApiResponseOrError<T>
+/// Posts a request to `endpoint` that uses tokens of a model, first checking the budget of the
+/// client's [`UsageTracker`](accounting::UsageTracker) and waiting for capacity in its
+/// [`RateLimiter`](rate_limit::RateLimiter), then counting the tokens used.
+async fn openai_post_metered<J, T>(
+    client: &OpenAiClient,
+    endpoint: &str,
+    route: &str,
+    request: &J,
+) -> ApiResponseOrError<T>
 where
-    T: DeserializeOwned,
+    J: Serialize + TokenEstimate,
+    T: DeserializeOwned + HasResponseMeta + TokenUsage,
 {
-    openai_request_json(Method::DELETE, route, |request| request).await
+    check_budget(client)?;
+    let mut reservation = wait_for_rate_limit(client, request).await;
+    let response: T = openai_post(client, route, request).await?;
+    if let Some(tokens) = response.token_counts() {
+        record_usage(client, usage_model(request, &response), endpoint, tokens);
+        if let Some(reservation) = &mut reservation {
+            reservation.set_used(tokens.input + tokens.output);
+        }
+    }
+    Ok(response)
+}
+
+/// Fails if the budget of the client's usage tracker has been reached.
+fn check_budget(client: &OpenAiClient) -> ApiResponseOrError<()> {
+    match client.usage_tracker() {
+        Some(usage_tracker) => usage_tracker.check_budget(),
+        None => Ok(()),
+    }
 }
 
-async fn openai_post<J, T>(route: &str, json: &J) -> ApiResponseOrError<T>
+/// The model to price the usage of a response with: the one the response reports, falling back
+/// on the model of the request for responses that don't report one.
+fn usage_model<'a>(request: &'a impl TokenEstimate, response: &'a impl TokenUsage) -> &'a str {
+    match response.model() {
+        "" => request.model(),
+        model => model,
+    }
+}
+
+/// Counts tokens in the client's usage tracker, if it has one.
+fn record_usage(client: &OpenAiClient, model: &str, endpoint: &str, tokens: TokenCounts) {
+    if let Some(usage_tracker) = client.usage_tracker() {
+        usage_tracker.record(model, endpoint, client.usage_tag(), tokens);
+    }
+}
+
+/// Waits for capacity in the client's rate limiter, returning the tokens taken from it.
+/// Dropping the reservation without recording the usage gives them all back.
+async fn wait_for_rate_limit<J>(client: &OpenAiClient, request: &J) -> Option<Reservation>
 where
-    J: Serialize + ?Sized,
-    T: DeserializeOwned,
+    J: TokenEstimate,
 {
-    openai_request_json(Method::POST, route, |request| request.json(json)).await
+    let rate_limiter = client.rate_limiter()?;
+    Some(
+        rate_limiter
+            .reserve(
+                request.model(),
+                request.estimate_prompt_tokens(),
+                request.estimate_completion_tokens(),
+            )
+            .await,
+    )
 }
 
-async fn openai_post_multipart<T>(route: &str, form: Form) -> ApiResponseOrError<T>
+/// Posts a multipart form. `form` is called once per attempt, since forms can't be cloned.
+async fn openai_post_multipart<F, T>(
+    client: &OpenAiClient,
+    route: &str,
+    form: F,
+) -> ApiResponseOrError<T>
 where
-    T: DeserializeOwned,
+    F: Fn() -> Form,
+    T: DeserializeOwned + HasResponseMeta,
 {
-    openai_request_json(Method::POST, route, |request| request.multipart(form)).await
+    openai_request_json(client, Method::POST, route, |request| {
+        request.multipart(form())
+    })
+    .await
 }
 
-/// Sets the key for all OpenAI API functions.
+/// Sets the key of the default client, used by requests that were not given an [`OpenAiClient`].
 ///
 /// ## Examples
 ///
 /// Use environment variable `OPENAI_KEY` defined from `.env` file:
 ///
-/// ```rust
+/// ```no_run
 /// use openai::set_key;
 /// use dotenvy::dotenv;
 /// use std::env;
@@ -165,10 +456,11 @@ where
 /// set_key(env::var("OPENAI_KEY").unwrap());
 /// ```
 pub fn set_key(value: String) {
-    *API_KEY.lock().unwrap() = value;
+    client::set_default_key(value);
 }
 
-/// Sets the base url for all OpenAI API functions.
+/// Sets the base url of the default client, used by requests that were not given an [`OpenAiClient`].
+/// Defaults to `https://api.openai.com/v1/`.
 ///
 /// ## Examples
 ///
@@ -183,63 +475,392 @@ pub fn set_key(value: String) {
 /// set_base_url(env::var("OPENAI_BASE_URL").unwrap_or_default());
 /// ```
 pub fn set_base_url(value: String) {
-    let base_url_mutex = get_base_url();
-    if value.is_empty() {
-        return;
-    }
-    let mut base_url = base_url_mutex.lock().unwrap();
-    *base_url = value;
-    if !base_url.ends_with('/') {
-        *base_url += "/";
-    }
-}
-
-/// Returns the base url for all OpenAI API functions.
-/// Defaults to `https://api.openai.com/v1/`.
-fn get_base_url() -> &'static Mutex<String> {
-    let mut base_url = BASE_URL.lock().unwrap();
-    if base_url.is_empty() {
-        *base_url = String::from("https://api.openai.com/v1/");
-    }
-    &BASE_URL
+    client::set_default_base_url(value);
 }
 
 #[cfg(test)]
 pub mod tests {
     use super::*;
+    use crate::cassette::Cassette;
+    use crate::client::HttpConfig;
+    use crate::retry::RetryPolicy;
+    use eventsource_stream::EventStreamError;
+    use serde_json::Value;
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
 
     pub const DEFAULT_LEGACY_MODEL: &str = "gpt-3.5-turbo-instruct";
 
-    #[test]
-    fn test_get_base_url_default() {
-        assert_eq!(
-            get_base_url().lock().unwrap().to_owned(),
-            String::from("https://api.openai.com/v1/")
+    /// Builds a raw HTTP response for [`serve_responses`].
+    pub fn http_response(status: u16, headers: &[(&str, &str)], body: &str) -> String {
+        let mut response = format!(
+            "HTTP/1.1 {status} {status}\r\ncontent-length: {}\r\nconnection: close\r\n",
+            body.len()
         );
+        for (name, value) in headers {
+            response += &format!("{name}: {value}\r\n");
+        }
+        response + "\r\n" + body
+    }
 
-        // empty env var
-        set_base_url(String::from(""));
-        assert_eq!(
-            get_base_url().lock().unwrap().to_owned(),
-            String::from("https://api.openai.com/v1/")
-        );
+    /// The body of a chat completion from `model` whose only choice is `message`.
+    pub fn chat_completion_json(model: &str, finish_reason: &str, message: Value) -> Value {
+        serde_json::json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 0,
+            "model": model,
+            "choices": [{ "index": 0, "finish_reason": finish_reason, "message": message }],
+            "usage": { "prompt_tokens": 9, "completion_tokens": 2, "total_tokens": 11 }
+        })
+    }
 
-        // appends slash
-        set_base_url(String::from("https://api.openai.com/v1"));
-        assert_eq!(
-            get_base_url().lock().unwrap().to_owned(),
-            String::from("https://api.openai.com/v1/")
-        );
+    /// A response to a chat completion request where the assistant replies with `content`.
+    pub fn chat_completion_response(model: &str, content: &str) -> String {
+        let message = serde_json::json!({ "role": "assistant", "content": content });
+        let body = chat_completion_json(model, "stop", message);
+        http_response(200, &[], &body.to_string())
+    }
+
+    /// The JSON body of a raw request received by [`serve_responses`].
+    pub fn request_body(request: &str) -> Value {
+        serde_json::from_str(request.split("\r\n\r\n").nth(1).unwrap()).unwrap()
+    }
+
+    /// Serves the given raw HTTP responses in order, one per connection, on a local port.
+    /// Returns the base url to point a client at and the raw requests received so far.
+    pub async fn serve_responses(responses: Vec<String>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}/v1/", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+        tokio::spawn(async move {
+            for response in responses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let request = read_request(&mut socket).await;
+                received.lock().unwrap().push(request);
+                socket.write_all(response.as_bytes()).await.unwrap();
+                socket.shutdown().await.ok();
+            }
+        });
+        (base_url, requests)
+    }
+
+    async fn read_request(socket: &mut tokio::net::TcpStream) -> String {
+        let mut request = Vec::new();
+        let mut buffer = [0; 4096];
+        loop {
+            let read = socket.read(&mut buffer).await.unwrap();
+            request.extend_from_slice(&buffer[..read]);
+            let text = String::from_utf8_lossy(&request);
+            if let Some(header_end) = text.find("\r\n\r\n") {
+                let headers = text[..header_end].to_lowercase();
+                let body = &request[header_end + 4..];
+                let complete = match headers
+                    .lines()
+                    .find_map(|line| line.strip_prefix("content-length:"))
+                {
+                    Some(length) => body.len() >= length.trim().parse::<usize>().unwrap(),
+                    None if headers.contains("transfer-encoding: chunked") => {
+                        body.ends_with(b"0\r\n\r\n")
+                    }
+                    None => true,
+                };
+                if complete {
+                    return text.into_owned();
+                }
+            }
+            if read == 0 {
+                return String::from_utf8_lossy(&request).into_owned();
+            }
+        }
+    }
+
+    /// Answers every connection with `response_start`, then keeps it open without sending more.
+    /// Returns the base url to point a client at and the number of connections accepted.
+    pub async fn serve_stalled(response_start: String) -> (String, Arc<Mutex<usize>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}/v1/", listener.local_addr().unwrap());
+        let connections = Arc::new(Mutex::new(0));
+        let accepted = connections.clone();
+        tokio::spawn(async move {
+            let mut sockets = Vec::new();
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                *accepted.lock().unwrap() += 1;
+                read_request(&mut socket).await;
+                socket.write_all(response_start.as_bytes()).await.unwrap();
+                sockets.push(socket);
+            }
+        });
+        (base_url, connections)
     }
 
-    #[test]
-    fn test_get_base_url_set() {
-        set_base_url(String::from("https://api.openai.com/v2/"));
-        assert_eq!(
-            get_base_url().lock().unwrap().to_owned(),
-            String::from("https://api.openai.com/v2/")
+    /// A client for tests of the real API, which replays the cassette
+    /// `test_data/cassettes/{name}.json`. The checked-in cassettes are synthetic fixtures, so
+    /// these tests check requests and parsing, not the API's current behavior. When
+    /// `OPENAI_RECORD` is set, the cassette is recorded against the real API instead, with a
+    /// client configured from the environment.
+    pub fn cassette_client(name: &str) -> OpenAiClient {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("test_data/cassettes")
+            .join(format!("{name}.json"));
+        if recording() {
+            dotenvy::dotenv().ok();
+            return OpenAiClient::from_env()
+                .unwrap()
+                .with_middleware(Cassette::record(path));
+        }
+        OpenAiClient::new("sk-test")
+            .with_base_url("http://127.0.0.1:9/v1/")
+            .with_middleware(Cassette::replay(path).unwrap())
+    }
+
+    /// Whether the tests of the real API are recording their cassettes.
+    pub fn recording() -> bool {
+        std::env::var_os("OPENAI_RECORD").is_some()
+    }
+
+    pub fn test_retry_policy() -> RetryPolicy {
+        RetryPolicy {
+            base_delay: Duration::from_millis(1),
+            ..RetryPolicy::default()
+        }
+    }
+
+    #[derive(Deserialize)]
+    struct TestObject {
+        id: String,
+        #[serde(skip)]
+        meta: Option<ResponseMeta>,
+    }
+
+    impl HasResponseMeta for TestObject {
+        fn set_meta(&mut self, meta: ResponseMeta) {
+            self.meta = Some(meta);
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors() {
+        let (base_url, requests) = serve_responses(vec![
+            http_response(
+                500,
+                &[],
+                r#"{"error":{"message":"boom","type":"server_error","param":null,"code":null}}"#,
+            ),
+            http_response(429, &[("retry-after-ms", "1")], "{}"),
+            http_response(200, &[("x-request-id", "req_3")], r#"{"id":"model"}"#),
+        ])
+        .await;
+        let client = OpenAiClient::new("key")
+            .with_base_url(base_url)
+            .with_retry_policy(test_retry_policy());
+
+        let object: TestObject = openai_get(&client, "models/model").await.unwrap();
+
+        assert_eq!(object.id, "model");
+        assert_eq!(object.meta.unwrap().request_id.as_deref(), Some("req_3"));
+        assert_eq!(requests.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_by_default() {
+        let (base_url, requests) = serve_responses(vec![http_response(
+            500,
+            &[],
+            r#"{"error":{"message":"boom","type":"server_error","param":null,"code":null}}"#,
+        )])
+        .await;
+        let client = OpenAiClient::new("key").with_base_url(base_url);
+
+        let error = openai_get::<TestObject>(&client, "models/model")
+            .await
+            .err()
+            .unwrap();
+
+        match error {
+            OpenAiError::Api(error) => {
+                assert_eq!(error.status, 500);
+                assert_eq!(error.message, "boom");
+                assert_eq!(error.error_type.as_deref(), Some("server_error"));
+            }
+            error => panic!("unexpected error: {error:?}"),
+        }
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_successful_responses() {
+        let (base_url, requests) = serve_responses(vec![
+            http_response(200, &[("x-should-retry", "true")], r#"{"id":"first"}"#),
+            http_response(200, &[], r#"{"id":"second"}"#),
+        ])
+        .await;
+        let client = OpenAiClient::new("key")
+            .with_base_url(base_url)
+            .with_retry_policy(test_retry_policy());
+
+        let object: TestObject = openai_post(&client, "models", &()).await.unwrap();
+
+        assert_eq!(object.id, "first");
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_attempts() {
+        let error =
+            r#"{"error":{"message":"slow down","type":"requests","param":null,"code":null}}"#;
+        let (base_url, requests) = serve_responses(vec![
+            http_response(429, &[], error),
+            http_response(429, &[], error),
+            http_response(429, &[], error),
+        ])
+        .await;
+        let client = OpenAiClient::new("key")
+            .with_base_url(base_url)
+            .with_retry_policy(test_retry_policy());
+
+        let error = openai_get::<TestObject>(&client, "models/model")
+            .await
+            .err()
+            .unwrap();
+
+        assert_eq!(error.status(), Some(reqwest::StatusCode::TOO_MANY_REQUESTS));
+        assert_eq!(error.to_string(), "slow down (429 Too Many Requests)");
+        assert_eq!(requests.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn sends_user_agent() {
+        let (base_url, requests) =
+            serve_responses(vec![http_response(200, &[], r#"{"id":"model"}"#)]).await;
+        let client = OpenAiClient::new("key")
+            .with_base_url(base_url)
+            .with_http_config(HttpConfig {
+                user_agent: Some("my-app/1.0".to_string()),
+                connect_timeout: Some(Duration::from_secs(1)),
+                ..HttpConfig::default()
+            })
+            .unwrap();
+
+        openai_get::<TestObject>(&client, "models/model")
+            .await
+            .unwrap();
+
+        assert!(requests.lock().unwrap()[0].contains("user-agent: my-app/1.0\r\n"));
+    }
+
+    #[tokio::test]
+    async fn read_timeout_retries_stalled_responses() {
+        let (base_url, connections) = serve_stalled(String::new()).await;
+        let client = OpenAiClient::new("key")
+            .with_base_url(base_url)
+            .with_retry_policy(RetryPolicy {
+                max_attempts: 2,
+                ..test_retry_policy()
+            })
+            .with_http_config(HttpConfig {
+                read_timeout: Some(Duration::from_millis(50)),
+                ..HttpConfig::default()
+            })
+            .unwrap();
+
+        let error = openai_get::<TestObject>(&client, "models/model")
+            .await
+            .err()
+            .unwrap();
+
+        assert!(
+            matches!(error, OpenAiError::ReadTimeout(timeout) if timeout == Duration::from_millis(50))
         );
-        // need this here to reset the base url for other tests
-        set_base_url(String::from("https://api.openai.com/v1"));
+        assert_eq!(*connections.lock().unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn read_timeout_applies_between_body_chunks() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}/v1/", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            read_request(&mut socket).await;
+            let body = r#"{"id":"trickled"}"#;
+            let head = format!("HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n", body.len());
+            socket.write_all(head.as_bytes()).await.unwrap();
+            for byte in body.bytes() {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                socket.write_all(&[byte]).await.unwrap();
+            }
+        });
+        let client = OpenAiClient::new("key")
+            .with_base_url(base_url)
+            .with_http_config(HttpConfig {
+                read_timeout: Some(Duration::from_millis(200)),
+                ..HttpConfig::default()
+            })
+            .unwrap();
+
+        let object = openai_get::<TestObject>(&client, "models/model")
+            .await
+            .unwrap();
+
+        assert_eq!(object.id, "trickled");
+    }
+
+    #[tokio::test]
+    async fn read_timeout_ends_stalled_bodies() {
+        let (base_url, _) =
+            serve_stalled("HTTP/1.1 200 OK\r\ncontent-length: 100\r\n\r\n{\"id\"".to_string())
+                .await;
+        let client = OpenAiClient::new("key")
+            .with_base_url(base_url)
+            .with_http_config(HttpConfig {
+                read_timeout: Some(Duration::from_millis(50)),
+                ..HttpConfig::default()
+            })
+            .unwrap();
+
+        let error = openai_get::<TestObject>(&client, "models/model")
+            .await
+            .err()
+            .unwrap();
+
+        assert!(matches!(error, OpenAiError::ReadTimeout(_)));
+    }
+
+    #[tokio::test]
+    async fn stream_read_timeout_ends_stalled_streams() {
+        let event = "data: {}\n\n";
+        let (base_url, _) = serve_stalled(format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\ntransfer-encoding: chunked\r\n\r\n{:x}\r\n{event}\r\n",
+            event.len()
+        ))
+        .await;
+        let client = OpenAiClient::new("key")
+            .with_base_url(base_url)
+            .with_http_config(HttpConfig {
+                // Streams are not limited by the timeouts of regular requests.
+                read_timeout: Some(Duration::from_millis(1)),
+                stream_read_timeout: Some(Duration::from_millis(100)),
+                ..HttpConfig::default()
+            })
+            .unwrap();
+
+        let (_, mut events) =
+            openai_request_stream(&client, Method::POST, "chat/completions", |request| request)
+                .await
+                .unwrap();
+
+        assert_eq!(events.next().await.unwrap().unwrap().data, "{}");
+        assert!(matches!(
+            events.next().await,
+            Some(Err(EventStreamError::Transport(OpenAiError::ReadTimeout(
+                _
+            ))))
+        ));
+        assert!(events.next().await.is_none());
     }
 }
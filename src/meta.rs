@@ -0,0 +1,183 @@
+//! Metadata read from the headers of API responses.
+//!
+//! Every response object has a `meta` field holding the [`ResponseMeta`] of the response it
+//! was parsed from. Streamed chat completions carry it on their first delta.
+//!
+//! ```no_run
+//! use openai::models::Model;
+//!
+//! # async fn example() -> openai::ApiResponseOrError<()> {
+//! let model = Model::from("gpt-3.5-turbo").await?;
+//! let meta = model.meta.unwrap();
+//! println!("request id: {:?}", meta.request_id);
+//! println!("requests left: {:?}", meta.rate_limit.remaining_requests);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::time::Duration;
+
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+
+const REQUEST_ID_HEADER: &str = "x-request-id";
+const ORGANIZATION_HEADER: &str = "openai-organization";
+const PROCESSING_MS_HEADER: &str = "openai-processing-ms";
+const LIMIT_REQUESTS_HEADER: &str = "x-ratelimit-limit-requests";
+const LIMIT_TOKENS_HEADER: &str = "x-ratelimit-limit-tokens";
+const REMAINING_REQUESTS_HEADER: &str = "x-ratelimit-remaining-requests";
+const REMAINING_TOKENS_HEADER: &str = "x-ratelimit-remaining-tokens";
+const RESET_REQUESTS_HEADER: &str = "x-ratelimit-reset-requests";
+const RESET_TOKENS_HEADER: &str = "x-ratelimit-reset-tokens";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResponseMeta {
+    /// The HTTP status of the response.
+    pub status: StatusCode,
+    /// The `x-request-id` header, useful for support requests.
+    pub request_id: Option<String>,
+    /// The organization the request was billed to.
+    pub organization: Option<String>,
+    /// How long the API took to process the request.
+    pub processing_time: Option<Duration>,
+    /// The rate limits of the account at the time of the response.
+    pub rate_limit: RateLimitInfo,
+}
+
+/// Rate limit headers of a response.
+/// See the [rate limits guide](https://platform.openai.com/docs/guides/rate-limits/rate-limits-in-headers).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimitInfo {
+    /// The maximum number of requests permitted before exhausting the rate limit.
+    pub limit_requests: Option<u64>,
+    /// The maximum number of tokens permitted before exhausting the rate limit.
+    pub limit_tokens: Option<u64>,
+    /// The remaining number of requests permitted before exhausting the rate limit.
+    pub remaining_requests: Option<u64>,
+    /// The remaining number of tokens permitted before exhausting the rate limit.
+    pub remaining_tokens: Option<u64>,
+    /// The time until the request rate limit resets to its initial state.
+    pub reset_requests: Option<Duration>,
+    /// The time until the token rate limit resets to its initial state.
+    pub reset_tokens: Option<Duration>,
+}
+
+impl ResponseMeta {
+    /// Reads the metadata of a response from its status and headers.
+    pub fn from_headers(status: StatusCode, headers: &HeaderMap) -> Self {
+        ResponseMeta {
+            status,
+            request_id: header_str(headers, REQUEST_ID_HEADER).map(str::to_string),
+            organization: header_str(headers, ORGANIZATION_HEADER).map(str::to_string),
+            processing_time: header_str(headers, PROCESSING_MS_HEADER)
+                .and_then(|millis| millis.parse().ok())
+                .map(Duration::from_millis),
+            rate_limit: RateLimitInfo {
+                limit_requests: header_u64(headers, LIMIT_REQUESTS_HEADER),
+                limit_tokens: header_u64(headers, LIMIT_TOKENS_HEADER),
+                remaining_requests: header_u64(headers, REMAINING_REQUESTS_HEADER),
+                remaining_tokens: header_u64(headers, REMAINING_TOKENS_HEADER),
+                reset_requests: header_str(headers, RESET_REQUESTS_HEADER).and_then(parse_duration),
+                reset_tokens: header_str(headers, RESET_TOKENS_HEADER).and_then(parse_duration),
+            },
+        }
+    }
+}
+
+/// Response objects that carry the [`ResponseMeta`] of the response they were parsed from.
+pub(crate) trait HasResponseMeta {
+    fn set_meta(&mut self, meta: ResponseMeta);
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn header_u64(headers: &HeaderMap, name: &str) -> Option<u64> {
+    header_str(headers, name).and_then(|value| value.trim().parse().ok())
+}
+
+/// Parses durations such as `1s`, `6m0s`, `20ms` or `1h2m3.5s`.
+fn parse_duration(value: &str) -> Option<Duration> {
+    let mut rest = value.trim();
+    if rest.is_empty() {
+        return None;
+    }
+    let mut total = Duration::ZERO;
+    while !rest.is_empty() {
+        let number_end = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        let number: f64 = rest[..number_end].parse().ok()?;
+        rest = &rest[number_end..];
+        let unit_end = rest
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(rest.len());
+        let seconds = match &rest[..unit_end] {
+            "h" => number * 3600.0,
+            "m" => number * 60.0,
+            "s" => number,
+            "ms" => number / 1000.0,
+            "us" | "µs" => number / 1_000_000.0,
+            "ns" => number / 1_000_000_000.0,
+            _ => return None,
+        };
+        total += Duration::try_from_secs_f64(seconds).ok()?;
+        rest = &rest[unit_end..];
+    }
+    Some(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("1s"), Some(Duration::from_secs(1)));
+        assert_eq!(parse_duration("6m0s"), Some(Duration::from_secs(360)));
+        assert_eq!(parse_duration("20ms"), Some(Duration::from_millis(20)));
+        assert_eq!(
+            parse_duration("1h2m3.5s"),
+            Some(Duration::from_millis(3_723_500))
+        );
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("soon"), None);
+    }
+
+    #[test]
+    fn from_headers() {
+        let mut headers = HeaderMap::new();
+        for (name, value) in [
+            ("x-request-id", "req_123"),
+            ("openai-organization", "org-abc"),
+            ("openai-processing-ms", "250"),
+            ("x-ratelimit-limit-requests", "10000"),
+            ("x-ratelimit-limit-tokens", "1000000"),
+            ("x-ratelimit-remaining-requests", "9999"),
+            ("x-ratelimit-remaining-tokens", "999990"),
+            ("x-ratelimit-reset-requests", "6ms"),
+            ("x-ratelimit-reset-tokens", "1m30s"),
+        ] {
+            headers.insert(name, HeaderValue::from_static(value));
+        }
+
+        let meta = ResponseMeta::from_headers(StatusCode::OK, &headers);
+
+        assert_eq!(meta.request_id.as_deref(), Some("req_123"));
+        assert_eq!(meta.organization.as_deref(), Some("org-abc"));
+        assert_eq!(meta.processing_time, Some(Duration::from_millis(250)));
+        assert_eq!(
+            meta.rate_limit,
+            RateLimitInfo {
+                limit_requests: Some(10000),
+                limit_tokens: Some(1000000),
+                remaining_requests: Some(9999),
+                remaining_tokens: Some(999990),
+                reset_requests: Some(Duration::from_millis(6)),
+                reset_tokens: Some(Duration::from_secs(90)),
+            }
+        );
+    }
+}
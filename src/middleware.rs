@@ -0,0 +1,216 @@
+//! Hooks around every request sent to the API.
+//!
+//! A [`Middleware`] receives each outgoing [`Request`] along with the rest of the chain as
+//! [`Next`]. It can change the request, including its headers and body, before passing it on
+//! with [`Next::run`], and inspect the response or error that comes back. It can also answer
+//! without calling the rest of the chain at all.
+//!
+//! Middlewares are registered on an [`OpenAiClient`](crate::OpenAiClient) and run in the order
+//! they were added: the first one sees the request first and the response last. They run once
+//! per attempt, so requests retried by the [`RetryPolicy`](crate::retry::RetryPolicy) pass
+//! through them again. Responses with an error status are passed through the chain like any
+//! other response. For streams, the response body is the event stream, which should be left
+//! unread.
+//!
+//! On `wasm32`, requests are sent by the browser and the returned future doesn't need to be
+//! `Send`.
+//!
+//! ```
+//! use futures_util::future::BoxFuture;
+//! use openai::middleware::{Middleware, Next};
+//! use openai::{ApiResponseOrError, OpenAiClient};
+//! use reqwest::{Request, Response};
+//! use std::time::Instant;
+//!
+//! struct Latency;
+//!
+//! impl Middleware for Latency {
+//!     fn handle<'a>(
+//!         &'a self,
+//!         request: Request,
+//!         next: Next<'a>,
+//!     ) -> BoxFuture<'a, ApiResponseOrError<Response>> {
+//!         Box::pin(async move {
+//!             let url = request.url().clone();
+//!             let start = Instant::now();
+//!             let response = next.run(request).await;
+//!             println!("{url} took {:?}", start.elapsed());
+//!             response
+//!         })
+//!     }
+//! }
+//!
+//! let client = OpenAiClient::new("sk-...").with_middleware(Latency);
+//! ```
+
+use std::sync::Arc;
+
+use futures_util::FutureExt;
+use reqwest::{Client, Request, Response};
+
+use crate::runtime::BoxFuture;
+use crate::ApiResponseOrError;
+
+/// A hook around the requests sent by an [`OpenAiClient`](crate::OpenAiClient).
+pub trait Middleware: Send + Sync + 'static {
+    /// Handles a request, usually by passing it on to `next` and returning its response.
+    fn handle<'a>(
+        &'a self,
+        request: Request,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, ApiResponseOrError<Response>>;
+}
+
+/// The rest of a middleware chain, ending with sending the request.
+#[derive(Clone, Copy)]
+pub struct Next<'a> {
+    http: &'a Client,
+    middlewares: &'a [Arc<dyn Middleware>],
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(http: &'a Client, middlewares: &'a [Arc<dyn Middleware>]) -> Self {
+        Next { http, middlewares }
+    }
+
+    /// Passes the request on to the next middleware, or sends it if there are none left.
+    pub fn run(self, request: Request) -> BoxFuture<'a, ApiResponseOrError<Response>> {
+        match self.middlewares.split_first() {
+            Some((middleware, middlewares)) => middleware.handle(
+                request,
+                Next {
+                    http: self.http,
+                    middlewares,
+                },
+            ),
+            None => Box::pin(
+                self.http
+                    .execute(request)
+                    .map(|response| response.map_err(Into::into)),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{http_response, serve_responses, test_retry_policy};
+    use crate::{OpenAiClient, OpenAiError};
+    use reqwest::header::HeaderValue;
+    use reqwest::{Method, StatusCode};
+    use std::sync::Mutex;
+
+    /// Adds a trace header and redacts a secret from the body.
+    struct Redact {
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Middleware for Redact {
+        fn handle<'a>(
+            &'a self,
+            mut request: Request,
+            next: Next<'a>,
+        ) -> BoxFuture<'a, ApiResponseOrError<Response>> {
+            Box::pin(async move {
+                self.log.lock().unwrap().push("redact request".to_string());
+                request
+                    .headers_mut()
+                    .insert("x-trace-id", HeaderValue::from_static("trace-1"));
+                let body = request.body().and_then(|body| body.as_bytes()).unwrap();
+                let body = String::from_utf8_lossy(body).replace("hunter2", "[redacted]");
+                *request.body_mut() = Some(body.into());
+                let response = next.run(request).await;
+                self.log.lock().unwrap().push("redact response".to_string());
+                response
+            })
+        }
+    }
+
+    /// Records the status of every response.
+    struct Record {
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Middleware for Record {
+        fn handle<'a>(
+            &'a self,
+            request: Request,
+            next: Next<'a>,
+        ) -> BoxFuture<'a, ApiResponseOrError<Response>> {
+            Box::pin(async move {
+                let response = next.run(request).await;
+                let status = response.as_ref().map(|response| response.status().as_u16());
+                self.log.lock().unwrap().push(format!("status {status:?}"));
+                response
+            })
+        }
+    }
+
+    /// Refuses every request without sending it.
+    struct Refuse;
+
+    impl Middleware for Refuse {
+        fn handle<'a>(
+            &'a self,
+            _request: Request,
+            _next: Next<'a>,
+        ) -> BoxFuture<'a, ApiResponseOrError<Response>> {
+            Box::pin(async { Err(OpenAiError::Validation("refused".to_string())) })
+        }
+    }
+
+    #[tokio::test]
+    async fn runs_chain_in_order_for_each_attempt() {
+        let (base_url, requests) = serve_responses(vec![
+            http_response(500, &[], "{}"),
+            http_response(200, &[], "{}"),
+        ])
+        .await;
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let client = OpenAiClient::new("key")
+            .with_base_url(base_url)
+            .with_retry_policy(test_retry_policy())
+            .with_middleware(Redact { log: log.clone() })
+            .with_middleware(Record { log: log.clone() });
+
+        let response = crate::openai_request(&client, Method::POST, "moderations", |request| {
+            request.json(&serde_json::json!({ "input": "my password is hunter2" }))
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            *log.lock().unwrap(),
+            [
+                "redact request",
+                "status Ok(500)",
+                "redact response",
+                "redact request",
+                "status Ok(200)",
+                "redact response",
+            ]
+        );
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1].contains("x-trace-id: trace-1"));
+        assert!(requests[1].contains("my password is [redacted]"));
+        assert!(!requests[1].contains("hunter2"));
+    }
+
+    #[tokio::test]
+    async fn middleware_can_answer_without_sending() {
+        let (base_url, requests) = serve_responses(vec![http_response(200, &[], "{}")]).await;
+        let client = OpenAiClient::new("key")
+            .with_base_url(base_url)
+            .with_middleware(Refuse);
+
+        let error = crate::openai_request(&client, Method::GET, "models", |request| request)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(error, OpenAiError::Validation(message) if message == "refused"));
+        assert!(requests.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,787 @@
+//! A local stand-in for the OpenAI API, for development and tests without an account.
+//!
+//! [`MockServer`] serves the routes covered by this crate: chat completions (including streams),
+//! completions, embeddings, moderations, models, files and threads. Its answers are
+//! deterministic:
+//!
+//! - Chat completions and completions echo the last message or the prompt, unless replies were
+//!   queued with [`MockServer::push_reply`]. Streams send one event per word.
+//! - Embeddings are derived from a hash of the input, so equal inputs get equal vectors.
+//! - Moderations flag inputs containing the word `violence`.
+//! - Files and threads are kept in memory for the lifetime of the server.
+//!
+//! Errors are injected with [`MockServer::fail_next`], which answers the next request with the
+//! given [`Fault`] instead.
+//!
+//! This module requires the `mock-server` feature, which also builds the `openai-mock-server`
+//! binary. The binary serves on `127.0.0.1:8080`, or the address given as its first argument.
+//!
+//! ```no_run
+//! use openai::mock::{Fault, MockServer};
+//! use openai::models::Model;
+//!
+//! # async fn example() -> openai::ApiResponseOrError<()> {
+//! let server = MockServer::start().await?;
+//! openai::set_base_url(server.base_url().to_string());
+//! openai::set_key("mock-key".to_string());
+//!
+//! server.fail_next(Fault::RateLimit);
+//! assert!(Model::from("gpt-4").await.is_err());
+//! assert_eq!(Model::from("gpt-4").await?.id, "gpt-4");
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::{BTreeMap, VecDeque};
+use std::convert::Infallible;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use futures_util::stream;
+use hyper::header::CONTENT_TYPE;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde_json::{json, Value};
+use tokio::sync::oneshot;
+
+use crate::rate_limit::estimate_text_tokens;
+use crate::OpenAiClient;
+
+const EMBEDDING_DIMENSIONS: usize = 16;
+const FLAGGED_WORD: &str = "violence";
+
+/// A running mock server. It shuts down when dropped.
+pub struct MockServer {
+    base_url: String,
+    state: Arc<Mutex<MockState>>,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+/// An error the server answers a request with, instead of handling it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fault {
+    /// A `429 Too Many Requests` with a `retry-after-ms` header.
+    RateLimit,
+    /// A `500 Internal Server Error`.
+    ServerError,
+    /// A `200 OK` whose body is not valid JSON.
+    MalformedJson,
+    /// The given status, with an error object describing it.
+    Status(u16),
+}
+
+/// A request received by the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockRequest {
+    pub method: String,
+    /// The path of the request, relative to the base url.
+    pub route: String,
+    pub body: Vec<u8>,
+}
+
+#[derive(Default)]
+struct MockState {
+    faults: VecDeque<Fault>,
+    replies: VecDeque<String>,
+    requests: Vec<MockRequest>,
+    files: BTreeMap<String, MockFile>,
+    threads: BTreeMap<String, Value>,
+    messages: BTreeMap<String, Vec<Value>>,
+    next_id: u64,
+}
+
+struct MockFile {
+    object: Value,
+    contents: Vec<u8>,
+}
+
+impl MockServer {
+    /// Starts a server on a free local port.
+    pub async fn start() -> io::Result<Self> {
+        MockServer::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await
+    }
+
+    /// Starts a server on the given address.
+    pub async fn bind(address: SocketAddr) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        let base_url = format!("http://{}/v1/", listener.local_addr()?);
+        let state = Arc::new(Mutex::new(MockState::default()));
+        let service_state = state.clone();
+        let make_service = make_service_fn(move |_| {
+            let state = service_state.clone();
+            async move { Ok::<_, Infallible>(service_fn(move |request| handle(state.clone(), request))) }
+        });
+        let (shutdown, shutdown_signal) = oneshot::channel::<()>();
+        let server = Server::from_tcp(listener)
+            .map_err(io::Error::other)?
+            .serve(make_service)
+            .with_graceful_shutdown(async {
+                shutdown_signal.await.ok();
+            });
+        tokio::spawn(server);
+        Ok(MockServer {
+            base_url,
+            state,
+            shutdown: Some(shutdown),
+        })
+    }
+
+    /// The base url to point a client at, such as `http://127.0.0.1:8080/v1/`.
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// A client for this server.
+    pub fn client(&self) -> OpenAiClient {
+        OpenAiClient::new("mock-key").with_base_url(&self.base_url)
+    }
+
+    /// Answers the next request with `fault`. Faults queued together answer requests in order.
+    pub fn fail_next(&self, fault: Fault) {
+        self.state.lock().unwrap().faults.push_back(fault);
+    }
+
+    /// Queues the text of the next chat completion or completion, instead of an echo.
+    pub fn push_reply(&self, reply: impl Into<String>) {
+        self.state.lock().unwrap().replies.push_back(reply.into());
+    }
+
+    /// The requests received so far.
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            shutdown.send(()).ok();
+        }
+    }
+}
+
+async fn handle(
+    state: Arc<Mutex<MockState>>,
+    request: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let method = request.method().clone();
+    let content_type = request
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let path = request.uri().path().to_string();
+    let body = match hyper::body::to_bytes(request.into_body()).await {
+        Ok(body) => body.to_vec(),
+        Err(error) => return Ok(error_response(400, &error.to_string(), None)),
+    };
+
+    let mut state = state.lock().unwrap();
+    state.next_id += 1;
+    let request_id = format!("req_{}", state.next_id);
+    let route = path.strip_prefix("/v1/").unwrap_or(&path).to_string();
+    state.requests.push(MockRequest {
+        method: method.to_string(),
+        route: route.clone(),
+        body: body.clone(),
+    });
+
+    let mut response = match state.faults.pop_front() {
+        Some(fault) => fault_response(fault),
+        None if !path.starts_with("/v1/") => not_found(&path),
+        None => state.route(&method, &route, &content_type, &body),
+    };
+    response
+        .headers_mut()
+        .insert("x-request-id", request_id.parse().unwrap());
+    Ok(response)
+}
+
+impl MockState {
+    fn route(
+        &mut self,
+        method: &Method,
+        route: &str,
+        content_type: &str,
+        body: &[u8],
+    ) -> Response<Body> {
+        let segments: Vec<&str> = route.split('/').collect();
+        if content_type.starts_with("multipart/form-data") {
+            return match (method, segments.as_slice()) {
+                (&Method::POST, ["files"]) => self.upload_file(content_type, body),
+                _ => not_found(route),
+            };
+        }
+        let json = match body.is_empty() {
+            true => Value::Null,
+            false => match serde_json::from_slice::<Value>(body) {
+                Ok(json) => json,
+                Err(error) => return error_response(400, &error.to_string(), None),
+            },
+        };
+        match (method, segments.as_slice()) {
+            (&Method::POST, ["chat", "completions"]) => self.chat_completion(&json),
+            (&Method::POST, ["completions"]) => self.completion(&json),
+            (&Method::POST, ["embeddings"]) => embeddings(&json),
+            (&Method::POST, ["moderations"]) => self.moderation(&json),
+            (&Method::GET, ["models"]) => json_response(json!({
+                "object": "list",
+                "data": [model("gpt-3.5-turbo"), model("gpt-4")],
+            })),
+            (&Method::GET, ["models", id]) => json_response(model(id)),
+            (&Method::GET, ["files"]) => json_response(json!({
+                "object": "list",
+                "data": self.files.values().map(|file| &file.object).collect::<Vec<_>>(),
+            })),
+            (&Method::GET, ["files", id]) => match self.files.get(*id) {
+                Some(file) => json_response(file.object.clone()),
+                None => not_found(route),
+            },
+            (&Method::GET, ["files", id, "content"]) => match self.files.get(*id) {
+                Some(file) => Response::new(Body::from(file.contents.clone())),
+                None => not_found(route),
+            },
+            (&Method::DELETE, ["files", id]) => match self.files.remove(*id) {
+                Some(_) => json_response(json!({ "id": id, "object": "file", "deleted": true })),
+                None => not_found(route),
+            },
+            (&Method::POST, ["threads"]) => self.create_thread(&json),
+            (&Method::GET, ["threads", id]) => match self.threads.get(*id) {
+                Some(thread) => json_response(thread.clone()),
+                None => not_found(route),
+            },
+            (&Method::POST, ["threads", id]) => match self.threads.get_mut(*id) {
+                Some(thread) => {
+                    if let Some(metadata) = json.get("metadata") {
+                        thread["metadata"] = metadata.clone();
+                    }
+                    json_response(thread.clone())
+                }
+                None => not_found(route),
+            },
+            (&Method::DELETE, ["threads", id]) => match self.threads.remove(*id) {
+                Some(_) => {
+                    self.messages.remove(*id);
+                    json_response(json!({ "id": id, "object": "thread.deleted", "deleted": true }))
+                }
+                None => not_found(route),
+            },
+            (&Method::POST, ["threads", id, "messages"]) => match self.threads.contains_key(*id) {
+                true => {
+                    let message = self.create_message(id, &json);
+                    json_response(message)
+                }
+                false => not_found(route),
+            },
+            (&Method::GET, ["threads", id, "messages"]) => match self.messages.get(*id) {
+                Some(messages) => json_response(json!({ "object": "list", "data": messages })),
+                None => not_found(route),
+            },
+            _ => not_found(route),
+        }
+    }
+
+    fn new_id(&mut self, prefix: &str) -> String {
+        self.next_id += 1;
+        format!("{prefix}-mock{}", self.next_id)
+    }
+
+    fn chat_completion(&mut self, request: &Value) -> Response<Body> {
+        let messages = request["messages"].as_array().cloned().unwrap_or_default();
+        let prompt_tokens: u32 = messages
+            .iter()
+            .map(|message| estimate_text_tokens(message["content"].as_str().unwrap_or_default()))
+            .sum();
+        let last_message = messages
+            .last()
+            .and_then(|message| message["content"].as_str())
+            .unwrap_or_default();
+        let reply = self
+            .replies
+            .pop_front()
+            .unwrap_or_else(|| last_message.to_string());
+        let id = self.new_id("chatcmpl");
+        let model = request["model"].clone();
+        let n = request["n"].as_u64().unwrap_or(1);
+
+        if request["stream"].as_bool() == Some(true) {
+            let chunk = |index: u64, delta: Value, finish_reason: Value| {
+                let chunk = json!({
+                    "id": id,
+                    "object": "chat.completion.chunk",
+                    "created": now(),
+                    "model": model,
+                    "choices": [{ "index": index, "delta": delta, "finish_reason": finish_reason }],
+                });
+                format!("data: {chunk}\n\n")
+            };
+            let mut events = Vec::new();
+            for index in 0..n {
+                events.push(chunk(
+                    index,
+                    json!({ "role": "assistant", "content": "" }),
+                    Value::Null,
+                ));
+                for word in reply.split_inclusive(' ') {
+                    events.push(chunk(index, json!({ "content": word }), Value::Null));
+                }
+                events.push(chunk(index, json!({}), json!("stop")));
+            }
+            events.push("data: [DONE]\n\n".to_string());
+            return Response::builder()
+                .header(CONTENT_TYPE, "text/event-stream")
+                .body(Body::wrap_stream(stream::iter(
+                    events.into_iter().map(Ok::<_, Infallible>),
+                )))
+                .unwrap();
+        }
+
+        let completion_tokens = estimate_text_tokens(&reply) * n as u32;
+        json_response(json!({
+            "id": id,
+            "object": "chat.completion",
+            "created": now(),
+            "model": model,
+            "choices": (0..n).map(|index| json!({
+                "index": index,
+                "message": { "role": "assistant", "content": reply },
+                "finish_reason": "stop",
+            })).collect::<Vec<_>>(),
+            "usage": usage(prompt_tokens, completion_tokens),
+        }))
+    }
+
+    fn completion(&mut self, request: &Value) -> Response<Body> {
+        let prompt = request["prompt"].as_str().unwrap_or_default();
+        let reply = self
+            .replies
+            .pop_front()
+            .unwrap_or_else(|| prompt.to_string());
+        let n = request["n"].as_u64().unwrap_or(1);
+        json_response(json!({
+            "id": self.new_id("cmpl"),
+            "object": "text_completion",
+            "created": now(),
+            "model": request["model"],
+            "choices": (0..n).map(|index| json!({
+                "text": reply,
+                "index": index,
+                "logprobs": null,
+                "finish_reason": "stop",
+            })).collect::<Vec<_>>(),
+            "usage": usage(
+                estimate_text_tokens(prompt),
+                estimate_text_tokens(&reply) * n as u32,
+            ),
+        }))
+    }
+
+    fn moderation(&mut self, request: &Value) -> Response<Body> {
+        let results: Vec<Value> = inputs(&request["input"])
+            .iter()
+            .map(|input| {
+                let flagged = input.to_lowercase().contains(FLAGGED_WORD);
+                let score = if flagged { 0.9 } else { 0.0 };
+                json!({
+                    "flagged": flagged,
+                    "categories": categories(|category| flagged && category == "violence", json!(true)),
+                    "category_scores": categories(|category| category == "violence", json!(score)),
+                })
+            })
+            .collect();
+        json_response(json!({
+            "id": self.new_id("modr"),
+            "model": request["model"].as_str().unwrap_or("text-moderation-latest"),
+            "results": results,
+        }))
+    }
+
+    fn upload_file(&mut self, content_type: &str, body: &[u8]) -> Response<Body> {
+        let Some(fields) = parse_multipart(content_type, body) else {
+            return error_response(400, "Invalid multipart body", None);
+        };
+        let Some((Some(filename), contents)) = fields.get("file").cloned() else {
+            return error_response(400, "Missing file", Some("file"));
+        };
+        let purpose = fields
+            .get("purpose")
+            .map(|(_, purpose)| String::from_utf8_lossy(purpose).into_owned())
+            .unwrap_or_default();
+        let id = self.new_id("file");
+        let object = json!({
+            "id": id,
+            "object": "file",
+            "bytes": contents.len(),
+            "created_at": now(),
+            "filename": filename,
+            "purpose": purpose,
+        });
+        self.files.insert(
+            id,
+            MockFile {
+                object: object.clone(),
+                contents,
+            },
+        );
+        json_response(object)
+    }
+
+    fn create_thread(&mut self, request: &Value) -> Response<Body> {
+        let id = self.new_id("thread");
+        let thread = json!({
+            "id": id,
+            "object": "thread",
+            "created_at": now(),
+            "metadata": request.get("metadata").cloned().unwrap_or(json!({})),
+        });
+        self.threads.insert(id.clone(), thread.clone());
+        self.messages.insert(id.clone(), Vec::new());
+        for message in request["messages"].as_array().into_iter().flatten() {
+            self.create_message(&id, message);
+        }
+        json_response(thread)
+    }
+
+    fn create_message(&mut self, thread_id: &str, request: &Value) -> Value {
+        let message = json!({
+            "id": self.new_id("msg"),
+            "object": "thread.message",
+            "created_at": now(),
+            "thread_id": thread_id,
+            "status": "completed",
+            "role": request["role"],
+            "content": [{
+                "type": "text",
+                "text": { "value": request["content"], "annotations": [] },
+            }],
+            "attachments": request.get("attachments").cloned().unwrap_or(json!([])),
+            "metadata": request.get("metadata").cloned().unwrap_or(json!({})),
+        });
+        self.messages
+            .entry(thread_id.to_string())
+            .or_default()
+            .push(message.clone());
+        message
+    }
+}
+
+fn embeddings(request: &Value) -> Response<Body> {
+    let inputs = inputs(&request["input"]);
+    let tokens: u32 = inputs.iter().map(|input| estimate_text_tokens(input)).sum();
+    json_response(json!({
+        "object": "list",
+        "data": inputs.iter().enumerate().map(|(index, input)| json!({
+            "object": "embedding",
+            "index": index,
+            "embedding": embedding(input),
+        })).collect::<Vec<_>>(),
+        "model": request["model"],
+        "usage": { "prompt_tokens": tokens, "total_tokens": tokens },
+    }))
+}
+
+/// A unit vector derived from a hash of `input`.
+fn embedding(input: &str) -> Vec<f64> {
+    let vector: Vec<f64> = (0..EMBEDDING_DIMENSIONS)
+        .map(|dimension| {
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            (input, dimension).hash(&mut hasher);
+            (hasher.finish() % 2001) as f64 / 1000.0 - 1.0
+        })
+        .collect();
+    let length = vector.iter().map(|x| x * x).sum::<f64>().sqrt();
+    vector.into_iter().map(|x| x / length).collect()
+}
+
+/// Reads an `input` field given either as a string or as an array of strings.
+fn inputs(input: &Value) -> Vec<String> {
+    match input {
+        Value::String(input) => vec![input.clone()],
+        Value::Array(inputs) => inputs
+            .iter()
+            .filter_map(|input| input.as_str().map(str::to_string))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn categories(matches: impl Fn(&str) -> bool, value: Value) -> Value {
+    let zero = if value.is_boolean() {
+        json!(false)
+    } else {
+        json!(0.0)
+    };
+    let categories = [
+        "hate",
+        "hate/threatening",
+        "self-harm",
+        "sexual",
+        "sexual/minors",
+        "violence",
+        "violence/graphic",
+    ];
+    let mut map = serde_json::Map::new();
+    for category in categories {
+        let value = match matches(category) {
+            true => value.clone(),
+            false => zero.clone(),
+        };
+        map.insert(category.to_string(), value);
+    }
+    Value::Object(map)
+}
+
+fn model(id: &str) -> Value {
+    json!({ "id": id, "object": "model", "created": 0, "owned_by": "mock" })
+}
+
+fn usage(prompt_tokens: u32, completion_tokens: u32) -> Value {
+    json!({
+        "prompt_tokens": prompt_tokens,
+        "completion_tokens": completion_tokens,
+        "total_tokens": prompt_tokens + completion_tokens,
+    })
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// The file name, if any, and contents of a `multipart/form-data` field.
+type MultipartField = (Option<String>, Vec<u8>);
+
+/// Splits a `multipart/form-data` body into its fields, by name.
+fn parse_multipart(content_type: &str, body: &[u8]) -> Option<BTreeMap<String, MultipartField>> {
+    let boundary = content_type.split("boundary=").nth(1)?.trim_matches('"');
+    let delimiter = format!("--{boundary}").into_bytes();
+    let mut fields = BTreeMap::new();
+    for part in split_bytes(body, &delimiter).into_iter().skip(1) {
+        if part.starts_with(b"--") {
+            break;
+        }
+        let header_end = part.windows(4).position(|window| window == b"\r\n\r\n")?;
+        let headers = String::from_utf8_lossy(&part[..header_end]);
+        let contents = part[header_end + 4..]
+            .strip_suffix(b"\r\n")
+            .unwrap_or(&part[header_end + 4..]);
+        let disposition = headers
+            .lines()
+            .find(|line| line.to_lowercase().starts_with("content-disposition"))?;
+        let name = disposition_param(disposition, "name")?;
+        let filename = disposition_param(disposition, "filename");
+        fields.insert(name, (filename, contents.to_vec()));
+    }
+    Some(fields)
+}
+
+fn disposition_param(disposition: &str, name: &str) -> Option<String> {
+    disposition.split(';').find_map(|param| {
+        let (key, value) = param.trim().split_once('=')?;
+        (key == name).then(|| value.trim_matches('"').to_string())
+    })
+}
+
+fn split_bytes<'a>(bytes: &'a [u8], delimiter: &[u8]) -> Vec<&'a [u8]> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut index = 0;
+    while index + delimiter.len() <= bytes.len() {
+        if &bytes[index..index + delimiter.len()] == delimiter {
+            parts.push(&bytes[start..index]);
+            index += delimiter.len();
+            start = index;
+        } else {
+            index += 1;
+        }
+    }
+    parts.push(&bytes[start..]);
+    parts
+}
+
+fn fault_response(fault: Fault) -> Response<Body> {
+    match fault {
+        Fault::RateLimit => {
+            let mut response = error_response(429, "Rate limit reached", None);
+            response
+                .headers_mut()
+                .insert("retry-after-ms", "10".parse().unwrap());
+            response
+        }
+        Fault::ServerError => error_response(500, "The server had an error", None),
+        Fault::MalformedJson => Response::builder()
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from("{\"id\": \"malformed"))
+            .unwrap(),
+        Fault::Status(status) => error_response(status, "Injected error", None),
+    }
+}
+
+fn not_found(route: &str) -> Response<Body> {
+    error_response(404, &format!("No mock for {route}"), None)
+}
+
+fn error_response(status: u16, message: &str, param: Option<&str>) -> Response<Body> {
+    let (error_type, code) = match status {
+        401 => ("invalid_request_error", Some("invalid_api_key")),
+        404 => ("invalid_request_error", None),
+        429 => ("requests", Some("rate_limit_exceeded")),
+        500..=599 => ("server_error", None),
+        _ => ("invalid_request_error", None),
+    };
+    let mut response = json_response(json!({
+        "error": { "message": message, "type": error_type, "param": param, "code": code },
+    }));
+    *response.status_mut() = StatusCode::from_u16(status).unwrap_or(StatusCode::BAD_REQUEST);
+    response
+}
+
+fn json_response(body: Value) -> Response<Body> {
+    Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::{ChatCompletion, ChatCompletionMessage};
+    use crate::embeddings::Embeddings;
+    use crate::files::File;
+    use crate::moderations::Moderation;
+    use crate::tests::test_retry_policy;
+    use crate::threads::Thread;
+    use crate::{ApiErrorCode, OpenAiError};
+
+    fn message(content: &str) -> ChatCompletionMessage {
+        ChatCompletionMessage::user(content)
+    }
+
+    #[tokio::test]
+    async fn chat_streams_merge() {
+        let server = MockServer::start().await.unwrap();
+        server.push_reply("Hello from the mock server!");
+
+        let mut stream = ChatCompletion::builder("gpt-3.5-turbo", [message("Hi")])
+            .client(server.client())
+            .create_stream()
+            .await
+            .unwrap();
+        let mut merged = stream.recv().await.unwrap().unwrap();
+        while let Some(delta) = stream.recv().await {
+            merged.merge(delta.unwrap()).unwrap();
+        }
+        let chat_completion = ChatCompletion::from(merged);
+
+        assert_eq!(
+            chat_completion.choices[0].message.text(),
+            Some("Hello from the mock server!")
+        );
+        assert_eq!(chat_completion.choices[0].finish_reason, "stop");
+
+        let echo = ChatCompletion::builder("gpt-3.5-turbo", [message("Echo")])
+            .client(server.client())
+            .create()
+            .await
+            .unwrap();
+        assert_eq!(echo.choices[0].message.text(), Some("Echo"));
+    }
+
+    #[tokio::test]
+    async fn injected_errors() {
+        let server = MockServer::start().await.unwrap();
+        let client = server.client().with_retry_policy(test_retry_policy());
+        server.fail_next(Fault::RateLimit);
+        server.fail_next(Fault::ServerError);
+
+        let embeddings = Embeddings::builder("text-embedding-ada-002", ["a", "b", "a"])
+            .client(&client)
+            .create()
+            .await
+            .unwrap();
+        assert_eq!(embeddings.data[0].vec, embeddings.data[2].vec);
+        assert_ne!(embeddings.data[0].vec, embeddings.data[1].vec);
+        assert_eq!(server.requests().len(), 3);
+
+        server.fail_next(Fault::MalformedJson);
+        let error = Moderation::builder("hello")
+            .client(server.client())
+            .create()
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(error, OpenAiError::Decode { .. }));
+
+        server.fail_next(Fault::Status(401));
+        let error = Moderation::builder("hello")
+            .client(server.client())
+            .create()
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(error.code(), Some(&ApiErrorCode::InvalidApiKey));
+
+        let moderation = Moderation::builder("Graphic violence")
+            .client(server.client())
+            .create()
+            .await
+            .unwrap();
+        assert!(moderation.results[0].flagged);
+        assert!(moderation.results[0].categories.violence);
+    }
+
+    #[tokio::test]
+    async fn files_round_trip() {
+        let server = MockServer::start().await.unwrap();
+        let client = server.client();
+
+        let file = File::builder()
+            .file_name("test_data/file_upload_test1.jsonl")
+            .purpose("fine-tune")
+            .client(&client)
+            .create()
+            .await
+            .unwrap();
+        assert_eq!(file.filename, "file_upload_test1.jsonl");
+
+        let contents = File::get_content_bytes_with_client(&client, &file.id)
+            .await
+            .unwrap();
+        assert_eq!(
+            contents,
+            std::fs::read("test_data/file_upload_test1.jsonl").unwrap()
+        );
+
+        let deleted = File::delete_with_client(&client, &file.id).await.unwrap();
+        assert!(deleted.deleted);
+        let error = File::get_with_client(&client, &file.id)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(error.status(), Some(StatusCode::NOT_FOUND));
+    }
+
+    #[tokio::test]
+    async fn threads_round_trip() {
+        let server = MockServer::start().await.unwrap();
+        let client = server.client();
+
+        let thread = Thread::create_with_client(&client, vec![], Default::default())
+            .await
+            .unwrap();
+        let fetched = Thread::from_with_client(&client, &thread.id).await.unwrap();
+        assert_eq!(fetched.id, thread.id);
+
+        let deleted = Thread::delete_with_client(&client, &thread.id)
+            .await
+            .unwrap();
+        assert!(deleted.deleted);
+    }
+}
@@ -1,18 +1,55 @@
 //! List and describe the various models available in the API.
 //! You can refer to the [Models](https://beta.openai.com/docs/models)
 //! documentation to understand what models are available and the differences between them.
+//!
+//! The API doesn't describe what models can do, so the crate keeps a [`ModelRegistry`] of the
+//! context window, output limit and capabilities of OpenAI's models. Request builders check
+//! requests for known models against it, and fail with
+//! [`OpenAiError::Validation`](crate::OpenAiError::Validation) instead of sending requests the
+//! model would reject, such as ones with a `max_tokens` above its limit. Models can be added,
+//! or their limits changed, by replacing the registry:
+//!
+//! ```
+//! use openai::models::{self, ModelInfo, ModelRegistry};
+//!
+//! models::set_model_registry(ModelRegistry::builtin().with_model(
+//!     "my-fine-tune",
+//!     ModelInfo::new(16_385, 4_096).with_tools().with_streaming(),
+//! ));
+//! assert_eq!(models::model_registry().get("gpt-4o").unwrap().context_window, 128_000);
+//! ```
 
 use super::{openai_get, ApiResponseOrError};
-use serde::Deserialize;
+use crate::client::default_client;
+use crate::meta::HasResponseMeta;
+use crate::pagination::{self, ListQuery, Page};
+use crate::OpenAiClient;
+use crate::ResponseMeta;
+use futures_util::Stream;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
 
-#[derive(Deserialize, Clone)]
+static MODEL_REGISTRY: Mutex<Option<Arc<ModelRegistry>>> = Mutex::new(None);
+
+#[derive(Deserialize, Serialize, Clone)]
 pub struct Model {
     pub id: String,
     pub object: String,
     pub created: u32,
     pub owned_by: String,
+    /// Fields of the response this crate doesn't know about.
+    #[serde(flatten)]
+    pub extra_fields: Map<String, Value>,
+    /// Metadata of the response this was parsed from.
+    #[serde(skip)]
+    pub meta: Option<ResponseMeta>,
 }
 
+/// A list of models.
+pub type Models = Page<Model>;
+
 #[derive(Deserialize, Clone)]
 pub struct ModelPermission {
     pub id: String,
@@ -32,23 +69,565 @@ impl Model {
     //! Retrieves a model instance,
     //! providing basic information about the model such as the owner and permissioning.
     pub async fn from(id: &str) -> ApiResponseOrError<Self> {
-        openai_get(&format!("models/{id}")).await
+        Model::from_with_client(&default_client(), id).await
+    }
+
+    /// Retrieves a model instance using the given client.
+    pub async fn from_with_client(client: &OpenAiClient, id: &str) -> ApiResponseOrError<Self> {
+        openai_get(client, &format!("models/{id}")).await
+    }
+}
+
+impl Models {
+    /// Lists the models available to the account.
+    pub async fn list() -> ApiResponseOrError<Self> {
+        Models::list_with_client(&default_client()).await
+    }
+
+    /// Lists the models available to the account using the given client.
+    pub async fn list_with_client(client: &OpenAiClient) -> ApiResponseOrError<Self> {
+        pagination::list_page(client, "models", &ListQuery::default()).await
+    }
+
+    /// Streams every model available to the account, fetching pages as needed.
+    pub fn list_all(query: ListQuery) -> impl Stream<Item = ApiResponseOrError<Model>> {
+        Models::list_all_with_client(&default_client(), query)
+    }
+
+    /// Streams every model available to the account using the given client.
+    pub fn list_all_with_client(
+        client: &OpenAiClient,
+        query: ListQuery,
+    ) -> impl Stream<Item = ApiResponseOrError<Model>> {
+        pagination::list_all(client, "models", query)
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl Model {
+    /// Blocking version of [`Model::from`].
+    pub fn from_blocking(id: &str) -> ApiResponseOrError<Self> {
+        crate::blocking::block_on(Model::from(id))
+    }
+}
+
+impl HasResponseMeta for Model {
+    fn set_meta(&mut self, meta: ResponseMeta) {
+        self.meta = Some(meta);
+    }
+}
+
+/// The encoding a model splits text into tokens with.
+///
+/// With the `tokenizer` feature, encodings can also encode, decode and count tokens.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    Cl100kBase,
+    O200kBase,
+}
+
+impl Encoding {
+    /// The name of the encoding, such as `cl100k_base`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Cl100kBase => "cl100k_base",
+            Encoding::O200kBase => "o200k_base",
+        }
     }
 }
 
+/// What a model can do, and its limits.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ModelInfo {
+    /// The most tokens of input and output the model can handle in one request.
+    pub context_window: u32,
+    /// The most tokens the model can generate in one request.
+    pub max_output_tokens: u32,
+    pub encoding: Option<Encoding>,
+    /// Whether the model can call tools and functions.
+    pub supports_tools: bool,
+    /// Whether the model accepts images as input.
+    pub supports_vision: bool,
+    /// Whether the model supports the `json_object` response format.
+    pub supports_json_mode: bool,
+    /// Whether the model supports the `json_schema` response format.
+    #[serde(default)]
+    pub supports_structured_outputs: bool,
+    pub supports_streaming: bool,
+    /// The number of dimensions of the embeddings of embedding models.
+    pub embedding_dimensions: Option<u32>,
+}
+
+/// Models by id.
+///
+/// Dated snapshots of a model, such as `gpt-4o-2024-08-06` or `gpt-3.5-turbo-0125`, use the
+/// information of the model they are a snapshot of unless they have their own.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ModelRegistry {
+    models: HashMap<String, ModelInfo>,
+}
+
+impl ModelInfo {
+    /// A model without any of the optional capabilities or a known encoding.
+    pub const fn new(context_window: u32, max_output_tokens: u32) -> Self {
+        ModelInfo {
+            context_window,
+            max_output_tokens,
+            encoding: None,
+            supports_tools: false,
+            supports_vision: false,
+            supports_json_mode: false,
+            supports_structured_outputs: false,
+            supports_streaming: false,
+            embedding_dimensions: None,
+        }
+    }
+
+    pub const fn with_encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = Some(encoding);
+        self
+    }
+
+    pub const fn with_tools(mut self) -> Self {
+        self.supports_tools = true;
+        self
+    }
+
+    const fn without_tools(mut self) -> Self {
+        self.supports_tools = false;
+        self
+    }
+
+    pub const fn with_vision(mut self) -> Self {
+        self.supports_vision = true;
+        self
+    }
+
+    pub const fn with_json_mode(mut self) -> Self {
+        self.supports_json_mode = true;
+        self
+    }
+
+    pub const fn with_structured_outputs(mut self) -> Self {
+        self.supports_structured_outputs = true;
+        self
+    }
+
+    const fn without_structured_outputs(mut self) -> Self {
+        self.supports_structured_outputs = false;
+        self
+    }
+
+    pub const fn with_streaming(mut self) -> Self {
+        self.supports_streaming = true;
+        self
+    }
+
+    pub const fn with_embedding_dimensions(mut self, dimensions: u32) -> Self {
+        self.embedding_dimensions = Some(dimensions);
+        self
+    }
+
+    /// Fails if `max_tokens` is more than the model can generate.
+    pub(crate) fn check_max_tokens(&self, model: &str, max_tokens: u64) -> Result<(), String> {
+        match max_tokens > self.max_output_tokens as u64 {
+            true => Err(format!(
+                "max_tokens is {max_tokens}, but {model} generates at most {} tokens",
+                self.max_output_tokens
+            )),
+            false => Ok(()),
+        }
+    }
+
+    /// Fails if the prompt and `max_tokens` don't fit in the context window of the model.
+    #[cfg_attr(not(feature = "tokenizer"), allow(dead_code))]
+    pub(crate) fn check_context_window(
+        &self,
+        model: &str,
+        prompt_tokens: u64,
+        max_tokens: u64,
+    ) -> Result<(), String> {
+        match prompt_tokens + max_tokens > self.context_window as u64 {
+            true => Err(format!(
+                "the prompt has {prompt_tokens} tokens and max_tokens is {max_tokens}, \
+                 but the context window of {model} is {} tokens",
+                self.context_window
+            )),
+            false => Ok(()),
+        }
+    }
+
+    /// Fails with a message naming `capability` if `supported` is false.
+    pub(crate) fn check_support(
+        model: &str,
+        supported: bool,
+        capability: &str,
+    ) -> Result<(), String> {
+        match supported {
+            true => Ok(()),
+            false => Err(format!("{model} doesn't support {capability}")),
+        }
+    }
+}
+
+impl ModelRegistry {
+    /// A registry without any models.
+    pub fn new() -> Self {
+        ModelRegistry::default()
+    }
+
+    /// OpenAI's models.
+    pub fn builtin() -> Self {
+        BUILTIN_MODELS
+            .iter()
+            .fold(ModelRegistry::new(), |registry, (id, info)| {
+                registry.with_model(*id, *info)
+            })
+    }
+
+    /// Adds a model, replacing its current information.
+    pub fn with_model(mut self, id: impl Into<String>, info: ModelInfo) -> Self {
+        self.models.insert(id.into(), info);
+        self
+    }
+
+    /// The information of a model, or `None` if it isn't in the registry.
+    pub fn get(&self, id: &str) -> Option<&ModelInfo> {
+        find_model(&self.models, id)
+    }
+}
+
+/// Finds the entry of a model, or of the model it is a snapshot of.
+pub(crate) fn find_model<'a, T>(entries: &'a HashMap<String, T>, model: &str) -> Option<&'a T> {
+    if let Some(entry) = entries.get(model) {
+        return Some(entry);
+    }
+    // Snapshots are named after their model, followed by a date. Other suffixes, such as that
+    // of `gpt-4-1106-vision-preview`, name different models, which need entries of their own.
+    entries
+        .iter()
+        .filter(|(name, _)| {
+            model
+                .strip_prefix(name.as_str())
+                .and_then(|rest| rest.strip_prefix('-'))
+                .is_some_and(is_snapshot_date)
+        })
+        .max_by_key(|(name, _)| name.len())
+        .map(|(_, entry)| entry)
+}
+
+/// Whether `suffix` is the date of a snapshot, as `YYYY-MM-DD` or `MMDD`.
+fn is_snapshot_date(suffix: &str) -> bool {
+    let digits = |part: &str, len: usize| {
+        part.len() == len && part.bytes().all(|byte| byte.is_ascii_digit())
+    };
+    match suffix.split('-').collect::<Vec<_>>()[..] {
+        [year, month, day] => digits(year, 4) && digits(month, 2) && digits(day, 2),
+        [month_day] => digits(month_day, 4),
+        _ => false,
+    }
+}
+
+/// The registry request builders validate requests against.
+pub fn model_registry() -> Arc<ModelRegistry> {
+    static BUILTIN: OnceLock<Arc<ModelRegistry>> = OnceLock::new();
+    match MODEL_REGISTRY.lock().unwrap().as_ref() {
+        Some(registry) => registry.clone(),
+        None => BUILTIN
+            .get_or_init(|| Arc::new(ModelRegistry::builtin()))
+            .clone(),
+    }
+}
+
+/// Replaces the registry request builders validate requests against.
+/// An empty registry turns validation off.
+pub fn set_model_registry(registry: ModelRegistry) {
+    *MODEL_REGISTRY.lock().unwrap() = Some(Arc::new(registry));
+}
+
+/// The information of a model in the current registry.
+pub(crate) fn model_info(model: &str) -> Option<ModelInfo> {
+    model_registry().get(model).copied()
+}
+
+/// A chat model of the GPT-4o generation and later.
+const fn chat(context_window: u32, max_output_tokens: u32) -> ModelInfo {
+    ModelInfo::new(context_window, max_output_tokens)
+        .with_encoding(Encoding::O200kBase)
+        .with_tools()
+        .with_json_mode()
+        .with_structured_outputs()
+        .with_streaming()
+}
+
+/// A chat model of the GPT-3.5 and GPT-4 generations.
+const fn legacy_chat(context_window: u32, max_output_tokens: u32) -> ModelInfo {
+    ModelInfo::new(context_window, max_output_tokens)
+        .with_encoding(Encoding::Cl100kBase)
+        .with_tools()
+        .with_streaming()
+}
+
+/// The preview of GPT-4 with vision, which doesn't support tools or JSON mode.
+const fn vision_preview() -> ModelInfo {
+    legacy_chat(128_000, 4_096).without_tools().with_vision()
+}
+
+const fn embedding(dimensions: u32) -> ModelInfo {
+    ModelInfo::new(8_191, 0)
+        .with_encoding(Encoding::Cl100kBase)
+        .with_embedding_dimensions(dimensions)
+}
+
+const BUILTIN_MODELS: &[(&str, ModelInfo)] = &[
+    ("gpt-4.1", chat(1_047_576, 32_768).with_vision()),
+    ("gpt-4.1-mini", chat(1_047_576, 32_768).with_vision()),
+    ("gpt-4.1-nano", chat(1_047_576, 32_768).with_vision()),
+    ("gpt-4o", chat(128_000, 16_384).with_vision()),
+    // Structured outputs came with the 2024-08-06 snapshot.
+    (
+        "gpt-4o-2024-05-13",
+        chat(128_000, 4_096)
+            .with_vision()
+            .without_structured_outputs(),
+    ),
+    ("gpt-4o-mini", chat(128_000, 16_384).with_vision()),
+    ("o1", chat(200_000, 100_000).with_vision()),
+    (
+        "o1-mini",
+        ModelInfo::new(128_000, 65_536)
+            .with_encoding(Encoding::O200kBase)
+            .with_streaming(),
+    ),
+    ("o3", chat(200_000, 100_000).with_vision()),
+    ("o3-mini", chat(200_000, 100_000)),
+    ("o4-mini", chat(200_000, 100_000).with_vision()),
+    (
+        "gpt-4-turbo",
+        legacy_chat(128_000, 4_096).with_json_mode().with_vision(),
+    ),
+    (
+        "gpt-4-turbo-preview",
+        legacy_chat(128_000, 4_096).with_json_mode(),
+    ),
+    (
+        "gpt-4-0125-preview",
+        legacy_chat(128_000, 4_096).with_json_mode(),
+    ),
+    (
+        "gpt-4-1106-preview",
+        legacy_chat(128_000, 4_096).with_json_mode(),
+    ),
+    ("gpt-4-vision-preview", vision_preview()),
+    ("gpt-4-1106-vision-preview", vision_preview()),
+    ("gpt-4", legacy_chat(8_192, 8_192)),
+    // Function calling came with the 0613 snapshots.
+    ("gpt-4-0314", legacy_chat(8_192, 8_192).without_tools()),
+    ("gpt-4-32k", legacy_chat(32_768, 32_768)),
+    (
+        "gpt-4-32k-0314",
+        legacy_chat(32_768, 32_768).without_tools(),
+    ),
+    ("gpt-3.5-turbo", legacy_chat(16_385, 4_096).with_json_mode()),
+    ("gpt-3.5-turbo-0613", legacy_chat(4_096, 4_096)),
+    (
+        "gpt-3.5-turbo-0301",
+        legacy_chat(4_096, 4_096).without_tools(),
+    ),
+    ("gpt-3.5-turbo-16k", legacy_chat(16_385, 4_096)),
+    (
+        "gpt-3.5-turbo-instruct",
+        ModelInfo::new(4_096, 4_096)
+            .with_encoding(Encoding::Cl100kBase)
+            .with_streaming(),
+    ),
+    (
+        "davinci-002",
+        ModelInfo::new(16_384, 16_384)
+            .with_encoding(Encoding::Cl100kBase)
+            .with_streaming(),
+    ),
+    (
+        "babbage-002",
+        ModelInfo::new(16_384, 16_384)
+            .with_encoding(Encoding::Cl100kBase)
+            .with_streaming(),
+    ),
+    ("text-embedding-3-small", embedding(1_536)),
+    ("text-embedding-3-large", embedding(3_072)),
+    ("text-embedding-ada-002", embedding(1_536)),
+];
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::set_key;
-    use crate::tests::DEFAULT_LEGACY_MODEL;
-    use dotenvy::dotenv;
-    use std::env;
+    use crate::chat::{
+        ChatCompletion, ChatCompletionFunctionDefinition, ChatCompletionMessage,
+        ChatCompletionResponseFormat,
+    };
+    use crate::completions::Completion;
+    use crate::tests::{cassette_client, DEFAULT_LEGACY_MODEL};
+    use crate::OpenAiError;
+
+    #[test]
+    fn registry_lookup() {
+        let registry = ModelRegistry::builtin();
+
+        let gpt_4o = registry.get("gpt-4o-2024-08-06").unwrap();
+        assert_eq!(gpt_4o.max_output_tokens, 16_384);
+        assert_eq!(gpt_4o.encoding, Some(Encoding::O200kBase));
+        assert!(gpt_4o.supports_vision);
+        assert_eq!(
+            registry.get("gpt-4o-2024-05-13").unwrap().max_output_tokens,
+            4_096
+        );
+        assert_eq!(
+            registry
+                .get("text-embedding-3-large")
+                .unwrap()
+                .embedding_dimensions,
+            Some(3_072)
+        );
+        assert!(!registry.get("o1-mini").unwrap().supports_tools);
+        assert_eq!(registry.get("gpt-4o-realtime-preview"), None);
+
+        // Only dates name snapshots. Other suffixes name different models.
+        let vision = registry.get("gpt-4-1106-vision-preview").unwrap();
+        assert_eq!(vision.context_window, 128_000);
+        assert!(vision.supports_vision);
+        assert_eq!(registry.get("gpt-4-0613"), registry.get("gpt-4"));
+        assert_eq!(
+            registry.get("gpt-4-0125-preview").unwrap().context_window,
+            128_000
+        );
+        assert_eq!(registry.get("gpt-4-1234-preview"), None);
+        assert_eq!(registry.get("gpt-4o-2024-08"), None);
+        assert_eq!(
+            registry.get("gpt-3.5-turbo-0125"),
+            registry.get("gpt-3.5-turbo")
+        );
+        assert_eq!(
+            registry.get("gpt-3.5-turbo-0613").unwrap().context_window,
+            4_096
+        );
+        let turbo_0301 = registry.get("gpt-3.5-turbo-0301").unwrap();
+        assert_eq!(turbo_0301.context_window, 4_096);
+        assert!(!turbo_0301.supports_tools);
+        assert_eq!(
+            registry.get("gpt-3.5-turbo-16k-0613"),
+            registry.get("gpt-3.5-turbo-16k")
+        );
+
+        let registry = registry.with_model("my-model", ModelInfo::new(1_000, 100));
+        assert_eq!(registry.get("my-model").unwrap().context_window, 1_000);
+    }
+
+    #[test]
+    fn builders_validate_against_registry() {
+        let messages = [ChatCompletionMessage::user("Hello!")];
+
+        let error = ChatCompletion::builder("gpt-4", messages.clone())
+            .max_tokens(10_000u64)
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid request: max_tokens is 10000, but gpt-4 generates at most 8192 tokens"
+        );
+        let error = ChatCompletion::builder("o1-mini", messages.clone())
+            .functions([ChatCompletionFunctionDefinition {
+                name: "foo".to_string(),
+                description: None,
+                parameters: None,
+            }])
+            .build()
+            .unwrap_err();
+        assert!(
+            matches!(error, OpenAiError::Validation(message) if message == "o1-mini doesn't support function calling")
+        );
+        let error = ChatCompletion::builder("gpt-4", messages.clone())
+            .response_format(ChatCompletionResponseFormat::JsonObject)
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid request: gpt-4 doesn't support JSON outputs"
+        );
+        let structured = ChatCompletionResponseFormat::json_schema::<HashMap<String, u32>>;
+        ChatCompletion::builder("gpt-4o", messages.clone())
+            .response_format(structured())
+            .build()
+            .unwrap();
+        for model in ["gpt-4-turbo", "gpt-3.5-turbo", "gpt-4o-2024-05-13"] {
+            let error = ChatCompletion::builder(model, messages.clone())
+                .response_format(structured())
+                .build()
+                .unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                format!("invalid request: {model} doesn't support structured outputs")
+            );
+        }
+        let error = ChatCompletion::builder("gpt-4o", messages.clone())
+            .response_format(ChatCompletionResponseFormat::json_schema::<Vec<String>>())
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid request: the schema of the Vec response format must be an object at its root"
+        );
+        assert!(Completion::builder("gpt-3.5-turbo-instruct")
+            .max_tokens(5_000u16)
+            .build()
+            .is_err());
+
+        // Models that aren't in the registry aren't checked.
+        ChatCompletion::builder("my-model", messages.clone())
+            .max_tokens(1_000_000u64)
+            .build()
+            .unwrap();
+        ChatCompletion::builder("gpt-4", messages)
+            .max_tokens(8_000u64)
+            .build()
+            .unwrap();
+    }
+
+    #[cfg(feature = "tokenizer")]
+    #[test]
+    fn builders_validate_context_window() {
+        let messages = [ChatCompletionMessage::user("Hello!")];
+
+        // The message takes 9 tokens of the 8192 of the context window.
+        ChatCompletion::builder("gpt-4", messages.clone())
+            .max_tokens(8_183u64)
+            .build()
+            .unwrap();
+        let error = ChatCompletion::builder("gpt-4", messages.clone())
+            .max_tokens(8_184u64)
+            .build()
+            .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("the context window of gpt-4 is 8192 tokens"));
+
+        // Counts of content parts are estimates, which don't reject the request.
+        let parts =
+            ChatCompletionMessage::user(vec![crate::chat::ChatCompletionContentPart::text(
+                "Hello!",
+            )]);
+        ChatCompletion::builder("gpt-4", [parts])
+            .max_tokens(8_184u64)
+            .build()
+            .unwrap();
+    }
 
     #[tokio::test]
     async fn model() {
-        dotenv().ok();
-        set_key(env::var("OPENAI_KEY").unwrap());
-        let model = Model::from(DEFAULT_LEGACY_MODEL).await.unwrap();
+        let client = cassette_client("model");
+        let model = Model::from_with_client(&client, DEFAULT_LEGACY_MODEL)
+            .await
+            .unwrap();
         assert_eq!(model.id, DEFAULT_LEGACY_MODEL);
     }
 }
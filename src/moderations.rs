@@ -1,14 +1,26 @@
 //! Given a input text, outputs if the model classifies it as violating OpenAI's content policy.
 
 use super::{openai_post, ApiResponseOrError};
+use crate::client::{request_client, RequestHeaders};
+use crate::meta::HasResponseMeta;
+use crate::ResponseMeta;
+use crate::{OpenAiClient, OpenAiError};
 use derive_builder::Builder;
+use reqwest::header::HeaderMap;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 
 #[derive(Deserialize, Clone, Debug)]
 pub struct Moderation {
     pub id: String,
     pub model: String,
     pub results: Vec<ModerationResult>,
+    /// Fields of the response this crate doesn't know about.
+    #[serde(flatten)]
+    pub extra_fields: Map<String, Value>,
+    /// Metadata of the response this was parsed from.
+    #[serde(skip)]
+    pub meta: Option<ResponseMeta>,
 }
 
 #[derive(Deserialize, Clone, Debug)]
@@ -51,6 +63,7 @@ pub struct CategoryScores {
 #[derive(Serialize, Builder, Debug, Clone)]
 #[builder(pattern = "owned")]
 #[builder(name = "ModerationBuilder")]
+#[builder(build_fn(error = "OpenAiError"))]
 #[builder(setter(strip_option, into))]
 pub struct ModerationRequest {
     /// The input text to classify.
@@ -60,11 +73,33 @@ pub struct ModerationRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[builder(default)]
     pub model: Option<String>,
+    /// The client to send the request with. Uses the default client if not set.
+    #[serde(skip)]
+    #[builder(default)]
+    pub client: Option<OpenAiClient>,
+    /// Headers sent with this request, replacing the client's headers of the same name.
+    #[serde(skip)]
+    #[builder(
+        setter(custom),
+        field(type = "RequestHeaders", build = "self.headers.build()?")
+    )]
+    pub headers: HeaderMap,
+    /// Extra parameters sent in the body of the request.
+    #[serde(flatten)]
+    #[builder(default, setter(custom))]
+    pub extra_body: Map<String, Value>,
 }
 
+request_builder_setters!(ModerationBuilder);
+
 impl Moderation {
     async fn create(request: &ModerationRequest) -> ApiResponseOrError<Self> {
-        openai_post("moderations", request).await
+        let client = request_client(&request.client, &request.headers);
+        let route = match &request.model {
+            Some(model) => client.model_route(model, "moderations"),
+            None => "moderations".to_string(),
+        };
+        openai_post(&client, &route, request).await
     }
 
     pub fn builder(input: impl Into<String>) -> ModerationBuilder {
@@ -74,32 +109,41 @@ impl Moderation {
 
 impl ModerationBuilder {
     pub async fn create(self) -> ApiResponseOrError<Moderation> {
-        Moderation::create(&self.build().unwrap()).await
+        Moderation::create(&self.build()?).await
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl ModerationBuilder {
+    /// Blocking version of [`ModerationBuilder::create`].
+    pub fn create_blocking(self) -> ApiResponseOrError<Moderation> {
+        crate::blocking::block_on(self.create())
+    }
+}
+
+impl HasResponseMeta for Moderation {
+    fn set_meta(&mut self, meta: ResponseMeta) {
+        self.meta = Some(meta);
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::set_key;
-    use dotenvy::dotenv;
-    use std::env;
+    use crate::tests::cassette_client;
 
     #[tokio::test]
     async fn moderations() {
-        dotenv().ok();
-        set_key(env::var("OPENAI_KEY").unwrap());
+        let client = cassette_client("moderations");
 
         let moderation = Moderation::builder("I want to kill them.")
             .model("text-moderation-latest")
+            .client(&client)
             .create()
             .await
             .unwrap();
 
-        assert_eq!(
-            moderation.results.first().unwrap().categories.violence,
-            true
-        );
-        assert_eq!(moderation.results.first().unwrap().flagged, true);
+        assert!(moderation.results.first().unwrap().categories.violence);
+        assert!(moderation.results.first().unwrap().flagged);
     }
 }
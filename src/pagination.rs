@@ -0,0 +1,297 @@
+//! Cursor-based pagination of list endpoints.
+//!
+//! List endpoints answer with a [`Page`] of objects, and take a [`ListQuery`] to choose how many
+//! objects to return, in which order, and from which cursor. Each list call also has an
+//! `_all` version that returns a [`Stream`] of every object, fetching pages lazily as the stream
+//! is read:
+//!
+//! ```no_run
+//! use futures_util::StreamExt;
+//! use openai::files::Files;
+//! use openai::pagination::{ListQuery, Order};
+//!
+//! # async fn example() -> openai::ApiResponseOrError<()> {
+//! let mut files = std::pin::pin!(Files::list_all(ListQuery {
+//!     limit: Some(100),
+//!     order: Some(Order::Asc),
+//!     ..ListQuery::default()
+//! }));
+//! while let Some(file) = files.next().await {
+//!     println!("{}", file?.filename);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Endpoints without a wrapper in this crate can be listed with [`list_page`] and [`list_all`].
+
+use futures_util::stream::{self, Stream};
+use futures_util::{future, StreamExt};
+use reqwest::{Method, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::meta::HasResponseMeta;
+use crate::{openai_request_json, ApiResponseOrError, OpenAiClient, OpenAiError, ResponseMeta};
+
+/// One page of a list of objects.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Page<T> {
+    pub object: String,
+    pub data: Vec<T>,
+    /// The id of the first object of the page.
+    #[serde(default)]
+    pub first_id: Option<String>,
+    /// The id of the last object of the page.
+    #[serde(default)]
+    pub last_id: Option<String>,
+    /// Whether there are more objects after this page.
+    #[serde(default)]
+    pub has_more: bool,
+    /// Metadata of the response this was parsed from.
+    #[serde(skip)]
+    pub meta: Option<ResponseMeta>,
+}
+
+/// The order in which objects are listed, by creation time.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Order {
+    Asc,
+    Desc,
+}
+
+/// Which objects a list call returns. Fields left as `None` use the endpoint's defaults.
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ListQuery {
+    /// The number of objects per page.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order: Option<Order>,
+    /// Lists the objects after the one with this id.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub after: Option<String>,
+    /// Lists the objects before the one with this id.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub before: Option<String>,
+}
+
+impl<T> Page<T> {
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+impl<T: Serialize> Page<T> {
+    /// The query for the page following this one, or `None` if this is the last page.
+    ///
+    /// Queries with only a `before` cursor walk backwards, towards the start of the list.
+    /// Pages without a `first_id` or `last_id` continue from the id of their first or last
+    /// object; a page with more objects and no id to continue from is an error.
+    pub fn next_query(&self, query: &ListQuery) -> ApiResponseOrError<Option<ListQuery>> {
+        if !self.has_more {
+            return Ok(None);
+        }
+        let mut next = query.clone();
+        if query.before.is_some() && query.after.is_none() {
+            next.before = Some(self.cursor(&self.first_id, self.data.first())?);
+        } else {
+            next.after = Some(self.cursor(&self.last_id, self.data.last())?);
+        }
+        Ok(Some(next))
+    }
+
+    fn cursor(&self, id: &Option<String>, object: Option<&T>) -> ApiResponseOrError<String> {
+        if let Some(id) = id {
+            return Ok(id.clone());
+        }
+        let object_id = object
+            .and_then(|object| serde_json::to_value(object).ok())
+            .and_then(|object| object.get("id")?.as_str().map(str::to_string));
+        object_id.ok_or_else(|| OpenAiError::Decode {
+            status: self
+                .meta
+                .as_ref()
+                .map_or(StatusCode::OK, |meta| meta.status),
+            body: serde_json::to_string(self).unwrap_or_default(),
+            source: serde::de::Error::custom(
+                "page has more objects but no cursor or object id to continue from",
+            ),
+        })
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Page<T> {
+    type Item = &'a T;
+    type IntoIter = core::slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.data.iter()
+    }
+}
+
+impl<T> HasResponseMeta for Page<T> {
+    fn set_meta(&mut self, meta: ResponseMeta) {
+        self.meta = Some(meta);
+    }
+}
+
+/// Fetches one page from the list endpoint at `route`.
+pub async fn list_page<T>(
+    client: &OpenAiClient,
+    route: &str,
+    query: &ListQuery,
+) -> ApiResponseOrError<Page<T>>
+where
+    T: DeserializeOwned,
+{
+    openai_request_json(client, Method::GET, route, |request| request.query(query)).await
+}
+
+/// Lists every object of the list endpoint at `route`, starting from `query`.
+///
+/// Pages are fetched as the stream is read. The stream ends after the first error.
+pub fn list_all<T>(
+    client: &OpenAiClient,
+    route: &str,
+    query: ListQuery,
+) -> impl Stream<Item = ApiResponseOrError<T>> + 'static
+where
+    T: DeserializeOwned + Serialize + 'static,
+{
+    let client = client.clone();
+    let route = route.to_string();
+    stream::unfold(Some(Ok(query)), move |query| {
+        let client = client.clone();
+        let route = route.clone();
+        async move {
+            let query = match query? {
+                Ok(query) => query,
+                Err(error) => return Some((Err(error), None)),
+            };
+            let page = match list_page::<T>(&client, &route, &query).await {
+                Ok(page) => page,
+                Err(error) => return Some((Err(error), None)),
+            };
+            // A page that can't be continued still yields its objects before the error.
+            let next = page.next_query(&query).transpose();
+            Some((Ok(page.data), next))
+        }
+    })
+    .flat_map(|page| match page {
+        Ok(data) => stream::iter(data.into_iter().map(Ok)).left_stream(),
+        Err(error) => stream::once(future::ready(Err(error))).right_stream(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::{File, Files};
+    use crate::tests::{http_response, serve_responses};
+
+    fn file(id: &str) -> String {
+        format!(
+            r#"{{"id":"{id}","object":"file","bytes":1,"created_at":0,"filename":"{id}.jsonl","purpose":"fine-tune"}}"#
+        )
+    }
+
+    #[tokio::test]
+    async fn lists_all_pages_lazily() {
+        let (base_url, requests) = serve_responses(vec![
+            http_response(
+                200,
+                &[],
+                &format!(
+                    r#"{{"object":"list","data":[{},{}],"first_id":"file-1","last_id":"file-2","has_more":true}}"#,
+                    file("file-1"),
+                    file("file-2")
+                ),
+            ),
+            http_response(
+                200,
+                &[],
+                &format!(
+                    r#"{{"object":"list","data":[{}],"first_id":"file-3","last_id":"file-3","has_more":false}}"#,
+                    file("file-3")
+                ),
+            ),
+        ])
+        .await;
+        let client = OpenAiClient::new("key").with_base_url(base_url);
+        let query = ListQuery {
+            limit: Some(2),
+            order: Some(Order::Asc),
+            ..ListQuery::default()
+        };
+
+        let mut files = std::pin::pin!(Files::list_all_with_client(&client, query));
+        let first: File = files.next().await.unwrap().unwrap();
+        assert_eq!(first.id, "file-1");
+        assert_eq!(requests.lock().unwrap().len(), 1);
+        let rest: Vec<String> = files.map(|file| file.unwrap().id).collect().await;
+
+        assert_eq!(rest, ["file-2", "file-3"]);
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[0].starts_with("GET /v1/files?limit=2&order=asc "));
+        assert!(requests[1].starts_with("GET /v1/files?limit=2&order=asc&after=file-2 "));
+    }
+
+    #[test]
+    fn walks_backwards_from_before() {
+        let page: Page<File> = serde_json::from_str(
+            r#"{"object":"list","data":[],"first_id":"msg-4","last_id":"msg-6","has_more":true}"#,
+        )
+        .unwrap();
+        let query = ListQuery {
+            before: Some("msg-7".to_string()),
+            ..ListQuery::default()
+        };
+
+        let next = page.next_query(&query).unwrap().unwrap();
+
+        assert_eq!(next.before.as_deref(), Some("msg-4"));
+        assert_eq!(next.after, None);
+        let last_page = Page::<File> {
+            has_more: false,
+            ..page
+        };
+        assert_eq!(last_page.next_query(&query).unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn continues_from_object_ids_without_cursors() {
+        let (base_url, requests) = serve_responses(vec![
+            http_response(
+                200,
+                &[],
+                &format!(
+                    r#"{{"object":"list","data":[{},{}],"has_more":true}}"#,
+                    file("file-1"),
+                    file("file-2")
+                ),
+            ),
+            http_response(200, &[], r#"{"object":"list","data":[],"has_more":true}"#),
+        ])
+        .await;
+        let client = OpenAiClient::new("key").with_base_url(base_url);
+
+        let files: Vec<_> = Files::list_all_with_client(&client, ListQuery::default())
+            .collect()
+            .await;
+
+        assert_eq!(files.len(), 3);
+        assert_eq!(files[0].as_ref().unwrap().id, "file-1");
+        assert_eq!(files[1].as_ref().unwrap().id, "file-2");
+        assert!(matches!(files[2], Err(OpenAiError::Decode { .. })));
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1].starts_with("GET /v1/files?after=file-2 "));
+    }
+}
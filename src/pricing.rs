@@ -0,0 +1,409 @@
+//! What requests cost, from the tokens they use.
+//!
+//! Chat completions, completions, edits and embeddings have a `cost()` method, which prices their
+//! [`Usage`](crate::Usage) with the model's [`ModelPrice`]. Requests can be priced before they
+//! are sent with [`TokenEstimate::estimate_cost`], from the same estimate used for rate limiting.
+//!
+//! The built-in prices are OpenAI's list prices in US dollars, which change over time. They can
+//! be replaced, or completed with other models, such as fine-tuned models or Azure deployments:
+//!
+//! ```
+//! use openai::pricing::{self, ModelPrice, PriceTable};
+//!
+//! pricing::set_price_table(
+//!     PriceTable::builtin().with_price("my-deployment", ModelPrice::new(2.5, 10.0)),
+//! );
+//! ```
+
+use std::collections::HashMap;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Mul};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use serde::{Deserialize, Serialize};
+
+use crate::embeddings::EmbeddingsUsage;
+use crate::models::find_model;
+use crate::rate_limit::TokenEstimate;
+use crate::Usage;
+
+static PRICE_TABLE: Mutex<Option<Arc<PriceTable>>> = Mutex::new(None);
+
+/// The price of a model, in US dollars per million tokens.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct ModelPrice {
+    pub input: f64,
+    /// The price of input tokens read from the prompt cache.
+    /// Cached tokens are priced as other input tokens when `None`.
+    pub cached_input: Option<f64>,
+    pub output: f64,
+    /// The fraction of the price taken off requests sent through the Batch API.
+    pub batch_discount: f64,
+}
+
+/// The number of tokens a request used, or is expected to use.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TokenCounts {
+    /// Input tokens, including cached ones.
+    pub input: u32,
+    /// Input tokens read from the prompt cache.
+    pub cached_input: u32,
+    pub output: u32,
+}
+
+/// A cost in US dollars, split by kind of token.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct Cost {
+    /// The cost of input tokens that weren't cached.
+    pub input: f64,
+    pub cached_input: f64,
+    pub output: f64,
+}
+
+/// Prices by model.
+///
+/// Dated snapshots of a model, such as `gpt-4o-2024-08-06` or `gpt-3.5-turbo-0125`, use the
+/// price of the model they are a snapshot of unless they have a price of their own.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct PriceTable {
+    prices: HashMap<String, ModelPrice>,
+}
+
+impl ModelPrice {
+    /// A price without a discount on cached input, and with the usual batch discount of half.
+    pub const fn new(input: f64, output: f64) -> Self {
+        ModelPrice {
+            input,
+            cached_input: None,
+            output,
+            batch_discount: 0.5,
+        }
+    }
+
+    pub const fn with_cached_input(mut self, cached_input: f64) -> Self {
+        self.cached_input = Some(cached_input);
+        self
+    }
+
+    pub const fn with_batch_discount(mut self, batch_discount: f64) -> Self {
+        self.batch_discount = batch_discount;
+        self
+    }
+
+    /// The cost of `tokens` for a request sent directly.
+    pub fn cost(&self, tokens: TokenCounts) -> Cost {
+        let cached_input = tokens.cached_input.min(tokens.input);
+        Cost {
+            input: per_million(tokens.input - cached_input, self.input),
+            cached_input: per_million(cached_input, self.cached_input.unwrap_or(self.input)),
+            output: per_million(tokens.output, self.output),
+        }
+    }
+
+    /// The cost of `tokens` for a request sent through the Batch API.
+    pub fn batch_cost(&self, tokens: TokenCounts) -> Cost {
+        self.cost(tokens) * (1.0 - self.batch_discount)
+    }
+}
+
+fn per_million(tokens: u32, price: f64) -> f64 {
+    tokens as f64 * price / 1_000_000.0
+}
+
+impl From<Usage> for TokenCounts {
+    fn from(usage: Usage) -> Self {
+        TokenCounts {
+            input: usage.prompt_tokens,
+            cached_input: usage
+                .prompt_tokens_details
+                .map_or(0, |details| details.cached_tokens),
+            output: usage.completion_tokens,
+        }
+    }
+}
+
+impl From<EmbeddingsUsage> for TokenCounts {
+    fn from(usage: EmbeddingsUsage) -> Self {
+        TokenCounts {
+            input: usage.prompt_tokens,
+            cached_input: 0,
+            output: 0,
+        }
+    }
+}
+
+impl Cost {
+    pub fn total(&self) -> f64 {
+        self.input + self.cached_input + self.output
+    }
+}
+
+impl Add for Cost {
+    type Output = Cost;
+
+    fn add(self, other: Cost) -> Cost {
+        Cost {
+            input: self.input + other.input,
+            cached_input: self.cached_input + other.cached_input,
+            output: self.output + other.output,
+        }
+    }
+}
+
+impl AddAssign for Cost {
+    fn add_assign(&mut self, other: Cost) {
+        *self = *self + other;
+    }
+}
+
+impl Mul<f64> for Cost {
+    type Output = Cost;
+
+    fn mul(self, factor: f64) -> Cost {
+        Cost {
+            input: self.input * factor,
+            cached_input: self.cached_input * factor,
+            output: self.output * factor,
+        }
+    }
+}
+
+impl Sum for Cost {
+    fn sum<I: Iterator<Item = Cost>>(iter: I) -> Cost {
+        iter.fold(Cost::default(), Add::add)
+    }
+}
+
+impl PriceTable {
+    /// A table without any prices.
+    pub fn new() -> Self {
+        PriceTable::default()
+    }
+
+    /// OpenAI's list prices of its models.
+    pub fn builtin() -> Self {
+        BUILTIN_PRICES
+            .iter()
+            .fold(PriceTable::new(), |table, (model, price)| {
+                table.with_price(*model, *price)
+            })
+    }
+
+    /// Sets the price of a model, replacing its current one.
+    pub fn with_price(mut self, model: impl Into<String>, price: ModelPrice) -> Self {
+        self.prices.insert(model.into(), price);
+        self
+    }
+
+    /// The price of a model, or `None` if it isn't in the table.
+    pub fn price(&self, model: &str) -> Option<&ModelPrice> {
+        find_model(&self.prices, model)
+    }
+
+    /// The cost of `tokens` used by `model`, or `None` if the model has no price.
+    pub fn cost(&self, model: &str, tokens: TokenCounts) -> Option<Cost> {
+        Some(self.price(model)?.cost(tokens))
+    }
+
+    /// The cost of `tokens` used by `model` through the Batch API,
+    /// or `None` if the model has no price.
+    pub fn batch_cost(&self, model: &str, tokens: TokenCounts) -> Option<Cost> {
+        Some(self.price(model)?.batch_cost(tokens))
+    }
+
+    /// Estimates the cost of a request before sending it.
+    ///
+    /// The output is priced at the request's `max_tokens`, so the estimate is an upper bound
+    /// when it's set, and only covers the input otherwise.
+    pub fn estimate(&self, request: &(impl TokenEstimate + ?Sized)) -> Option<Cost> {
+        self.cost(
+            request.model(),
+            TokenCounts {
+                input: request.estimate_prompt_tokens(),
+                cached_input: 0,
+                output: request.estimate_completion_tokens(),
+            },
+        )
+    }
+}
+
+/// The price table used by the `cost()` methods of responses.
+pub fn price_table() -> Arc<PriceTable> {
+    static BUILTIN: OnceLock<Arc<PriceTable>> = OnceLock::new();
+    match PRICE_TABLE.lock().unwrap().as_ref() {
+        Some(table) => table.clone(),
+        None => BUILTIN
+            .get_or_init(|| Arc::new(PriceTable::builtin()))
+            .clone(),
+    }
+}
+
+/// Replaces the price table used by the `cost()` methods of responses.
+pub fn set_price_table(table: PriceTable) {
+    *PRICE_TABLE.lock().unwrap() = Some(Arc::new(table));
+}
+
+const BUILTIN_PRICES: &[(&str, ModelPrice)] = &[
+    ("gpt-4.1", ModelPrice::new(2.0, 8.0).with_cached_input(0.5)),
+    (
+        "gpt-4.1-mini",
+        ModelPrice::new(0.4, 1.6).with_cached_input(0.1),
+    ),
+    (
+        "gpt-4.1-nano",
+        ModelPrice::new(0.1, 0.4).with_cached_input(0.025),
+    ),
+    ("gpt-4o", ModelPrice::new(2.5, 10.0).with_cached_input(1.25)),
+    ("gpt-4o-2024-05-13", ModelPrice::new(5.0, 15.0)),
+    (
+        "gpt-4o-mini",
+        ModelPrice::new(0.15, 0.6).with_cached_input(0.075),
+    ),
+    ("o1", ModelPrice::new(15.0, 60.0).with_cached_input(7.5)),
+    ("o1-mini", ModelPrice::new(1.1, 4.4).with_cached_input(0.55)),
+    ("o3", ModelPrice::new(2.0, 8.0).with_cached_input(0.5)),
+    ("o3-mini", ModelPrice::new(1.1, 4.4).with_cached_input(0.55)),
+    (
+        "o4-mini",
+        ModelPrice::new(1.1, 4.4).with_cached_input(0.275),
+    ),
+    ("gpt-4-turbo", ModelPrice::new(10.0, 30.0)),
+    ("gpt-4-turbo-preview", ModelPrice::new(10.0, 30.0)),
+    ("gpt-4-0125-preview", ModelPrice::new(10.0, 30.0)),
+    ("gpt-4-1106-preview", ModelPrice::new(10.0, 30.0)),
+    ("gpt-4-vision-preview", ModelPrice::new(10.0, 30.0)),
+    ("gpt-4-1106-vision-preview", ModelPrice::new(10.0, 30.0)),
+    ("gpt-4", ModelPrice::new(30.0, 60.0)),
+    ("gpt-4-32k", ModelPrice::new(60.0, 120.0)),
+    ("gpt-3.5-turbo", ModelPrice::new(0.5, 1.5)),
+    ("gpt-3.5-turbo-1106", ModelPrice::new(1.0, 2.0)),
+    ("gpt-3.5-turbo-0613", ModelPrice::new(1.5, 2.0)),
+    ("gpt-3.5-turbo-0301", ModelPrice::new(1.5, 2.0)),
+    ("gpt-3.5-turbo-16k", ModelPrice::new(3.0, 4.0)),
+    ("gpt-3.5-turbo-instruct", ModelPrice::new(1.5, 2.0)),
+    ("davinci-002", ModelPrice::new(2.0, 2.0)),
+    ("babbage-002", ModelPrice::new(0.4, 0.4)),
+    ("text-embedding-3-small", ModelPrice::new(0.02, 0.0)),
+    ("text-embedding-3-large", ModelPrice::new(0.13, 0.0)),
+    ("text-embedding-ada-002", ModelPrice::new(0.1, 0.0)),
+    // The edit models were free to use.
+    ("text-davinci-edit-001", ModelPrice::new(0.0, 0.0)),
+    ("code-davinci-edit-001", ModelPrice::new(0.0, 0.0)),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::{ChatCompletion, ChatCompletionMessage};
+    use crate::completions::Completion;
+    use crate::embeddings::Embeddings;
+
+    fn assert_cost(cost: Cost, input: f64, cached_input: f64, output: f64) {
+        assert!((cost.input - input).abs() < 1e-12, "{cost:?}");
+        assert!((cost.cached_input - cached_input).abs() < 1e-12, "{cost:?}");
+        assert!((cost.output - output).abs() < 1e-12, "{cost:?}");
+    }
+
+    #[test]
+    fn prices_tokens() {
+        let tokens = TokenCounts {
+            input: 2_000,
+            cached_input: 1_000,
+            output: 500,
+        };
+        let table = PriceTable::builtin();
+
+        let cost = table.cost("gpt-4o", tokens).unwrap();
+        assert_cost(cost, 0.0025, 0.00125, 0.005);
+        assert!((cost.total() - 0.00875).abs() < 1e-12);
+        let batch_cost = table.batch_cost("gpt-4o", tokens).unwrap();
+        assert_cost(batch_cost, 0.00125, 0.000625, 0.0025);
+        // Without a cached price, cached tokens cost as much as others.
+        assert_cost(table.cost("gpt-4", tokens).unwrap(), 0.03, 0.03, 0.03);
+    }
+
+    #[test]
+    fn resolves_snapshots() {
+        let table = PriceTable::builtin();
+
+        assert_eq!(table.price("gpt-4o-2024-08-06"), table.price("gpt-4o"));
+        assert_eq!(
+            table.price("gpt-4o-mini-2024-07-18"),
+            table.price("gpt-4o-mini")
+        );
+        assert_eq!(table.price("gpt-4o-2024-05-13").unwrap().input, 5.0);
+        assert_eq!(
+            table.price("gpt-3.5-turbo-0125"),
+            table.price("gpt-3.5-turbo")
+        );
+        assert_eq!(table.price("gpt-4-turbo-2024-04-09").unwrap().input, 10.0);
+        assert_eq!(table.price("gpt-4o-audio-preview"), None);
+        assert_eq!(table.price("my-deployment"), None);
+        // Only dates name snapshots. Other suffixes name different models.
+        assert_eq!(
+            table.price("gpt-4-1106-vision-preview").unwrap().input,
+            10.0
+        );
+        assert_eq!(
+            table.price("gpt-4-1106-vision-preview").unwrap().output,
+            30.0
+        );
+        assert_eq!(table.price("gpt-4-0613"), table.price("gpt-4"));
+        assert_eq!(table.price("gpt-4-1234-preview"), None);
+        assert_eq!(table.price("gpt-3.5-turbo-0301").unwrap().input, 1.5);
+    }
+
+    #[test]
+    fn response_costs() {
+        let chat_completion: ChatCompletion = serde_json::from_str(
+            r#"{"id":"chatcmpl-1","object":"chat.completion","created":0,"model":"gpt-4o-mini-2024-07-18","choices":[],"usage":{"prompt_tokens":2000,"completion_tokens":1000,"total_tokens":3000,"prompt_tokens_details":{"cached_tokens":1000}}}"#,
+        )
+        .unwrap();
+        assert_cost(chat_completion.cost().unwrap(), 0.00015, 0.000075, 0.0006);
+
+        let embeddings: Embeddings = serde_json::from_str(
+            r#"{"data":[],"model":"text-embedding-3-small","usage":{"prompt_tokens":1000,"total_tokens":1000}}"#,
+        )
+        .unwrap();
+        assert_cost(embeddings.cost().unwrap(), 0.00002, 0.0, 0.0);
+
+        let completion: Completion = serde_json::from_str(
+            r#"{"id":"cmpl-1","created":0,"model":"my-model","choices":[],"usage":{"prompt_tokens":1,"completion_tokens":1,"total_tokens":2}}"#,
+        )
+        .unwrap();
+        assert_eq!(completion.cost(), None);
+    }
+
+    #[test]
+    fn estimates_requests() {
+        let request = ChatCompletion::builder(
+            "gpt-4o",
+            [ChatCompletionMessage::user("Say this is a test")],
+        )
+        .max_tokens(100u64)
+        .build()
+        .unwrap();
+
+        // 4 tokens wrap the message, 5 are its content and 3 prime the reply.
+        assert_eq!(request.estimate_prompt_tokens(), 12);
+        assert_cost(request.estimate_cost().unwrap(), 0.00003, 0.0, 0.001);
+    }
+
+    #[test]
+    fn overrides_prices() {
+        let table = PriceTable::builtin()
+            .with_price("gpt-4o", ModelPrice::new(1.0, 2.0).with_batch_discount(0.0));
+        let tokens = TokenCounts {
+            input: 1_000_000,
+            cached_input: 0,
+            output: 1_000_000,
+        };
+
+        assert_eq!(
+            table.cost("gpt-4o-2024-08-06", tokens).unwrap().total(),
+            3.0
+        );
+        assert_eq!(table.batch_cost("gpt-4o", tokens).unwrap().total(), 3.0);
+        assert_eq!(PriceTable::new().cost("gpt-4o", tokens), None);
+    }
+}
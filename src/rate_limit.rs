@@ -0,0 +1,379 @@
+//! Client-side rate limiting of requests and tokens per minute.
+//!
+//! A [`RateLimiter`] set on an [`OpenAiClient`](crate::OpenAiClient) makes chat, completion and
+//! embedding requests wait until the model's budget has room for them, instead of failing with
+//! a `429`. Clones of the client share the same limiter, so many workers can draw from one budget.
+//!
+//! Token usage is estimated before sending, then corrected from the
+//! [`Usage`](crate::Usage) returned by the API. Requests that fail give their tokens back, and
+//! streams that don't report their usage are corrected when they end, counting a token per chunk.
+//!
+//! ```
+//! use openai::rate_limit::{RateLimit, RateLimiter};
+//! use openai::OpenAiClient;
+//!
+//! let limiter = RateLimiter::new()
+//!     .with_limit("gpt-4", RateLimit::new(500, 30_000))
+//!     .with_default_limit(RateLimit::new(3_500, 90_000));
+//! let client = OpenAiClient::new("sk-...").with_rate_limiter(limiter);
+//! ```
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::pricing::{price_table, Cost, TokenCounts};
+use crate::runtime::{self, Instant};
+
+/// Requests and tokens allowed per minute for a model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RateLimit {
+    pub requests_per_minute: Option<u32>,
+    pub tokens_per_minute: Option<u32>,
+}
+
+impl RateLimit {
+    pub fn new(requests_per_minute: u32, tokens_per_minute: u32) -> Self {
+        RateLimit {
+            requests_per_minute: Some(requests_per_minute),
+            tokens_per_minute: Some(tokens_per_minute),
+        }
+    }
+}
+
+/// Per-model budgets of requests and tokens per minute, refilled continuously.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    limits: HashMap<String, RateLimit>,
+    default_limit: Option<RateLimit>,
+    buckets: Mutex<HashMap<String, Buckets>>,
+}
+
+#[derive(Debug)]
+struct Buckets {
+    requests: Option<Bucket>,
+    tokens: Option<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    capacity: f64,
+    available: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    /// Creates a limiter without any limits.
+    pub fn new() -> Self {
+        RateLimiter::default()
+    }
+
+    /// Limits requests for the given model.
+    pub fn with_limit(mut self, model: impl Into<String>, limit: RateLimit) -> Self {
+        self.limits.insert(model.into(), limit);
+        self
+    }
+
+    /// Limits requests for models without a limit of their own.
+    /// Each model gets its own budget.
+    pub fn with_default_limit(mut self, limit: RateLimit) -> Self {
+        self.default_limit = Some(limit);
+        self
+    }
+
+    /// The limit applied to requests for the given model.
+    pub fn limit(&self, model: &str) -> Option<RateLimit> {
+        self.limits.get(model).copied().or(self.default_limit)
+    }
+
+    /// Waits until the model's budget has room for one request using `tokens` tokens,
+    /// then takes them from the budget.
+    ///
+    /// Requests estimated to use more tokens than a minute's budget wait for a full budget.
+    pub async fn acquire(&self, model: &str, tokens: u32) {
+        let Some(limit) = self.limit(model) else {
+            return;
+        };
+        loop {
+            let wait = self.try_acquire(model, limit, tokens);
+            match wait {
+                None => return,
+                Some(wait) => runtime::sleep(wait).await,
+            }
+        }
+    }
+
+    /// Waits like [`acquire`](RateLimiter::acquire), returning a reservation that corrects the
+    /// budget once the request is over.
+    pub(crate) async fn reserve(
+        self: &Arc<Self>,
+        model: &str,
+        prompt_tokens: u32,
+        completion_tokens: u32,
+    ) -> Reservation {
+        let estimated_tokens = prompt_tokens + completion_tokens;
+        self.acquire(model, estimated_tokens).await;
+        Reservation {
+            limiter: self.clone(),
+            model: model.to_string(),
+            estimated_tokens,
+            prompt_tokens,
+            used_tokens: None,
+            streamed_chunks: 0,
+        }
+    }
+
+    /// Gives back or takes extra tokens once the actual usage of a request is known.
+    pub fn correct(&self, model: &str, estimated_tokens: u32, used_tokens: u32) {
+        let mut buckets = self.buckets.lock().unwrap();
+        if let Some(bucket) = buckets
+            .get_mut(model)
+            .and_then(|buckets| buckets.tokens.as_mut())
+        {
+            bucket.refill(Instant::now());
+            bucket.available = (bucket.available + estimated_tokens as f64 - used_tokens as f64)
+                .min(bucket.capacity);
+        }
+    }
+
+    /// Takes from the budget if possible, or returns how long to wait before trying again.
+    fn try_acquire(&self, model: &str, limit: RateLimit, tokens: u32) -> Option<Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let buckets = buckets.entry(model.to_string()).or_insert_with(|| Buckets {
+            requests: limit.requests_per_minute.map(|rpm| Bucket::new(rpm, now)),
+            tokens: limit.tokens_per_minute.map(|tpm| Bucket::new(tpm, now)),
+        });
+        let request_wait = buckets
+            .requests
+            .as_mut()
+            .and_then(|bucket| bucket.wait_for(1.0, now));
+        let token_wait = buckets
+            .tokens
+            .as_mut()
+            .and_then(|bucket| bucket.wait_for(tokens as f64, now));
+        match request_wait.max(token_wait) {
+            Some(wait) => Some(wait),
+            None => {
+                if let Some(bucket) = buckets.requests.as_mut() {
+                    bucket.available -= 1.0;
+                }
+                if let Some(bucket) = buckets.tokens.as_mut() {
+                    bucket.available -= tokens as f64;
+                }
+                None
+            }
+        }
+    }
+}
+
+impl Bucket {
+    fn new(per_minute: u32, now: Instant) -> Self {
+        Bucket {
+            capacity: per_minute as f64,
+            available: per_minute as f64,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.available = (self.available + elapsed * self.capacity / 60.0).min(self.capacity);
+        self.updated = now;
+    }
+
+    /// How long until `amount` is available, or `None` if it already is.
+    fn wait_for(&mut self, amount: f64, now: Instant) -> Option<Duration> {
+        self.refill(now);
+        let needed = amount.min(self.capacity);
+        if self.available >= needed {
+            return None;
+        }
+        let seconds = (needed - self.available) * 60.0 / self.capacity;
+        Some(Duration::from_secs_f64(seconds).max(Duration::from_millis(1)))
+    }
+}
+
+/// Tokens taken from a [`RateLimiter`] for one request. When dropped, the tokens the request
+/// didn't use are given back: all of them if it failed before any output.
+pub(crate) struct Reservation {
+    limiter: Arc<RateLimiter>,
+    model: String,
+    estimated_tokens: u32,
+    prompt_tokens: u32,
+    used_tokens: Option<u32>,
+    streamed_chunks: u32,
+}
+
+impl Reservation {
+    /// Records the usage reported by the API.
+    pub(crate) fn set_used(&mut self, tokens: u32) {
+        self.used_tokens = Some(tokens);
+    }
+
+    /// Counts a streamed chunk, for streams that end without reporting their usage.
+    /// Each chunk carries about one token of each choice.
+    pub(crate) fn count_chunk(&mut self, choices: usize) {
+        self.streamed_chunks += choices as u32;
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        let used_tokens = self.used_tokens.unwrap_or(match self.streamed_chunks {
+            0 => 0,
+            chunks => self.prompt_tokens + chunks,
+        });
+        self.limiter
+            .correct(&self.model, self.estimated_tokens, used_tokens);
+    }
+}
+
+/// Requests whose token usage can be estimated before sending.
+pub trait TokenEstimate {
+    /// The model the request is sent to.
+    fn model(&self) -> &str;
+    /// A rough estimate of the prompt tokens of the request.
+    fn estimate_prompt_tokens(&self) -> u32;
+    /// The most completion tokens the request can use, or 0 if that isn't known.
+    fn estimate_completion_tokens(&self) -> u32;
+
+    /// A rough estimate of the prompt and completion tokens the request will use.
+    fn estimate_tokens(&self) -> u32 {
+        self.estimate_prompt_tokens() + self.estimate_completion_tokens()
+    }
+
+    /// Estimates the cost of the request with the prices of [`price_table`].
+    fn estimate_cost(&self) -> Option<Cost> {
+        price_table().estimate(self)
+    }
+}
+
+/// Responses that report how many tokens were used.
+pub(crate) trait TokenUsage {
+    fn token_counts(&self) -> Option<TokenCounts>;
+
+    /// The model the response reports, which names the snapshot that was used
+    /// rather than an alias or, on Azure, a deployment.
+    fn model(&self) -> &str;
+}
+
+/// Roughly estimates the number of tokens of English text, at four characters per token.
+pub(crate) fn estimate_text_tokens(text: &str) -> u32 {
+    (text.chars().count() as u32).div_ceil(4)
+}
+
+/// Estimates the number of tokens of text for a model. With the `tokenizer` feature, this is
+/// an exact count for models with a known encoding.
+pub(crate) fn estimate_model_tokens(model: &str, text: &str) -> u32 {
+    #[cfg(feature = "tokenizer")]
+    if let Some(encoding) = crate::tokenizer::Encoding::for_model(model) {
+        return encoding.count(text) as u32;
+    }
+    #[cfg(not(feature = "tokenizer"))]
+    let _ = model;
+    estimate_text_tokens(text)
+}
+
+// The tests run on tokio's paused clock.
+#[cfg(all(test, feature = "tokio"))]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn waits_for_requests() {
+        let limiter = RateLimiter::new().with_limit(
+            "gpt-4",
+            RateLimit {
+                requests_per_minute: Some(2),
+                tokens_per_minute: None,
+            },
+        );
+        let start = Instant::now();
+
+        limiter.acquire("gpt-4", 10).await;
+        limiter.acquire("gpt-4", 10).await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        limiter.acquire("gpt-4", 10).await;
+        assert!(start.elapsed() >= Duration::from_secs(30));
+        assert!(start.elapsed() < Duration::from_secs(31));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn waits_for_tokens_and_corrects() {
+        let limiter = RateLimiter::new().with_default_limit(RateLimit::new(1000, 100));
+        let start = Instant::now();
+
+        limiter.acquire("gpt-3.5-turbo", 80).await;
+        // Only 10 of the 80 estimated tokens were used.
+        limiter.correct("gpt-3.5-turbo", 80, 10);
+        limiter.acquire("gpt-3.5-turbo", 90).await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        // Budgets are per model.
+        limiter.acquire("gpt-4", 100).await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        limiter.acquire("gpt-3.5-turbo", 60).await;
+        assert!(start.elapsed() >= Duration::from_secs(36));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reservations_give_back_unused_tokens() {
+        let limiter = Arc::new(RateLimiter::new().with_default_limit(RateLimit::new(1000, 200)));
+        let start = Instant::now();
+
+        // A failed request gives back everything.
+        drop(limiter.reserve("gpt-4", 30, 40).await);
+        // A stream without usage keeps its prompt and a token per chunk.
+        let mut stream = limiter.reserve("gpt-4", 30, 40).await;
+        stream.count_chunk(1);
+        stream.count_chunk(1);
+        drop(stream);
+        // Reported usage replaces the estimate.
+        let mut reported = limiter.reserve("gpt-4", 30, 40).await;
+        reported.count_chunk(1);
+        reported.set_used(8);
+        drop(reported);
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        limiter.acquire("gpt-4", 160).await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        limiter.acquire("gpt-4", 10).await;
+        assert!(start.elapsed() >= Duration::from_secs(3));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn oversized_requests_wait_for_full_budget() {
+        let limiter = RateLimiter::new().with_limit(
+            "gpt-4",
+            RateLimit {
+                requests_per_minute: None,
+                tokens_per_minute: Some(100),
+            },
+        );
+        let start = Instant::now();
+
+        limiter.acquire("gpt-4", 500).await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        limiter.acquire("gpt-4", 500).await;
+        assert!(start.elapsed() >= Duration::from_secs(300));
+    }
+
+    #[tokio::test]
+    async fn unlimited_models_pass() {
+        let limiter = RateLimiter::new().with_limit("gpt-4", RateLimit::new(1, 1));
+        for _ in 0..10 {
+            limiter.acquire("gpt-3.5-turbo", 1000).await;
+        }
+        assert_eq!(limiter.limit("gpt-3.5-turbo"), None);
+    }
+
+    #[test]
+    fn text_estimate() {
+        assert_eq!(estimate_text_tokens(""), 0);
+        assert_eq!(estimate_text_tokens("Hello"), 2);
+        assert_eq!(estimate_text_tokens("Say this is a test"), 5);
+    }
+}
@@ -0,0 +1,292 @@
+//! Automatic retries with exponential backoff.
+//!
+//! A [`RetryPolicy`] is set on an [`OpenAiClient`](crate::OpenAiClient) and applies to every
+//! request sent through it, including multipart uploads and streams.
+//! Streams are only retried until the response arrives; once events start flowing, errors are
+//! passed on to the caller.
+//!
+//! ```
+//! use openai::retry::RetryPolicy;
+//! use openai::OpenAiClient;
+//! use std::time::Duration;
+//!
+//! let client = OpenAiClient::new("sk-...").with_retry_policy(RetryPolicy {
+//!     max_attempts: 5,
+//!     base_delay: Duration::from_secs(1),
+//!     ..RetryPolicy::default()
+//! });
+//! ```
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, UNIX_EPOCH};
+
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+
+const RETRY_AFTER_MS_HEADER: &str = "retry-after-ms";
+const RETRY_AFTER_HEADER: &str = "retry-after";
+const SHOULD_RETRY_HEADER: &str = "x-should-retry";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// The maximum number of times a request is sent, including the first attempt.
+    /// A value of `1` disables retries.
+    pub max_attempts: u32,
+    /// The delay before the first retry. Each following retry doubles it.
+    pub base_delay: Duration,
+    /// The longest delay between two attempts, before jitter is applied.
+    pub max_delay: Duration,
+    /// The fraction of each delay that is randomized, between `0.0` and `1.0`.
+    /// A jitter of `0.25` waits between 75% and 100% of the computed delay.
+    pub jitter: f64,
+    /// HTTP status codes of responses that are retried.
+    pub retry_statuses: Vec<u16>,
+    /// Whether requests that failed to connect are retried.
+    pub retry_connect_errors: bool,
+    /// Whether requests that timed out are retried.
+    pub retry_timeouts: bool,
+    /// Whether the `retry-after-ms`, `retry-after` and `x-should-retry` response headers
+    /// are honored.
+    pub respect_retry_after: bool,
+    /// The longest delay accepted from a `retry-after` header.
+    /// Responses asking to wait longer are returned to the caller instead.
+    pub max_retry_after: Duration,
+}
+
+impl RetryPolicy {
+    /// A policy that sends every request exactly once.
+    pub fn never() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// Returns how long to wait before retrying a response, or `None` if it should be returned.
+    /// Successful responses are never retried, whatever their headers say.
+    ///
+    /// `attempt` is the number of attempts made so far, starting at 1.
+    pub fn retry_response(
+        &self,
+        attempt: u32,
+        status: StatusCode,
+        headers: &HeaderMap,
+    ) -> Option<Duration> {
+        if attempt >= self.max_attempts || status.is_success() {
+            return None;
+        }
+        if self.respect_retry_after {
+            match header_str(headers, SHOULD_RETRY_HEADER) {
+                Some("true") => return Some(self.backoff(attempt)),
+                Some("false") => return None,
+                _ => {}
+            }
+        }
+        if !self.retry_statuses.contains(&status.as_u16()) {
+            return None;
+        }
+        if !self.respect_retry_after {
+            return Some(self.backoff(attempt));
+        }
+        match retry_after(headers) {
+            Some(delay) if delay > self.max_retry_after => None,
+            Some(delay) => Some(delay),
+            None => Some(self.backoff(attempt)),
+        }
+    }
+
+    /// Returns how long to wait before retrying a request that failed to send,
+    /// or `None` if the error should be returned.
+    pub fn retry_error(&self, attempt: u32, error: &reqwest::Error) -> Option<Duration> {
+        if error.is_timeout() {
+            return self.retry_timeout(attempt);
+        }
+        let retry = self.retry_connect_errors && is_connect(error) && attempt < self.max_attempts;
+        retry.then(|| self.backoff(attempt))
+    }
+
+    /// Returns how long to wait before retrying a request that timed out,
+    /// or `None` if the error should be returned.
+    pub fn retry_timeout(&self, attempt: u32) -> Option<Duration> {
+        let retry = self.retry_timeouts && attempt < self.max_attempts;
+        retry.then(|| self.backoff(attempt))
+    }
+
+    /// The exponential backoff delay after the given attempt, with jitter applied.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31);
+        let delay = self
+            .base_delay
+            .saturating_mul(1 << exponent)
+            .min(self.max_delay);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        delay.mul_f64(1.0 - jitter * random_fraction())
+    }
+}
+
+impl Default for RetryPolicy {
+    /// Retries up to twice on rate limits, server errors, timeouts and connection failures,
+    /// waiting 0.5s and then 1s.
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(8),
+            jitter: 0.25,
+            retry_statuses: vec![408, 409, 429, 500, 502, 503, 504],
+            retry_connect_errors: true,
+            retry_timeouts: true,
+            respect_retry_after: true,
+            max_retry_after: Duration::from_secs(60),
+        }
+    }
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+/// Reads the delay asked for by the `retry-after-ms` or `retry-after` headers.
+/// `retry-after` may be a number of seconds or an HTTP date.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    if let Some(millis) = header_str(headers, RETRY_AFTER_MS_HEADER) {
+        if let Ok(millis) = millis.trim().parse::<f64>() {
+            return Duration::try_from_secs_f64(millis / 1000.0).ok();
+        }
+    }
+    let value = header_str(headers, RETRY_AFTER_HEADER)?.trim();
+    if let Ok(seconds) = value.parse::<f64>() {
+        return Duration::try_from_secs_f64(seconds).ok();
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    // The clock is read through `web_time`, as `SystemTime::now` panics on `wasm32`.
+    let now = web_time::SystemTime::now()
+        .duration_since(web_time::UNIX_EPOCH)
+        .ok()?;
+    Some(date.duration_since(UNIX_EPOCH).ok()?.saturating_sub(now))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn is_connect(error: &reqwest::Error) -> bool {
+    error.is_connect()
+}
+
+/// Connection failures can't be told apart from other errors in the browser.
+#[cfg(target_arch = "wasm32")]
+fn is_connect(_error: &reqwest::Error) -> bool {
+    false
+}
+
+/// A random number in `[0, 1)`, good enough to spread out retries.
+fn random_fraction() -> f64 {
+    let random = RandomState::new().build_hasher().finish();
+    (random >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            jitter: 0.0,
+            ..RetryPolicy::default()
+        }
+    }
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn exponential_backoff() {
+        let policy = policy();
+        assert_eq!(policy.backoff(1), Duration::from_millis(500));
+        assert_eq!(policy.backoff(2), Duration::from_secs(1));
+        assert_eq!(policy.backoff(3), Duration::from_secs(2));
+        assert_eq!(policy.backoff(10), Duration::from_secs(8));
+        assert_eq!(policy.backoff(u32::MAX), Duration::from_secs(8));
+    }
+
+    #[test]
+    fn jitter_shortens_delay() {
+        let policy = RetryPolicy {
+            jitter: 0.5,
+            ..RetryPolicy::default()
+        };
+        for _ in 0..100 {
+            let delay = policy.backoff(2);
+            assert!(delay <= Duration::from_secs(1));
+            assert!(delay >= Duration::from_millis(500));
+        }
+    }
+
+    #[test]
+    fn retries_configured_statuses() {
+        let policy = policy();
+        let empty = HeaderMap::new();
+        assert!(policy
+            .retry_response(1, StatusCode::TOO_MANY_REQUESTS, &empty)
+            .is_some());
+        assert!(policy
+            .retry_response(1, StatusCode::BAD_GATEWAY, &empty)
+            .is_some());
+        assert!(policy
+            .retry_response(1, StatusCode::BAD_REQUEST, &empty)
+            .is_none());
+        assert!(policy
+            .retry_response(3, StatusCode::TOO_MANY_REQUESTS, &empty)
+            .is_none());
+        assert!(RetryPolicy::never()
+            .retry_response(1, StatusCode::TOO_MANY_REQUESTS, &empty)
+            .is_none());
+    }
+
+    #[test]
+    fn honors_retry_after_headers() {
+        let policy = policy();
+        let status = StatusCode::TOO_MANY_REQUESTS;
+        assert_eq!(
+            policy.retry_response(1, status, &headers(&[("retry-after-ms", "1500")])),
+            Some(Duration::from_millis(1500))
+        );
+        assert_eq!(
+            policy.retry_response(1, status, &headers(&[("retry-after", "3")])),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(
+            policy.retry_response(1, status, &headers(&[("retry-after", "600")])),
+            None
+        );
+        assert_eq!(
+            policy.retry_response(
+                1,
+                status,
+                &headers(&[("retry-after", "Wed, 21 Oct 2015 07:28:00 GMT")])
+            ),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(
+            policy.retry_response(1, status, &headers(&[("x-should-retry", "false")])),
+            None
+        );
+        assert_eq!(
+            policy.retry_response(
+                1,
+                StatusCode::BAD_REQUEST,
+                &headers(&[("x-should-retry", "true")])
+            ),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(
+            policy.retry_response(1, StatusCode::OK, &headers(&[("x-should-retry", "true")])),
+            None
+        );
+    }
+}
@@ -0,0 +1,127 @@
+//! The few things the client needs from an async runtime.
+//!
+//! With the `tokio` feature, which is enabled by default, timers and file reads use tokio.
+//! Without it, timers come from `futures-timer`, which works with any executor, including
+//! the browser's on `wasm32`, and nothing is spawned.
+
+use std::future::Future;
+use std::io;
+use std::path::Path;
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[cfg(feature = "tokio")]
+pub(crate) use tokio::time::Instant;
+#[cfg(not(feature = "tokio"))]
+pub(crate) use web_time::Instant;
+
+/// A boxed stream, which must be `Send` everywhere but on `wasm32`,
+/// where responses are read through the browser and can't leave their thread.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) type BoxStream<'a, T> = futures_util::stream::BoxStream<'a, T>;
+#[cfg(target_arch = "wasm32")]
+pub(crate) type BoxStream<'a, T> = futures_util::stream::LocalBoxStream<'a, T>;
+
+/// A boxed future, which must be `Send` everywhere but on `wasm32`.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) type BoxFuture<'a, T> = futures_util::future::BoxFuture<'a, T>;
+#[cfg(target_arch = "wasm32")]
+pub(crate) type BoxFuture<'a, T> = futures_util::future::LocalBoxFuture<'a, T>;
+
+/// `Send`, except on `wasm32`.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) trait MaybeSend: Send {}
+#[cfg(not(target_arch = "wasm32"))]
+impl<T: Send> MaybeSend for T {}
+#[cfg(target_arch = "wasm32")]
+pub(crate) trait MaybeSend {}
+#[cfg(target_arch = "wasm32")]
+impl<T> MaybeSend for T {}
+
+pub(crate) async fn sleep(duration: Duration) {
+    #[cfg(feature = "tokio")]
+    tokio::time::sleep(duration).await;
+    #[cfg(not(feature = "tokio"))]
+    futures_timer::Delay::new(duration).await;
+}
+
+/// Runs `future`, or returns `None` if it doesn't complete within `duration`.
+pub(crate) async fn timeout<F: Future>(duration: Duration, future: F) -> Option<F::Output> {
+    #[cfg(feature = "tokio")]
+    return tokio::time::timeout(duration, future).await.ok();
+    #[cfg(not(feature = "tokio"))]
+    {
+        use futures_util::future::{select, Either};
+
+        let future = std::pin::pin!(future);
+        match select(future, futures_timer::Delay::new(duration)).await {
+            Either::Left((output, _)) => Some(output),
+            Either::Right(_) => None,
+        }
+    }
+}
+
+/// Writes a whole local file, creating its directory if needed.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) async fn write_file(path: &Path, contents: Vec<u8>) -> io::Result<()> {
+    #[cfg(feature = "tokio")]
+    {
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(path, contents).await
+    }
+    #[cfg(not(feature = "tokio"))]
+    {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, contents)
+    }
+}
+
+/// A local file sent as a part of a multipart request.
+///
+/// With the `tokio` feature, the file is streamed from disk and opened again for each attempt,
+/// so large files are never held in memory. Without it, and on `wasm32`, whose multipart forms
+/// can't stream, the file is read into memory once.
+pub(crate) struct UploadFile {
+    #[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+    path: PathBuf,
+    #[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+    len: u64,
+    #[cfg(not(all(feature = "tokio", not(target_arch = "wasm32"))))]
+    contents: bytes::Bytes,
+}
+
+impl UploadFile {
+    pub(crate) async fn open(path: &Path) -> io::Result<Self> {
+        #[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+        return Ok(UploadFile {
+            path: path.to_path_buf(),
+            len: tokio::fs::metadata(path).await?.len(),
+        });
+        #[cfg(not(all(feature = "tokio", not(target_arch = "wasm32"))))]
+        Ok(UploadFile {
+            contents: bytes::Bytes::from(std::fs::read(path)?),
+        })
+    }
+
+    /// A part with the contents of the file. If the file can no longer be opened, the error is
+    /// reported when the part is sent.
+    pub(crate) fn part(&self) -> reqwest::multipart::Part {
+        #[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+        return reqwest::multipart::Part::stream_with_length(
+            match std::fs::File::open(&self.path) {
+                Ok(file) => tokio::fs::File::from_std(file).into(),
+                Err(error) => reqwest::Body::wrap_stream(futures_util::stream::once(async move {
+                    Err::<bytes::Bytes, _>(error)
+                })),
+            },
+            self.len,
+        );
+        #[cfg(not(all(feature = "tokio", not(target_arch = "wasm32"))))]
+        reqwest::multipart::Part::bytes(self.contents.to_vec())
+    }
+}
@@ -0,0 +1,721 @@
+//! JSON Schemas of Rust types, to define functions from the types of their arguments.
+//!
+//! [`JsonSchema`] gives the schema of a type, and [`FunctionArguments`] turns the type of a
+//! function's arguments into its [`ChatCompletionFunctionDefinition`], and parses the arguments
+//! of its calls back into that type. With the `derive` feature, both are derived from the type,
+//! with the descriptions taken from its doc comments:
+//!
+//! ```
+//! # #[cfg(feature = "derive")]
+//! # fn main() {
+//! use openai::schema::{FunctionArguments, JsonSchema};
+//! use serde::Deserialize;
+//!
+//! /// Get the current weather in a city.
+//! #[derive(Deserialize, FunctionArguments)]
+//! struct GetWeather {
+//!     /// The name of the city, in English.
+//!     city: String,
+//!     unit: Option<Unit>,
+//! }
+//!
+//! #[derive(Deserialize, JsonSchema)]
+//! #[serde(rename_all = "lowercase")]
+//! enum Unit {
+//!     Celsius,
+//!     Fahrenheit,
+//! }
+//!
+//! let definition = GetWeather::definition();
+//! assert_eq!(definition.name, "get_weather");
+//! assert_eq!(definition.description.as_deref(), Some("Get the current weather in a city."));
+//!
+//! let error = GetWeather::from_arguments(r#"{"city": "Paris", "unit": "kelvin"}"#).err().unwrap();
+//! assert_eq!(error.path.as_deref(), Some("unit"));
+//! # }
+//! # #[cfg(not(feature = "derive"))]
+//! # fn main() {}
+//! ```
+//!
+//! The derived function name is the type's name in `snake_case`, unless set with
+//! `#[function(name = "...")]`. `#[derive(FunctionArguments)]` also derives `JsonSchema`, which
+//! is derived on its own for the types of the fields.
+//!
+//! `Option` fields and fields with `#[serde(default)]` are optional. Objects don't allow
+//! properties that aren't fields. A type that contains itself, through a `Box`, `Option` or `Vec`,
+//! refers to its own schema with a `$ref`.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt;
+
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+use crate::chat::{
+    ChatCompletionFunctionCall, ChatCompletionFunctionDefinition, ChatCompletionTool,
+};
+
+#[cfg(feature = "derive")]
+pub use openai_derive::{FunctionArguments, JsonSchema};
+
+/// A type with a JSON Schema, describing its values as deserialized by serde.
+pub trait JsonSchema {
+    fn json_schema() -> Value;
+
+    /// Whether the field of an object can be left out when it has this type, as with `Option`.
+    fn optional() -> bool {
+        false
+    }
+}
+
+/// The arguments of a function the model can call.
+pub trait FunctionArguments: JsonSchema + DeserializeOwned {
+    /// The name of the function.
+    const NAME: &'static str;
+    /// The description of the function, which tells the model when to call it.
+    const DESCRIPTION: Option<&'static str> = None;
+
+    /// The definition of the function, with the schema of `Self` as its parameters.
+    fn definition() -> ChatCompletionFunctionDefinition {
+        ChatCompletionFunctionDefinition {
+            name: Self::NAME.to_string(),
+            description: Self::DESCRIPTION.map(str::to_string),
+            parameters: Some(Self::json_schema()),
+        }
+    }
+
+    /// The function as a tool of a chat completion request.
+    fn tool() -> ChatCompletionTool {
+        ChatCompletionTool::function(Self::definition())
+    }
+
+    /// Parses the JSON arguments of a call.
+    fn from_arguments(arguments: &str) -> Result<Self, ArgumentsError> {
+        parse_arguments(Self::NAME, arguments)
+    }
+
+    /// Parses the arguments of a call, after checking that it calls this function.
+    fn from_call(call: &ChatCompletionFunctionCall) -> Result<Self, ArgumentsError> {
+        if call.name != Self::NAME {
+            return Err(ArgumentsError {
+                function: call.name.clone(),
+                path: None,
+                message: format!("expected a call of `{}`", Self::NAME),
+            });
+        }
+        Self::from_arguments(&call.arguments)
+    }
+}
+
+/// The arguments of a function call don't match the type of the function's arguments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArgumentsError {
+    /// The name of the function called.
+    pub function: String,
+    /// The path of the value that failed to parse, such as `items[0].name`, or `None` if the
+    /// arguments as a whole are invalid.
+    pub path: Option<String>,
+    /// What is wrong with the value.
+    pub message: String,
+}
+
+impl fmt::Display for ArgumentsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid arguments for `{}`", self.function)?;
+        if let Some(path) = &self.path {
+            write!(f, " at `{path}`")?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl std::error::Error for ArgumentsError {}
+
+/// Parses the JSON arguments of a call of `function`. Models send empty arguments for functions
+/// without parameters, which are read as an empty object.
+pub(crate) fn parse_arguments<T: DeserializeOwned>(
+    function: &str,
+    arguments: &str,
+) -> Result<T, ArgumentsError> {
+    let arguments = match arguments.trim() {
+        "" => "{}",
+        arguments => arguments,
+    };
+    let mut deserializer = serde_json::Deserializer::from_str(arguments);
+    serde_path_to_error::deserialize(&mut deserializer)
+        .map_err(|error| ArgumentsError::new(function, error))
+}
+
+/// Like [`parse_arguments`], for arguments already parsed as JSON.
+pub(crate) fn arguments_from_value<T: DeserializeOwned>(
+    function: &str,
+    arguments: Value,
+) -> Result<T, ArgumentsError> {
+    serde_path_to_error::deserialize(arguments)
+        .map_err(|error| ArgumentsError::new(function, error))
+}
+
+impl ArgumentsError {
+    fn new(function: &str, error: serde_path_to_error::Error<serde_json::Error>) -> Self {
+        let path = error.path().to_string();
+        ArgumentsError {
+            function: function.to_string(),
+            path: (path != ".").then_some(path),
+            message: error.into_inner().to_string(),
+        }
+    }
+}
+
+/// Converts a schema to the subset accepted by strict structured outputs and function calls.
+///
+/// Strict schemas require every property of an object, so the optional properties become
+/// nullable instead. Fields with `#[serde(default)]` should then be `Option`s, which accept the
+/// `null` sent in place of a missing value. `uniqueItems` isn't supported, and is removed.
+pub fn strict_schema(mut schema: Value) -> Value {
+    make_strict(&mut schema);
+    schema
+}
+
+fn make_strict(schema: &mut Value) {
+    let Value::Object(object) = schema else {
+        return;
+    };
+    object.remove("uniqueItems");
+    for key in ["items", "additionalProperties"] {
+        if let Some(schema) = object.get_mut(key) {
+            make_strict(schema);
+        }
+    }
+    if let Some(Value::Array(schemas)) = object.get_mut("anyOf") {
+        schemas.iter_mut().for_each(make_strict);
+    }
+    if let Some(Value::Object(definitions)) = object.get_mut("$defs") {
+        definitions.values_mut().for_each(make_strict);
+    }
+    let Some(Value::Object(properties)) = object.get("properties") else {
+        return;
+    };
+    let mut required: Vec<Value> = match object.get("required") {
+        Some(Value::Array(required)) => required.clone(),
+        _ => Vec::new(),
+    };
+    let mut properties = properties.clone();
+    for (name, property) in properties.iter_mut() {
+        make_strict(property);
+        if !required.iter().any(|required| required == name) {
+            *property = json!({ "anyOf": [property.take(), { "type": "null" }] });
+            required.push(Value::from(name.as_str()));
+        }
+    }
+    object.insert("required".to_string(), Value::Array(required));
+    object.insert("properties".to_string(), Value::Object(properties));
+}
+
+/// A name for the schema of `T`, from the name of the type without its path and generics.
+pub(crate) fn schema_name<T: ?Sized>() -> String {
+    let name = std::any::type_name::<T>();
+    let name = name.split('<').next().unwrap_or(name);
+    let name = name.rsplit("::").next().unwrap_or(name);
+    name.chars()
+        .map(|ch| match ch.is_ascii_alphanumeric() || ch == '-' {
+            true => ch,
+            false => '_',
+        })
+        .take(64)
+        .collect()
+}
+
+macro_rules! impl_json_schema {
+    ($($ty:ty),* => $schema:tt) => {
+        $(
+            impl JsonSchema for $ty {
+                fn json_schema() -> Value {
+                    json!($schema)
+                }
+            }
+        )*
+    };
+}
+
+impl_json_schema!(bool => { "type": "boolean" });
+impl_json_schema!(String, str, char => { "type": "string" });
+impl_json_schema!(i8, i16, i32, i64, i128, isize => { "type": "integer" });
+impl_json_schema!(u8, u16, u32, u64, u128, usize => { "type": "integer", "minimum": 0 });
+impl_json_schema!(f32, f64 => { "type": "number" });
+impl_json_schema!(() => { "type": "null" });
+impl_json_schema!(Value => {});
+
+impl<T: JsonSchema> JsonSchema for Option<T> {
+    fn json_schema() -> Value {
+        T::json_schema()
+    }
+
+    fn optional() -> bool {
+        true
+    }
+}
+
+impl<T: JsonSchema + ?Sized> JsonSchema for Box<T> {
+    fn json_schema() -> Value {
+        T::json_schema()
+    }
+
+    fn optional() -> bool {
+        T::optional()
+    }
+}
+
+macro_rules! impl_array_schema {
+    ($($ty:ident),* => $unique:literal) => {
+        $(
+            impl<T: JsonSchema> JsonSchema for $ty<T> {
+                fn json_schema() -> Value {
+                    let mut schema = json!({ "type": "array", "items": T::json_schema() });
+                    if $unique {
+                        schema["uniqueItems"] = Value::Bool(true);
+                    }
+                    schema
+                }
+            }
+        )*
+    };
+}
+
+impl_array_schema!(Vec, VecDeque => false);
+impl_array_schema!(HashSet, BTreeSet => true);
+
+impl<T: JsonSchema> JsonSchema for [T] {
+    fn json_schema() -> Value {
+        Vec::<T>::json_schema()
+    }
+}
+
+impl<V: JsonSchema, S> JsonSchema for HashMap<String, V, S> {
+    fn json_schema() -> Value {
+        json!({ "type": "object", "additionalProperties": V::json_schema() })
+    }
+}
+
+impl<V: JsonSchema> JsonSchema for BTreeMap<String, V> {
+    fn json_schema() -> Value {
+        HashMap::<String, V>::json_schema()
+    }
+}
+
+/// Helpers of the code generated by the derive macros.
+#[doc(hidden)]
+pub mod __private {
+    use std::cell::RefCell;
+
+    use serde_json::{json, Map};
+
+    pub use serde_json::Value;
+
+    /// The derived types whose schemas are being built, innermost last, with whether they were
+    /// referred to from inside their own schema, and the schemas of the types that were.
+    #[derive(Default)]
+    struct Definitions {
+        building: Vec<(&'static str, bool)>,
+        schemas: Map<String, Value>,
+    }
+
+    thread_local! {
+        static DEFINITIONS: RefCell<Definitions> = RefCell::default();
+    }
+
+    /// The schema of the derived type `T`, built by `schema`. Within its own schema, a type is
+    /// given by a `$ref`: to the root for the outermost derived type, and otherwise to its
+    /// schema in the `$defs` added to the outermost one, which is expected to be the root.
+    pub fn definition<T: ?Sized>(schema: impl FnOnce() -> Value) -> Value {
+        let type_name = std::any::type_name::<T>();
+        let name = super::schema_name::<T>();
+        let reference = DEFINITIONS.with(|definitions| {
+            let mut definitions = definitions.borrow_mut();
+            let building = &mut definitions.building;
+            match building
+                .iter()
+                .position(|(building, _)| *building == type_name)
+            {
+                Some(index) => {
+                    building[index].1 = true;
+                    Some(match index {
+                        0 => json!({ "$ref": "#" }),
+                        _ => json!({ "$ref": format!("#/$defs/{name}") }),
+                    })
+                }
+                None => {
+                    building.push((type_name, false));
+                    None
+                }
+            }
+        });
+        if let Some(reference) = reference {
+            return reference;
+        }
+        let mut schema = schema();
+        DEFINITIONS.with(|definitions| {
+            let mut definitions = definitions.borrow_mut();
+            let (_, referenced) = definitions.building.pop().unwrap_or_default();
+            if definitions.building.is_empty() {
+                let schemas = std::mem::take(&mut definitions.schemas);
+                if let (false, Value::Object(object)) = (schemas.is_empty(), &mut schema) {
+                    object.insert("$defs".to_string(), Value::Object(schemas));
+                }
+            } else if referenced {
+                definitions.schemas.insert(name.clone(), schema.take());
+                schema = json!({ "$ref": format!("#/$defs/{name}") });
+            }
+        });
+        schema
+    }
+
+    /// An object schema, built one property at a time.
+    pub struct ObjectSchema {
+        properties: Map<String, Value>,
+        required: Vec<Value>,
+    }
+
+    impl ObjectSchema {
+        #[allow(clippy::new_without_default)]
+        pub fn new() -> Self {
+            ObjectSchema {
+                properties: Map::new(),
+                required: Vec::new(),
+            }
+        }
+
+        pub fn property(
+            &mut self,
+            name: &str,
+            schema: Value,
+            description: Option<&str>,
+            required: bool,
+        ) {
+            self.properties
+                .insert(name.to_string(), describe(schema, description));
+            if required {
+                self.required.push(Value::from(name));
+            }
+        }
+
+        /// Adds the properties of a flattened field.
+        pub fn flatten(&mut self, schema: Value) {
+            if let Some(Value::Object(properties)) = schema.get("properties") {
+                self.properties.extend(properties.clone());
+            }
+            if let Some(Value::Array(required)) = schema.get("required") {
+                self.required.extend(required.iter().cloned());
+            }
+        }
+
+        pub fn into_schema(self) -> Value {
+            json!({
+                "type": "object",
+                "properties": self.properties,
+                "required": self.required,
+                "additionalProperties": false,
+            })
+        }
+    }
+
+    pub fn describe(mut schema: Value, description: Option<&str>) -> Value {
+        if let (Some(description), Value::Object(schema)) = (description, &mut schema) {
+            schema.insert("description".to_string(), Value::from(description));
+        }
+        schema
+    }
+
+    pub fn string_enum(names: &[&str]) -> Value {
+        json!({ "type": "string", "enum": names })
+    }
+
+    pub fn constant(name: &str) -> Value {
+        string_enum(&[name])
+    }
+
+    pub fn null() -> Value {
+        json!({ "type": "null" })
+    }
+
+    pub fn any_of(schemas: Vec<Value>) -> Value {
+        json!({ "anyOf": schemas })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openai_derive::{FunctionArguments, JsonSchema};
+    use serde::Deserialize;
+
+    /// Search the catalog.
+    ///
+    /// Returns at most `limit` products.
+    #[derive(Deserialize, FunctionArguments, Debug, PartialEq)]
+    #[allow(dead_code)]
+    struct SearchProducts {
+        /// Words to look for.
+        query: String,
+        #[serde(default)]
+        limit: u32,
+        filters: Vec<Filter>,
+        sort: Option<Sort>,
+        #[serde(skip)]
+        cursor: Option<String>,
+    }
+
+    #[derive(Deserialize, JsonSchema, Debug, PartialEq)]
+    #[serde(tag = "kind", rename_all = "snake_case")]
+    enum Filter {
+        /// Products in this price range, in cents.
+        Price {
+            min: u64,
+            max: u64,
+        },
+        InStock,
+    }
+
+    #[derive(Deserialize, JsonSchema, Debug, PartialEq)]
+    #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+    enum Sort {
+        PriceAscending,
+        #[serde(rename = "newest")]
+        Newest,
+    }
+
+    #[derive(Deserialize, FunctionArguments)]
+    #[function(name = "lookup", description = "Look up a word.")]
+    #[allow(dead_code)]
+    struct Lookup {
+        #[serde(rename = "term")]
+        word: String,
+        #[serde(flatten)]
+        options: LookupOptions,
+    }
+
+    #[derive(Deserialize, JsonSchema)]
+    #[serde(rename_all = "camelCase")]
+    #[allow(dead_code)]
+    struct LookupOptions {
+        include_examples: bool,
+    }
+
+    #[derive(Deserialize, JsonSchema)]
+    #[allow(dead_code)]
+    enum Shape {
+        Circle(f64),
+        Square { side: f64 },
+        Empty,
+    }
+
+    #[test]
+    fn derives_definitions() {
+        let definition = SearchProducts::definition();
+
+        assert_eq!(definition.name, "search_products");
+        assert_eq!(
+            definition.description.as_deref(),
+            Some("Search the catalog.\n\nReturns at most `limit` products.")
+        );
+        assert_eq!(
+            definition.parameters.unwrap(),
+            json!({
+                "type": "object",
+                "properties": {
+                    "query": { "type": "string", "description": "Words to look for." },
+                    "limit": { "type": "integer", "minimum": 0 },
+                    "filters": {
+                        "type": "array",
+                        "items": {
+                            "anyOf": [
+                                {
+                                    "type": "object",
+                                    "properties": {
+                                        "kind": { "type": "string", "enum": ["price"] },
+                                        "min": { "type": "integer", "minimum": 0 },
+                                        "max": { "type": "integer", "minimum": 0 }
+                                    },
+                                    "required": ["kind", "min", "max"],
+                                    "additionalProperties": false,
+                                    "description": "Products in this price range, in cents."
+                                },
+                                {
+                                    "type": "object",
+                                    "properties": {
+                                        "kind": { "type": "string", "enum": ["in_stock"] }
+                                    },
+                                    "required": ["kind"],
+                                    "additionalProperties": false
+                                }
+                            ]
+                        }
+                    },
+                    "sort": { "type": "string", "enum": ["PRICE_ASCENDING", "newest"] }
+                },
+                "required": ["query", "filters"],
+                "additionalProperties": false
+            })
+        );
+        let lookup = Lookup::definition();
+        assert_eq!(lookup.name, "lookup");
+        assert_eq!(lookup.description.as_deref(), Some("Look up a word."));
+        assert_eq!(
+            lookup.parameters.unwrap(),
+            json!({
+                "type": "object",
+                "properties": {
+                    "term": { "type": "string" },
+                    "includeExamples": { "type": "boolean" }
+                },
+                "required": ["term", "includeExamples"],
+                "additionalProperties": false
+            })
+        );
+        assert_eq!(
+            Shape::json_schema(),
+            json!({
+                "anyOf": [
+                    {
+                        "type": "object",
+                        "properties": { "Circle": { "type": "number" } },
+                        "required": ["Circle"],
+                        "additionalProperties": false
+                    },
+                    {
+                        "type": "object",
+                        "properties": {
+                            "Square": {
+                                "type": "object",
+                                "properties": { "side": { "type": "number" } },
+                                "required": ["side"],
+                                "additionalProperties": false
+                            }
+                        },
+                        "required": ["Square"],
+                        "additionalProperties": false
+                    },
+                    { "type": "string", "enum": ["Empty"] }
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn refers_to_recursive_types() {
+        #[derive(Deserialize, JsonSchema)]
+        #[allow(dead_code)]
+        struct Node {
+            name: String,
+            children: Vec<Node>,
+        }
+
+        #[derive(Deserialize, JsonSchema)]
+        #[allow(dead_code)]
+        struct Tree {
+            root: Node,
+            largest: Option<Box<Node>>,
+        }
+
+        let node = Node::json_schema();
+        assert_eq!(
+            node["properties"]["children"]["items"],
+            json!({ "$ref": "#" })
+        );
+        assert_eq!(node.get("$defs"), None);
+
+        let tree = strict_schema(Tree::json_schema());
+        let reference = json!({ "$ref": "#/$defs/Node" });
+        assert_eq!(tree["properties"]["root"], reference);
+        assert_eq!(tree["properties"]["largest"]["anyOf"][0], reference);
+        assert_eq!(
+            tree["$defs"]["Node"]["required"],
+            json!(["name", "children"])
+        );
+        assert_eq!(
+            tree["$defs"]["Node"]["properties"]["children"]["items"],
+            reference
+        );
+        // Building a schema leaves nothing behind for the next one.
+        assert_eq!(Tree::json_schema(), Tree::json_schema());
+    }
+
+    #[test]
+    fn makes_schemas_strict() {
+        let strict = strict_schema(SearchProducts::json_schema());
+
+        assert_eq!(
+            strict["required"],
+            json!(["query", "filters", "limit", "sort"])
+        );
+        assert_eq!(
+            strict["properties"]["limit"],
+            json!({ "anyOf": [{ "type": "integer", "minimum": 0 }, { "type": "null" }] })
+        );
+        assert_eq!(
+            strict["properties"]["filters"]["items"]["anyOf"][0]["required"],
+            json!(["kind", "min", "max"])
+        );
+        assert_eq!(
+            strict_schema(HashSet::<String>::json_schema()),
+            json!({ "type": "array", "items": { "type": "string" } })
+        );
+        assert_eq!(schema_name::<SearchProducts>(), "SearchProducts");
+        assert_eq!(schema_name::<Vec<Option<Sort>>>(), "Vec");
+    }
+
+    #[test]
+    fn parses_arguments() {
+        let call = ChatCompletionFunctionCall {
+            name: "search_products".to_string(),
+            arguments: r#"{"query":"lamp","filters":[{"kind":"in_stock"}],"sort":"newest"}"#
+                .to_string(),
+        };
+
+        assert_eq!(
+            SearchProducts::from_call(&call).unwrap(),
+            SearchProducts {
+                query: "lamp".to_string(),
+                limit: 0,
+                filters: vec![Filter::InStock],
+                sort: Some(Sort::Newest),
+                cursor: None,
+            }
+        );
+        assert_eq!(
+            call.parse_arguments::<Value>().unwrap()["query"],
+            Value::from("lamp")
+        );
+
+        let error = SearchProducts::from_arguments(
+            r#"{"query":"lamp","filters":[{"kind":"price","min":100,"max":-1}]}"#,
+        )
+        .unwrap_err();
+        assert_eq!(error.function, "search_products");
+        // Internally tagged enums are buffered before being parsed, which loses the inner path.
+        assert_eq!(error.path.as_deref(), Some("filters[0]"));
+        assert!(error.message.starts_with("invalid value: integer `-1`"));
+        let error = SearchProducts::from_arguments(
+            r#"{"query":"lamp","filters":[{"kind":"in_stock"}],"sort":"oldest"}"#,
+        )
+        .unwrap_err();
+        assert_eq!(error.path.as_deref(), Some("sort"));
+        assert!(error
+            .to_string()
+            .starts_with("invalid arguments for `search_products` at `sort`: unknown variant"));
+
+        let error = SearchProducts::from_arguments(r#"{"query":"lamp"}"#).unwrap_err();
+        assert_eq!(error.path, None);
+        assert!(error.message.starts_with("missing field `filters`"));
+        let error = SearchProducts::from_arguments(r#"{"query":"#).unwrap_err();
+        assert!(error.message.contains("line 1 column 9"));
+
+        let other = ChatCompletionFunctionCall {
+            name: "lookup".to_string(),
+            arguments: "{}".to_string(),
+        };
+        assert_eq!(
+            SearchProducts::from_call(&other).unwrap_err().to_string(),
+            "invalid arguments for `lookup`: expected a call of `search_products`"
+        );
+    }
+}
@@ -1,21 +1,72 @@
+use crate::client::{default_client, request_client, RequestHeaders};
+use crate::meta::HasResponseMeta;
+use crate::pagination::{self, ListQuery, Page};
+use crate::ResponseMeta;
+use crate::{
+    openai_delete, openai_get, openai_post, ApiResponseOrError, OpenAiClient, OpenAiError,
+};
+use derive_builder::Builder;
+use futures_util::Stream;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use crate::{openai_get, openai_post, openai_delete, ApiResponseOrError};
-use derive_builder::Builder;
 use std::collections::HashMap as Map;
 
+const BETA_HEADER: &str = "openai-beta";
+const ASSISTANTS_BETA: &str = "assistants=v2";
+
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct Thread {
     pub id: String,
     pub object: String,
+    /// Unix timestamp, seconds since epoch, of when the thread was created.
+    #[serde(alias = "created_at")]
     pub created: u32,
     pub metadata: Value,
+    /// Fields of the response this crate doesn't know about.
+    #[serde(flatten)]
+    pub extra_fields: serde_json::Map<String, Value>,
+    /// Metadata of the response this was parsed from.
+    #[serde(skip)]
+    pub meta: Option<ResponseMeta>,
 }
 
+/// A request to create a thread, built with [`Thread::builder`] to set per-request options
+/// such as the organization.
 #[derive(Builder, Deserialize, Serialize, Clone, Debug)]
+#[builder(pattern = "owned")]
+#[builder(build_fn(error = "OpenAiError"))]
 pub struct ThreadBuilder {
     pub messages: Vec<Message>,
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub metadata: Option<Value>,
+    /// The client to send the request with. Uses the default client if not set.
+    #[serde(skip)]
+    #[builder(default, setter(strip_option, into))]
+    pub client: Option<OpenAiClient>,
+    /// Headers sent with this request, replacing the client's headers of the same name.
+    #[serde(skip)]
+    #[builder(
+        setter(custom),
+        field(type = "RequestHeaders", build = "self.headers.build()?")
+    )]
+    pub headers: HeaderMap,
+    /// Extra parameters sent in the body of the request.
+    #[serde(flatten)]
+    #[builder(default, setter(custom))]
+    pub extra_body: serde_json::Map<String, Value>,
+}
+
+request_builder_setters!(ThreadBuilderBuilder);
+
+impl ThreadBuilderBuilder {
+    /// Creates the thread, with this request's client and headers.
+    pub async fn create(self) -> ApiResponseOrError<Thread> {
+        let request = self.build()?;
+        let client = request_client(&request.client, &request.headers);
+        openai_post(&assistants_client(&client), "threads", &request).await
+    }
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
@@ -23,9 +74,13 @@ pub struct DeletedThread {
     pub id: String,
     pub object: String,
     pub deleted: bool,
+    /// Metadata of the response this was parsed from.
+    #[serde(skip)]
+    pub meta: Option<ResponseMeta>,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(rename_all = "lowercase")]
 pub enum Role {
     Owner,
     Assistant,
@@ -63,31 +118,74 @@ impl Thread {
         messages: Vec<Message>,
         metadata: Map<String, String>,
     ) -> ApiResponseOrError<Self> {
-        openai_post("threads", &serde_json::json!({ "messages": messages, "metadata": metadata })).await
+        Thread::create_with_client(&default_client(), messages, metadata).await
+    }
+
+    /// Builds a request to create a thread, which can set per-request options
+    /// such as the organization, project or other headers.
+    pub fn builder(messages: Vec<Message>) -> ThreadBuilderBuilder {
+        ThreadBuilderBuilder::create_empty().messages(messages)
+    }
+
+    /// Creates a new thread using the given client.
+    pub async fn create_with_client(
+        client: &OpenAiClient,
+        messages: Vec<Message>,
+        metadata: Map<String, String>,
+    ) -> ApiResponseOrError<Self> {
+        openai_post(
+            &assistants_client(client),
+            "threads",
+            &serde_json::json!({ "messages": messages, "metadata": metadata }),
+        )
+        .await
     }
 
     /// Retrieves a thread instance,
     /// providing basic information about the thread such as the owner and metadata.
     pub async fn from(id: &str) -> ApiResponseOrError<Self> {
-        openai_get(&format!("threads/{id}")).await
+        Thread::from_with_client(&default_client(), id).await
+    }
+
+    /// Retrieves a thread instance using the given client.
+    pub async fn from_with_client(client: &OpenAiClient, id: &str) -> ApiResponseOrError<Self> {
+        openai_get(&assistants_client(client), &format!("threads/{id}")).await
     }
 
     /// Modifies a thread instance,
     /// changing the metadata.
-    pub async fn update(
+    pub async fn update(id: &str, metadata: Map<String, String>) -> ApiResponseOrError<Self> {
+        Thread::update_with_client(&default_client(), id, metadata).await
+    }
+
+    /// Modifies a thread instance using the given client.
+    pub async fn update_with_client(
+        client: &OpenAiClient,
         id: &str,
         metadata: Map<String, String>,
     ) -> ApiResponseOrError<Self> {
-        openai_post(&format!("threads/{id}"), &serde_json::json!({ "metadata": metadata })).await
+        openai_post(
+            &assistants_client(client),
+            &format!("threads/{id}"),
+            &serde_json::json!({ "metadata": metadata }),
+        )
+        .await
     }
 
     /// Deletes a thread instance.
     /// This is a permanent action and cannot be undone.
     pub async fn delete(id: &str) -> ApiResponseOrError<DeletedThread> {
-        openai_delete(&format!("threads/{id}")).await
+        Thread::delete_with_client(&default_client(), id).await
     }
-}
 
+    /// Deletes a thread instance using the given client.
+    pub async fn delete_with_client(
+        client: &OpenAiClient,
+        id: &str,
+    ) -> ApiResponseOrError<DeletedThread> {
+        openai_delete(&assistants_client(client), &format!("threads/{id}")).await
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TextContent {
@@ -157,23 +255,30 @@ pub enum Content {
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct IncompleteDetails {
-    pub reason: String
+    pub reason: String,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct MessageObject {
     pub id: String,
     pub object: String,
+    #[serde(alias = "created_at")]
     pub created: u32,
     pub thread_id: String,
     pub status: String,
     #[serde(default)]
     pub incomplete_details: Option<IncompleteDetails>,
     pub role: Role,
-    pub content: Content,
+    pub content: Vec<Content>,
     pub file_ids: Option<Vec<String>>,
     #[serde(default)]
-    pub metadata: Map<String, String>
+    pub metadata: Map<String, String>,
+    /// Fields of the response this crate doesn't know about.
+    #[serde(flatten)]
+    pub extra_fields: serde_json::Map<String, Value>,
+    /// Metadata of the response this was parsed from.
+    #[serde(skip)]
+    pub meta: Option<ResponseMeta>,
 }
 
 impl Thread {
@@ -183,33 +288,204 @@ impl Thread {
         id: &str,
         role: Role,
         content: &str,
-        file_ids: Option<Vec<String>>,
         metadata: Option<Value>,
     ) -> ApiResponseOrError<MessageObject> {
-        openai_post(&format!("threads/{id}/messages"), &serde_json::json!({
-            "role": role.as_str(),
-            "content": content,
-            "file_ids": file_ids,
-            "metadata": metadata,
-        })).await
+        Thread::create_message_with_client(&default_client(), id, role, content, metadata).await
+    }
+
+    /// Creates a new message in the thread using the given client.
+    pub async fn create_message_with_client(
+        client: &OpenAiClient,
+        id: &str,
+        role: Role,
+        content: &str,
+        metadata: Option<Value>,
+    ) -> ApiResponseOrError<MessageObject> {
+        openai_post(
+            &assistants_client(client),
+            &format!("threads/{id}/messages"),
+            &serde_json::json!({
+                "role": role.as_str(),
+                "content": content,
+                "metadata": metadata,
+            }),
+        )
+        .await
+    }
+
+    /// Lists one page of the messages in the thread.
+    pub async fn list_messages(
+        id: &str,
+        query: &ListQuery,
+    ) -> ApiResponseOrError<Page<MessageObject>> {
+        Thread::list_messages_with_client(&default_client(), id, query).await
+    }
+
+    /// Lists one page of the messages in the thread using the given client.
+    pub async fn list_messages_with_client(
+        client: &OpenAiClient,
+        id: &str,
+        query: &ListQuery,
+    ) -> ApiResponseOrError<Page<MessageObject>> {
+        pagination::list_page(
+            &assistants_client(client),
+            &format!("threads/{id}/messages"),
+            query,
+        )
+        .await
+    }
+
+    /// Streams every message in the thread, fetching pages as needed.
+    pub fn list_all_messages(
+        id: &str,
+        query: ListQuery,
+    ) -> impl Stream<Item = ApiResponseOrError<MessageObject>> {
+        Thread::list_all_messages_with_client(&default_client(), id, query)
+    }
+
+    /// Streams every message in the thread using the given client.
+    pub fn list_all_messages_with_client(
+        client: &OpenAiClient,
+        id: &str,
+        query: ListQuery,
+    ) -> impl Stream<Item = ApiResponseOrError<MessageObject>> {
+        pagination::list_all(
+            &assistants_client(client),
+            &format!("threads/{id}/messages"),
+            query,
+        )
+    }
+}
+
+/// The client to send assistants requests with, opted into the version of the beta this module
+/// implements, unless the client sets its own `OpenAI-Beta` header.
+fn assistants_client(client: &OpenAiClient) -> OpenAiClient {
+    if client.headers().contains_key(BETA_HEADER) {
+        return client.clone();
+    }
+    client.clone().with_header(
+        HeaderName::from_static(BETA_HEADER),
+        HeaderValue::from_static(ASSISTANTS_BETA),
+    )
+}
+
+impl HasResponseMeta for Thread {
+    fn set_meta(&mut self, meta: ResponseMeta) {
+        self.meta = Some(meta);
+    }
+}
+
+impl HasResponseMeta for DeletedThread {
+    fn set_meta(&mut self, meta: ResponseMeta) {
+        self.meta = Some(meta);
+    }
+}
+
+impl HasResponseMeta for MessageObject {
+    fn set_meta(&mut self, meta: ResponseMeta) {
+        self.meta = Some(meta);
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::set_key;
-    use crate::tests::DEFAULT_THREAD;
-    use dotenvy::dotenv;
-    use std::env;
+    use crate::pagination::Order;
+    use crate::tests::{cassette_client, http_response, serve_responses};
+    use futures_util::StreamExt;
 
     #[tokio::test]
     async fn thread() {
-        dotenv().ok();
-        set_key(env::var("OPENAI_KEY").unwrap());
-        let thread = Thread::from(DEFAULT_THREAD).await.unwrap();
-        assert_eq!(thread.id, DEFAULT_THREAD);
+        let client = cassette_client("thread");
+        let created = Thread::create_with_client(&client, Vec::new(), Map::new())
+            .await
+            .unwrap();
+        let thread = Thread::from_with_client(&client, &created.id)
+            .await
+            .unwrap();
+        assert_eq!(thread.id, created.id);
+        assert!(
+            Thread::delete_with_client(&client, &created.id)
+                .await
+                .unwrap()
+                .deleted
+        );
+    }
+
+    #[tokio::test]
+    async fn thread_builder_headers() {
+        let (base_url, requests) = serve_responses(vec![http_response(
+            200,
+            &[],
+            r#"{"id":"thread_1","object":"thread","created_at":0,"metadata":{}}"#,
+        )])
+        .await;
+        let client = OpenAiClient::new("key")
+            .with_base_url(base_url)
+            .with_organization("org-client");
+
+        let thread = Thread::builder(Vec::new())
+            .client(&client)
+            .organization("org-request")
+            .project("proj_request")
+            .create()
+            .await
+            .unwrap();
+
+        assert_eq!(thread.id, "thread_1");
+        let request = requests.lock().unwrap()[0].to_lowercase();
+        assert!(request.contains("openai-organization: org-request\r\n"));
+        assert!(request.contains("openai-project: proj_request\r\n"));
+        assert!(request.contains("openai-beta: assistants=v2\r\n"));
+        assert!(request.ends_with(r#"{"messages":[]}"#));
     }
-}
 
+    #[tokio::test]
+    async fn list_all_messages() {
+        let message = |id: &str| {
+            format!(
+                r#"{{"id":"{id}","object":"thread.message","created_at":0,"thread_id":"thread_1","status":"completed","role":"assistant","content":[{{"type":"text","text":{{"value":"Hi","annotations":[]}}}}],"attachments":[],"metadata":{{}}}}"#
+            )
+        };
+        let (base_url, requests) = serve_responses(vec![
+            http_response(
+                200,
+                &[],
+                &format!(
+                    r#"{{"object":"list","data":[{}],"first_id":"msg_2","last_id":"msg_2","has_more":true}}"#,
+                    message("msg_2")
+                ),
+            ),
+            http_response(
+                200,
+                &[],
+                &format!(
+                    r#"{{"object":"list","data":[{}],"first_id":"msg_1","last_id":"msg_1","has_more":false}}"#,
+                    message("msg_1")
+                ),
+            ),
+        ])
+        .await;
+        let client = OpenAiClient::new("key").with_base_url(base_url);
+        let query = ListQuery {
+            limit: Some(1),
+            order: Some(Order::Desc),
+            ..ListQuery::default()
+        };
+
+        let messages: Vec<MessageObject> =
+            Thread::list_all_messages_with_client(&client, "thread_1", query)
+                .map(Result::unwrap)
+                .collect()
+                .await;
 
+        let ids: Vec<&str> = messages.iter().map(|message| message.id.as_str()).collect();
+        assert_eq!(ids, ["msg_2", "msg_1"]);
+        assert!(matches!(messages[0].content[..], [Content::Text(_)]));
+        let requests = requests.lock().unwrap();
+        assert!(requests[0].contains("openai-beta: assistants=v2\r\n"));
+        assert!(requests[0].starts_with("GET /v1/threads/thread_1/messages?limit=1&order=desc "));
+        assert!(requests[1]
+            .starts_with("GET /v1/threads/thread_1/messages?limit=1&order=desc&after=msg_2 "));
+    }
+}
@@ -0,0 +1,490 @@
+//! Counting tokens offline, the way OpenAI's models do.
+//!
+//! With the `tokenizer` feature, text can be encoded, decoded and counted with the
+//! `cl100k_base` encoding of GPT-4 and GPT-3.5 models, and the `o200k_base` encoding of GPT-4o,
+//! GPT-4.1 and o-series models. The encodings are bundled with the crate.
+//!
+//! ```
+//! use openai::tokenizer::Encoding;
+//!
+//! let encoding = Encoding::for_model("gpt-4o").unwrap();
+//! let tokens = encoding.encode("Say this is a test");
+//! assert_eq!(tokens.len(), 5);
+//! assert_eq!(encoding.decode(&tokens).unwrap(), "Say this is a test");
+//! ```
+//!
+//! Chat messages are wrapped in a few tokens of formatting, and function definitions are
+//! rendered into the prompt, which [`count_messages`] and
+//! [`ChatCompletionRequest::count_prompt_tokens`](crate::chat::ChatCompletionRequest::count_prompt_tokens)
+//! take into account. With this feature, the token estimates used for rate limiting and
+//! pricing are exact counts of text for models with a known encoding; images and audio are
+//! still estimated.
+
+use serde_json::Value;
+use tiktoken_rs::tokenizer::{get_tokenizer, Tokenizer};
+use tiktoken_rs::CoreBPE;
+
+use crate::chat::{
+    ChatCompletionFunctionDefinition, ChatCompletionMessage, ChatCompletionMessageRole,
+};
+use crate::models::model_info;
+pub use crate::models::Encoding;
+use crate::{ApiResponseOrError, OpenAiError};
+
+/// Tokens wrapped around each chat message.
+const TOKENS_PER_MESSAGE: usize = 3;
+/// Tokens added to messages with a name.
+const TOKENS_PER_NAME: usize = 1;
+/// Tokens priming the reply of the assistant.
+const REPLY_PRIMER_TOKENS: usize = 3;
+/// Tokens wrapped around the function definitions.
+const FUNCTION_DEFINITIONS_TOKENS: usize = 9;
+
+impl Encoding {
+    /// The encoding used by a model, or `None` if it isn't known or isn't bundled.
+    ///
+    /// Models in the [`model_registry`](crate::models::model_registry) use its encoding.
+    pub fn for_model(model: &str) -> Option<Encoding> {
+        if let Some(encoding) = model_info(model).and_then(|info| info.encoding) {
+            return Some(encoding);
+        }
+        match get_tokenizer(model)? {
+            Tokenizer::Cl100kBase => Some(Encoding::Cl100kBase),
+            Tokenizer::O200kBase => Some(Encoding::O200kBase),
+            _ => None,
+        }
+    }
+
+    /// Encodes text into tokens. Special tokens, such as `<|endoftext|>`, are encoded as text.
+    pub fn encode(self, text: &str) -> Vec<u32> {
+        self.bpe().encode_ordinary(text)
+    }
+
+    /// Decodes tokens into text.
+    ///
+    /// Fails with [`OpenAiError::Validation`] if a token isn't part of the encoding,
+    /// or if the tokens split a character, as a prefix of a longer text can.
+    pub fn decode(self, tokens: &[u32]) -> ApiResponseOrError<String> {
+        self.bpe()
+            .decode(tokens.to_vec())
+            .map_err(|error| OpenAiError::Validation(error.to_string()))
+    }
+
+    /// The number of tokens of the text.
+    pub fn count(self, text: &str) -> usize {
+        self.encode(text).len()
+    }
+
+    fn bpe(self) -> &'static CoreBPE {
+        match self {
+            Encoding::Cl100kBase => tiktoken_rs::cl100k_base_singleton(),
+            Encoding::O200kBase => tiktoken_rs::o200k_base_singleton(),
+        }
+    }
+}
+
+/// The number of prompt tokens of a conversation, including the formatting of each message
+/// and the tokens that prime the reply. Images are estimated at a fixed number of tokens for
+/// their detail, and audio isn't counted.
+pub fn count_messages(encoding: Encoding, messages: &[ChatCompletionMessage]) -> usize {
+    count_chat_prompt(encoding, messages, &[], None)
+}
+
+/// The number of prompt tokens of a chat completion request.
+///
+/// `functions` includes the functions of the request's tools, and `function_call` is the value
+/// of its `function_call` parameter, or its `tool_choice` in the same form.
+pub(crate) fn count_chat_prompt(
+    encoding: Encoding,
+    messages: &[ChatCompletionMessage],
+    functions: &[ChatCompletionFunctionDefinition],
+    function_call: Option<&Value>,
+) -> usize {
+    let has_functions = !functions.is_empty();
+    let mut padded_system = false;
+    let mut tokens: usize = messages
+        .iter()
+        .map(|message| {
+            // Function definitions are added to the first system message, after a line break.
+            let pad = has_functions
+                && matches!(message.role, ChatCompletionMessageRole::System)
+                && !std::mem::replace(&mut padded_system, true);
+            count_message(encoding, message, pad)
+        })
+        .sum::<usize>()
+        + REPLY_PRIMER_TOKENS;
+    if has_functions {
+        tokens +=
+            encoding.count(&format_function_definitions(functions)) + FUNCTION_DEFINITIONS_TOKENS;
+        // The definitions share the formatting of the system message, if there is one.
+        if padded_system {
+            tokens -= 4;
+        }
+    }
+    tokens
+        + match function_call {
+            Some(Value::String(mode)) if mode == "none" => 1,
+            Some(Value::Object(call)) => match call.get("name") {
+                Some(Value::String(name)) => encoding.count(name) + 4,
+                _ => 0,
+            },
+            _ => 0,
+        }
+}
+
+fn count_message(encoding: Encoding, message: &ChatCompletionMessage, pad: bool) -> usize {
+    let content = message.prompt_text();
+    let content_tokens = match pad {
+        true => encoding.count(&format!("{content}\n")),
+        false => encoding.count(&content),
+    };
+    let image_tokens = message.image_tokens() as usize;
+    let mut tokens = TOKENS_PER_MESSAGE
+        + encoding.count(role_name(message.role))
+        + content_tokens
+        + image_tokens;
+    if let Some(name) = &message.name {
+        tokens += encoding.count(name) + TOKENS_PER_NAME;
+    }
+    // Tool calls are formatted like function calls.
+    let tool_calls = message
+        .tool_calls
+        .iter()
+        .flatten()
+        .map(|call| &call.function);
+    for call in message.function_call.iter().chain(tool_calls) {
+        tokens += encoding.count(&call.name) + encoding.count(&call.arguments) + 3;
+    }
+    if let ChatCompletionMessageRole::Function = message.role {
+        tokens -= 2;
+    }
+    tokens
+}
+
+fn role_name(role: ChatCompletionMessageRole) -> &'static str {
+    match role {
+        ChatCompletionMessageRole::System => "system",
+        ChatCompletionMessageRole::User => "user",
+        ChatCompletionMessageRole::Assistant => "assistant",
+        ChatCompletionMessageRole::Function => "function",
+        ChatCompletionMessageRole::Tool => "tool",
+    }
+}
+
+/// Renders function definitions the way they are shown to the model,
+/// as TypeScript types in a namespace.
+fn format_function_definitions(functions: &[ChatCompletionFunctionDefinition]) -> String {
+    let mut lines = vec!["namespace functions {".to_string(), String::new()];
+    for function in functions {
+        if let Some(description) = &function.description {
+            lines.push(format!("// {description}"));
+        }
+        let parameters = function.parameters.as_ref();
+        match parameters.and_then(|parameters| parameters.get("properties")) {
+            Some(Value::Object(properties)) if !properties.is_empty() => {
+                lines.push(format!("type {} = (_: {{", function.name));
+                lines.push(format_object_properties(parameters.unwrap(), 0));
+                lines.push("}) => any;".to_string());
+            }
+            _ => lines.push(format!("type {} = () => any;", function.name)),
+        }
+        lines.push(String::new());
+    }
+    lines.push("} // namespace functions".to_string());
+    lines.join("\n")
+}
+
+fn format_object_properties(object: &Value, indent: usize) -> String {
+    let Some(Value::Object(properties)) = object.get("properties") else {
+        return String::new();
+    };
+    let required = |name: &str| {
+        object
+            .get("required")
+            .and_then(Value::as_array)
+            .is_some_and(|required| required.iter().any(|field| field == name))
+    };
+    let mut lines = Vec::new();
+    for (name, property) in properties {
+        if let (Some(Value::String(description)), true) = (property.get("description"), indent < 2)
+        {
+            lines.push(format!("// {description}"));
+        }
+        let optional = if required(name) { "" } else { "?" };
+        lines.push(format!(
+            "{name}{optional}: {},",
+            format_type(property, indent)
+        ));
+    }
+    lines
+        .iter()
+        .map(|line| format!("{}{line}", " ".repeat(indent)))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn format_type(property: &Value, indent: usize) -> String {
+    let enum_values = |quote: bool| {
+        property
+            .get("enum")
+            .and_then(Value::as_array)
+            .map(|values| {
+                values
+                    .iter()
+                    .map(|value| match (value, quote) {
+                        (Value::String(value), true) => format!("\"{value}\""),
+                        (Value::String(value), false) => value.clone(),
+                        (value, _) => value.to_string(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" | ")
+            })
+    };
+    match property.get("type").and_then(Value::as_str) {
+        Some("string") => enum_values(true).unwrap_or_else(|| "string".to_string()),
+        Some(number @ ("number" | "integer")) => {
+            enum_values(false).unwrap_or_else(|| number.to_string())
+        }
+        Some("array") => match property.get("items") {
+            Some(items) => format!("{}[]", format_type(items, indent)),
+            None => "any[]".to_string(),
+        },
+        Some("object") => format!("{{\n{}\n}}", format_object_properties(property, indent + 2)),
+        Some(other @ ("boolean" | "null")) => other.to_string(),
+        _ => "any".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::{
+        ChatCompletion, ChatCompletionContentPart, ChatCompletionFunctionCall,
+        ChatCompletionImageDetail, ChatCompletionTool, ChatCompletionToolCall,
+        ChatCompletionToolChoice,
+    };
+    use crate::rate_limit::TokenEstimate;
+    use serde_json::json;
+
+    fn message(role: ChatCompletionMessageRole, content: &str) -> ChatCompletionMessage {
+        ChatCompletionMessage {
+            role,
+            content: Some(content.into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn encodes_and_decodes() {
+        let text = "tiktoken is great!";
+        for (encoding, tokens) in [
+            (Encoding::Cl100kBase, vec![83, 1609, 5963, 374, 2294, 0]),
+            (Encoding::O200kBase, vec![83, 8251, 2488, 382, 2212, 0]),
+        ] {
+            assert_eq!(encoding.encode(text), tokens);
+            assert_eq!(encoding.count(text), 6);
+            assert_eq!(encoding.decode(&tokens).unwrap(), text);
+        }
+        assert_eq!(
+            Encoding::for_model("gpt-4o-2024-08-06"),
+            Some(Encoding::O200kBase)
+        );
+        assert_eq!(
+            Encoding::for_model("gpt-3.5-turbo"),
+            Some(Encoding::Cl100kBase)
+        );
+        assert_eq!(
+            Encoding::for_model("davinci-002"),
+            Some(Encoding::Cl100kBase)
+        );
+        assert_eq!(Encoding::for_model("text-davinci-003"), None);
+        assert!(matches!(
+            Encoding::Cl100kBase.decode(&[u32::MAX]),
+            Err(OpenAiError::Validation(_))
+        ));
+    }
+
+    #[test]
+    fn counts_messages() {
+        // The example conversation of OpenAI's cookbook, counted as 129 tokens by the API.
+        let messages = [
+            message(ChatCompletionMessageRole::System, "You are a helpful, pattern-following assistant that translates corporate jargon into plain English."),
+            ChatCompletionMessage {
+                name: Some("example_user".to_string()),
+                ..message(ChatCompletionMessageRole::System, "New synergies will help drive top-line growth.")
+            },
+            ChatCompletionMessage {
+                name: Some("example_assistant".to_string()),
+                ..message(ChatCompletionMessageRole::System, "Things working well together will increase revenue.")
+            },
+            ChatCompletionMessage {
+                name: Some("example_user".to_string()),
+                ..message(ChatCompletionMessageRole::System, "Let's circle back when we have more bandwidth to touch base on opportunities for increased leverage.")
+            },
+            ChatCompletionMessage {
+                name: Some("example_assistant".to_string()),
+                ..message(ChatCompletionMessageRole::System, "Let's talk later when we're less busy about how to do better.")
+            },
+            message(ChatCompletionMessageRole::User, "This late pivot means we don't have time to boil the ocean for the client deliverable."),
+        ];
+
+        assert_eq!(count_messages(Encoding::Cl100kBase, &messages), 129);
+        assert_eq!(count_messages(Encoding::O200kBase, &messages), 124);
+
+        let image = |detail| ChatCompletionMessage {
+            content: Some(
+                vec![
+                    ChatCompletionContentPart::text("hello"),
+                    ChatCompletionContentPart::image_url("https://example.com/cat.png", detail),
+                ]
+                .into(),
+            ),
+            ..message(ChatCompletionMessageRole::User, "")
+        };
+        let text = [message(ChatCompletionMessageRole::User, "hello")];
+        assert_eq!(
+            count_messages(
+                Encoding::O200kBase,
+                &[image(ChatCompletionImageDetail::Low)]
+            ),
+            count_messages(Encoding::O200kBase, &text) + 85
+        );
+        assert_eq!(
+            count_messages(
+                Encoding::O200kBase,
+                &[image(ChatCompletionImageDetail::High)]
+            ),
+            count_messages(Encoding::O200kBase, &text) + 765
+        );
+    }
+
+    #[test]
+    fn counts_functions() {
+        let functions = [ChatCompletionFunctionDefinition {
+            name: "get_current_weather".to_string(),
+            description: Some("Get the current weather in a given location".to_string()),
+            parameters: Some(json!({
+                "type": "object",
+                "properties": {
+                    "location": {
+                        "type": "string",
+                        "description": "The city and state, e.g. San Francisco, CA"
+                    },
+                    "unit": {"type": "string", "enum": ["celsius", "fahrenheit"]}
+                },
+                "required": ["location"]
+            })),
+        }];
+
+        assert_eq!(
+            format_function_definitions(&functions),
+            "namespace functions {\n\n// Get the current weather in a given location\ntype get_current_weather = (_: {\n// The city and state, e.g. San Francisco, CA\nlocation: string,\nunit?: \"celsius\" | \"fahrenheit\",\n}) => any;\n\n} // namespace functions"
+        );
+        let messages = [message(ChatCompletionMessageRole::User, "hello")];
+        let empty_function = [ChatCompletionFunctionDefinition {
+            name: "foo".to_string(),
+            description: None,
+            parameters: Some(json!({"type": "object", "properties": {}})),
+        }];
+        assert_eq!(count_messages(Encoding::Cl100kBase, &messages), 8);
+        assert_eq!(
+            count_chat_prompt(Encoding::Cl100kBase, &messages, &empty_function, None),
+            31
+        );
+        let base = count_chat_prompt(Encoding::Cl100kBase, &messages, &functions, None);
+        assert_eq!(base, 75);
+        let with_system = [
+            message(ChatCompletionMessageRole::System, "hello"),
+            message(ChatCompletionMessageRole::User, "hello"),
+        ];
+        assert_eq!(
+            count_chat_prompt(Encoding::Cl100kBase, &with_system, &functions, None),
+            base + count_messages(Encoding::Cl100kBase, &with_system[..1]) - 3 - 4 + 1
+        );
+        assert_eq!(
+            count_chat_prompt(
+                Encoding::Cl100kBase,
+                &messages,
+                &functions,
+                Some(&json!("none"))
+            ),
+            base + 1
+        );
+        let call = json!({"name": "get_current_weather"});
+        assert_eq!(
+            count_chat_prompt(Encoding::Cl100kBase, &messages, &functions, Some(&call)),
+            base + Encoding::Cl100kBase.count("get_current_weather") + 4
+        );
+
+        let function_reply = [
+            ChatCompletionMessage {
+                content: None,
+                function_call: Some(ChatCompletionFunctionCall {
+                    name: "get_current_weather".to_string(),
+                    arguments: r#"{"location":"Boston, MA"}"#.to_string(),
+                }),
+                ..message(ChatCompletionMessageRole::Assistant, "")
+            },
+            ChatCompletionMessage {
+                name: Some("get_current_weather".to_string()),
+                ..message(ChatCompletionMessageRole::Function, "22 degrees")
+            },
+        ];
+        let encoding = Encoding::Cl100kBase;
+        assert_eq!(
+            count_messages(encoding, &function_reply),
+            3 + 1
+                + encoding.count("get_current_weather")
+                + encoding.count(r#"{"location":"Boston, MA"}"#)
+                + 3
+                + 3
+                + 1
+                + encoding.count("22 degrees")
+                + encoding.count("get_current_weather")
+                + 1
+                - 2
+                + 3
+        );
+        let tool_reply = ChatCompletionMessage {
+            content: None,
+            tool_calls: Some(vec![ChatCompletionToolCall::function(
+                "call_1",
+                "get_current_weather",
+                r#"{"location":"Boston, MA"}"#,
+            )]),
+            ..message(ChatCompletionMessageRole::Assistant, "")
+        };
+        assert_eq!(
+            count_messages(encoding, &[tool_reply]),
+            count_messages(encoding, &function_reply[..1])
+        );
+    }
+
+    #[test]
+    fn counts_requests() {
+        let messages = [message(ChatCompletionMessageRole::User, "hello")];
+        let functions = vec![ChatCompletionFunctionDefinition {
+            name: "foo".to_string(),
+            description: None,
+            parameters: Some(json!({"type": "object", "properties": {}})),
+        }];
+        let request = ChatCompletion::builder("gpt-4-0613", messages.clone())
+            .functions(functions.clone())
+            .function_call(json!("none"))
+            .build()
+            .unwrap();
+        let tool_request = ChatCompletion::builder("gpt-4-0613", messages.clone())
+            .tools([ChatCompletionTool::function(functions[0].clone())])
+            .tool_choice(ChatCompletionToolChoice::None)
+            .build()
+            .unwrap();
+
+        assert_eq!(request.count_prompt_tokens(), Some(32));
+        assert_eq!(request.estimate_prompt_tokens(), 32);
+        assert_eq!(tool_request.count_prompt_tokens(), Some(32));
+        let unknown = ChatCompletion::builder("my-model", messages)
+            .build()
+            .unwrap();
+        assert_eq!(unknown.count_prompt_tokens(), None);
+    }
+}